@@ -1,11 +1,68 @@
 use axum::{
     extract::{Request, State},
-    http::{header, StatusCode},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use crate::{api::handlers::Claims, state::AppState};
+use std::{net::SocketAddr, time::Instant};
+use crate::{
+    api::handlers::{Claims, TokenUse},
+    config::BaseConfig,
+    models::{AuthBody, KanriGateResp},
+    services::{api_keys, authenticator::{IdentitySource, Role}},
+    state::AppState,
+};
+
+/// Access token for browser sessions managed by the backend.
+pub const SESSION_COOKIE: &str = "kanrigate_session";
+pub const REFRESH_COOKIE: &str = "kanrigate_refresh";
+/// Readable by scripts, which echo it in `X-CSRF-Token` (double-submit).
+pub const CSRF_COOKIE: &str = "kanrigate_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+const REFRESH_COOKIE_PATH: &str = "/apps/refresh";
+
+fn cookie(config: &BaseConfig, name: &str, value: &str, path: &str, max_age: i64, http_only: bool) -> HeaderValue {
+    let mut cookie = format!("{}={}; Path={}; Max-Age={}; SameSite={}", name, value, path, max_age, config.session_cookie_same_site);
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if config.session_cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    HeaderValue::from_str(&cookie).expect("cookie values are URL-safe")
+}
+
+/// `Set-Cookie` headers for a browser session holding the tokens in `body`.
+pub fn session_cookies(config: &BaseConfig, body: &AuthBody, csrf_token: &str) -> Vec<HeaderValue> {
+    vec![
+        cookie(config, SESSION_COOKIE, &body.access_token, "/", body.expires_in, true),
+        cookie(config, REFRESH_COOKIE, &body.refresh_token, REFRESH_COOKIE_PATH, body.refresh_expires_in, true),
+        cookie(config, CSRF_COOKIE, csrf_token, "/", body.refresh_expires_in, false),
+    ]
+}
+
+/// `Set-Cookie` headers that end a browser session.
+pub fn clear_session_cookies(config: &BaseConfig) -> Vec<HeaderValue> {
+    vec![
+        cookie(config, SESSION_COOKIE, "", "/", 0, true),
+        cookie(config, REFRESH_COOKIE, "", REFRESH_COOKIE_PATH, 0, true),
+        cookie(config, CSRF_COOKIE, "", "/", 0, false),
+    ]
+}
+
+/// Whether a cookie-authenticated request may proceed: safe methods always may, others must
+/// echo the CSRF cookie in the `X-CSRF-Token` header, which a cross-site page cannot read.
+pub fn csrf_valid(method: &Method, headers: &HeaderMap) -> bool {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    let expected = cookie_value(headers, CSRF_COOKIE).unwrap_or_default();
+    let presented = headers.get(CSRF_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    !expected.is_empty()
+        && expected.len() == presented.len()
+        && expected.bytes().zip(presented.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
 
 pub async fn auth_middleware(
     State(state): State<AppState>,
@@ -16,25 +73,165 @@ pub async fn auth_middleware(
         .get(header::AUTHORIZATION)
         .and_then(|header| header.to_str().ok());
 
-    let auth_header = if let Some(auth_header) = auth_header {
-        auth_header
+    // An explicit bearer token wins; browsers fall back to the session cookie, which is
+    // sent automatically and therefore needs the CSRF check.
+    let token = match auth_header {
+        Some(auth_header) if auth_header.starts_with("Bearer ") => auth_header[7..].to_string(),
+        Some(_) => return Err(StatusCode::UNAUTHORIZED),
+        None => {
+            let token = cookie_value(req.headers(), SESSION_COOKIE)
+                .ok_or(StatusCode::UNAUTHORIZED)?
+                .to_string();
+            if !csrf_valid(req.method(), req.headers()) {
+                tracing::warn!(method = %req.method(), path = %req.uri().path(), "Rejected cookie-authenticated request without a valid CSRF token");
+                return Err(StatusCode::FORBIDDEN);
+            }
+            token
+        }
+    };
+    let token = token.as_str();
+
+    let claims = if api_keys::is_api_key(token) {
+        let key = state.api_keys.authenticate(token).ok_or(StatusCode::UNAUTHORIZED)?;
+        tracing::Span::current().record("api_key", key.id.as_str());
+        Claims {
+            sub: key.owner,
+            exp: key.expires_at.timestamp() as usize,
+            jti: key.id,
+            sid: String::new(),
+            auth_time: key.created_at.timestamp(),
+            token_use: TokenUse::Access,
+            source: IdentitySource::ApiKey,
+            role: key.role,
+            namespaces: key.namespaces,
+        }
     } else {
-        return Err(StatusCode::UNAUTHORIZED);
+        let claims = state.jwt.decode::<Claims>(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // Tokens without a session id predate revocation support and cannot be logged out.
+        if claims.token_use != TokenUse::Access
+            || claims.sid.is_empty()
+            || state.revocations.is_revoked(&claims.sid)
+            || state.revocations.is_revoked(&claims.jti)
+        {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        claims
+    };
+
+    // Tag the request span so every log line for this call carries the operator identity.
+    tracing::Span::current().record("operator", claims.sub.as_str());
+    req.extensions_mut().insert(claims);
+
+    Ok(next.run(req).await)
+}
+
+/// Rejects requests whose session role is below `required` with a 403 in the usual envelope.
+///
+/// Must run inside `auth_middleware`, which places the `Claims` in the request extensions.
+pub async fn require_role(
+    State(required): State<Role>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
     };
 
-    if !auth_header.starts_with("Bearer ") {
-        return Err(StatusCode::UNAUTHORIZED);
+    if claims.role < required {
+        tracing::warn!(
+            operator = %claims.sub,
+            role = claims.role.as_str(),
+            required = required.as_str(),
+            path = %req.uri().path(),
+            "Forbidden: insufficient role"
+        );
+        let message = format!("This action requires the {} role", required.as_str());
+        return (
+            StatusCode::FORBIDDEN,
+            Json(KanriGateResp::new(403, message, start.elapsed().as_secs_f64(), String::new())),
+        ).into_response();
     }
 
-    let token = &auth_header[7..];
+    next.run(req).await
+}
+
+/// The client address for rate limiting: the peer itself, or, when the peer is a trusted
+/// proxy such as the bundled UI, the nearest `X-Forwarded-For` hop that is not. Hops are
+/// read from the right, since anything left of the last trusted proxy is whatever the client
+/// chose to send.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trusted_proxies: &[String]) -> String {
+    let peer_ip = peer.ip().to_string();
+    if !trusted_proxies.contains(&peer_ip) {
+        return peer_ip;
+    }
+    let hops: Vec<&str> = headers.get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    hops.iter()
+        .rev()
+        .find(|hop| !trusted_proxies.iter().any(|proxy| proxy == *hop))
+        .or(hops.first())
+        .map(|hop| hop.to_string())
+        .unwrap_or(peer_ip)
+}
 
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(state.config.jwt_secret.as_ref()),
-        &Validation::default(),
-    ).map_err(|_| StatusCode::UNAUTHORIZED)?;
+/// Returns the value of the named cookie from the request's `Cookie` headers.
+pub fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
 
-    req.extensions_mut().insert(token_data.claims);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(next.run(req).await)
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn trusted() -> Vec<String> {
+        vec!["127.0.0.1".to_string(), "10.0.0.5".to_string()]
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let peer = "203.0.113.7:4000".parse().unwrap();
+        assert_eq!(client_ip(&forwarded(&["198.51.100.1"]), peer, &trusted()), "203.0.113.7");
+    }
+
+    #[test]
+    fn spoofed_leading_entry_is_ignored() {
+        let peer = "127.0.0.1:4000".parse().unwrap();
+        let headers = forwarded(&["1.2.3.4, 203.0.113.7"]);
+        assert_eq!(client_ip(&headers, peer, &trusted()), "203.0.113.7");
+    }
+
+    #[test]
+    fn chained_trusted_proxies_are_skipped() {
+        let peer = "127.0.0.1:4000".parse().unwrap();
+        let headers = forwarded(&["1.2.3.4, 203.0.113.7", "10.0.0.5"]);
+        assert_eq!(client_ip(&headers, peer, &trusted()), "203.0.113.7");
+    }
+
+    #[test]
+    fn missing_header_falls_back_to_the_peer() {
+        let peer = "127.0.0.1:4000".parse().unwrap();
+        assert_eq!(client_ip(&HeaderMap::new(), peer, &trusted()), "127.0.0.1");
+    }
 }
@@ -1,30 +1,75 @@
 use axum::{
-    extract::{Query, State},
-    http::{header, StatusCode, HeaderMap},
+    extract::{ConnectInfo, Extension, Query, State},
+    http::{header, HeaderMap, Method, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
 use crate::{
-    models::{LoginRequest, AuthBody, KanriGateResp, UsernameQuery, RoleBindingQuery, ClusterRoleBindingQuery, K8sConfigQuery},
+    api::auth,
+    models::{LoginRequest, RefreshRequest, KubeLoginRequest, AuthBody, MfaChallenge, MfaLoginRequest, MfaTokenRequest, MfaCodeRequest, MfaSetupInfo, MfaRecoveryCodes, ClearLockoutQuery, LockoutInfo, KanriGateResp, UsernameQuery, SubjectQuery, RoleBindingQuery, ClusterRoleBindingQuery, K8sConfigQuery, KubeconfigTokenType, K8sCertConfigRequest, RotateTokenQuery, TokenRotated, TokenRotationInfo, ProvisionUserRequest, UserProvisioned, DeleteUserQuery, TemplateQuery, TemplateRequest, UpdateTemplateQuery, DeleteTemplateQuery, CreateOperatorRequest, OperatorInfo, OperatorNamespacesRequest, OperatorRoleQuery, OidcCallbackQuery, ChangePasswordRequest, CreateApiKeyRequest, ApiKeyQuery, ApiKeyInfo, ApiKeyCreated, HealthInfo},
     state::AppState,
-    services::kubernetes::KubeOps,
-    config::permissions,
+    services::{cache::KubeCache, authenticator::{hash_password, namespace_in_scope, password_policy_violation, AuthenticatedUser, IdentitySource, Role}, kubernetes::{Conflict, KubeOps, NotManaged, KubeconfigToken, SubjectKind, TemplateScope}, login_guard, oidc, operators::OperatorStore, provisioning::{self, ProvisionPlan}, revocation::RefreshStatus, templates::{self, TemplateManager, TemplateSpec}, token_rotation::TokenRotator},
+    config::BaseConfig,
 };
-use std::time::Instant;
-use argon2::{
-    password_hash::{PasswordHash, PasswordVerifier},
-    Argon2,
-};
-use jsonwebtoken::{encode, Header, EncodingKey};
+use std::{net::SocketAddr, time::Instant};
+use validator::Validate;
 use chrono::{Utc, Duration};
 
+/// Distinguishes access tokens from refresh tokens, which share the same claims.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenUse {
+    #[default]
+    Access,
+    Refresh,
+    /// Proves the password step of a login that still needs a second factor.
+    Mfa,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Unique id of this token, used to revoke it individually.
+    #[serde(default)]
+    pub jti: String,
+    /// Login session shared by every access and refresh token issued from one login.
+    #[serde(default)]
+    pub sid: String,
+    /// When the session's login happened (unix seconds).
+    #[serde(default)]
+    pub auth_time: i64,
+    #[serde(default)]
+    pub token_use: TokenUse,
+    #[serde(default)]
+    pub source: IdentitySource,
+    /// Tokens minted before roles existed carry no role and are treated as viewers.
+    #[serde(default)]
+    pub role: Role,
+    /// Delegated-admin scope; absent for cluster-wide sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+}
+
+impl Claims {
+    pub fn allows_namespace(&self, namespace: &str) -> bool {
+        namespace_in_scope(self.namespaces.as_deref(), namespace)
+    }
+}
+
+/// Short-lived state carried through the IdP round trip in the `kanrigate_oidc` cookie.
+#[derive(Debug, Serialize, Deserialize)]
+struct OidcFlowState {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    exp: usize,
 }
 
+const OIDC_FLOW_COOKIE: &str = "kanrigate_oidc";
+const MFA_TOKEN_TTL_MINUTES: i64 = 5;
+
 // Helper to create response
 fn json_response<T>(start: Instant, data: T) -> Json<KanriGateResp<T>> {
     let duration = start.elapsed().as_secs_f64();
@@ -32,375 +77,2263 @@ fn json_response<T>(start: Instant, data: T) -> Json<KanriGateResp<T>> {
 }
 
 fn error_response(start: Instant, err: anyhow::Error) -> (StatusCode, Json<KanriGateResp<String>>) {
+    let duration = start.elapsed().as_secs_f64();
+    let status = error_status(&err);
+    (
+        status,
+        Json(KanriGateResp::new(status.as_u16(), err.to_string(), duration, String::new())),
+    )
+}
+
+/// Client errors reported by the API server keep their status; everything else, including
+/// KanriGate itself being forbidden, is a server error.
+fn error_status(err: &anyhow::Error) -> StatusCode {
+    if err.downcast_ref::<Conflict>().is_some() {
+        return StatusCode::CONFLICT;
+    }
+    if err.downcast_ref::<NotManaged>().is_some() {
+        return StatusCode::FORBIDDEN;
+    }
+    match err.downcast_ref::<kube::Error>() {
+        Some(kube::Error::Api(e)) if matches!(e.code, 400 | 404 | 409 | 422) => {
+            StatusCode::from_u16(e.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn status_response(start: Instant, status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<KanriGateResp<String>>) {
     let duration = start.elapsed().as_secs_f64();
     (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(KanriGateResp::new(500, err.to_string(), duration, String::new())),
+        status,
+        Json(KanriGateResp::new(status.as_u16(), message, duration, String::new())),
     )
 }
 
+
+fn namespace_scope_denial(start: Instant, claims: &Claims, namespace: &str) -> Option<Response> {
+    if claims.allows_namespace(namespace) {
+        return None;
+    }
+    tracing::warn!(operator = %claims.sub, namespace = %namespace, "Forbidden: namespace outside delegated scope");
+    Some(status_response(start, StatusCode::FORBIDDEN, format!("Namespace {} is outside your delegated scope", namespace)).into_response())
+}
+
+/// Whether the `kind` subject `username` has a cached ClusterRoleBinding, or a cached RoleBinding in a namespace outside the scope of `claims`.
+async fn bindings_outside_scope(cache: &KubeCache, claims: &Claims, kind: SubjectKind, username: &str) -> anyhow::Result<bool> {
+    let (role_bindings, cluster_bindings) = tokio::try_join!(
+        cache.filtered_role_bindings(username, kind),
+        cache.filtered_cluster_role_bindings(username, kind),
+    )?;
+    let has_cluster_access = cluster_bindings.as_object().is_some_and(|o| !o.is_empty());
+    let outside = role_bindings.as_object().is_some_and(|o| o.keys().any(|ns| !claims.allows_namespace(ns)));
+    Ok(has_cluster_access || outside)
+}
+
+/// Whether the `kind` subject `username` may have access outside the scope of `claims`. The
+/// cache only holds KanriGate's bindings, so a subject anyone else created or bound counts
+/// as outside every scope.
+async fn access_outside_scope(state: &AppState, claims: &Claims, kind: SubjectKind, username: &str) -> anyhow::Result<bool> {
+    if !KubeOps::new(state.client.clone()).is_managed_subject(kind, username).await? {
+        return Ok(true);
+    }
+    bindings_outside_scope(&state.cache, claims, kind, username).await
+}
+
+/// Scoped sessions may only see or act on users whose access lies entirely inside their scope.
+async fn subject_scope_denial(start: Instant, state: &AppState, claims: &Claims, kind: SubjectKind, username: &str) -> Option<Response> {
+    // Cluster-wide sessions are not restricted.
+    claims.namespaces.as_ref()?;
+
+    match access_outside_scope(state, claims, kind, username).await {
+        Ok(false) => None,
+        Ok(true) => {
+            tracing::warn!(operator = %claims.sub, target = %username, "Forbidden: user has access outside delegated scope");
+            Some(status_response(start, StatusCode::FORBIDDEN, format!("User {} has access outside your delegated scope", username)).into_response())
+        }
+        Err(e) => Some(error_response(start, e).into_response()),
+    }
+}
+
+/// Rejects scoped sessions from actions whose effect is not confined to a namespace, such as
+/// managing operators or templates, or granting cluster-wide access.
+fn cluster_admin_denial(start: Instant, claims: &Claims) -> Option<Response> {
+    if claims.role == Role::Admin && claims.namespaces.is_none() {
+        return None;
+    }
+    tracing::warn!(operator = %claims.sub, "Forbidden: action requires a cluster-wide admin");
+    Some(status_response(start, StatusCode::FORBIDDEN, "Only cluster-wide admins can do this").into_response())
+}
+
+/// Rejects bindings to templates that do not exist in the cluster.
+async fn unknown_template_denial(start: Instant, state: &AppState, scope: TemplateScope, permission: &str) -> Option<Response> {
+    match state.cache.template(scope, permission).await {
+        Ok(Some(_)) => None,
+        Ok(None) => {
+            let scope = match scope {
+                TemplateScope::Namespaced => "namespaced",
+                TemplateScope::Cluster => "cluster",
+            };
+            Some(status_response(start, StatusCode::BAD_REQUEST, format!("Unknown {} template {}", scope, permission)).into_response())
+        }
+        Err(e) => Some(error_response(start, e).into_response()),
+    }
+}
+
+/// Rejects calls that only make sense for an interactive login, such as managing MFA or minting more keys.
+fn api_key_denial(start: Instant, claims: &Claims) -> Option<Response> {
+    if claims.source != IdentitySource::ApiKey {
+        return None;
+    }
+    Some(status_response(start, StatusCode::FORBIDDEN, "API keys cannot be used for this endpoint").into_response())
+}
+
+/// Keys acting for `username` were minted under its old role or scope, so they go with it.
+async fn revoke_api_keys_of(state: &AppState, operator: &str, username: &str) {
+    match state.api_keys.remove_owned_by(username).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(operator = %operator, target = %username, count, "API keys revoked"),
+        Err(e) => tracing::error!(operator = %operator, target = %username, error = %e, "Failed to revoke API keys"),
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/apps/login",
     request_body = LoginRequest,
     responses(
-        (status = 200, description = "Login successful", body = KanriGateRespAuthBody),
-        (status = 401, description = "Unauthorized")
+        (status = 200, description = "Login successful, or an MFA challenge when a second factor is needed", body = KanriGateRespAuthBody),
+        (status = 401, description = "Unauthorized"),
+        (status = 429, description = "Too many failed attempts, retry later")
+    )
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>,
+) -> Response {
+    let start = Instant::now();
+    let ip = auth::client_ip(&headers, peer, &state.config().trusted_proxies);
+    let user_key = login_guard::user_key(&payload.username);
+    let ip_key = login_guard::ip_key(&ip);
+
+    if let Some(wait) = state.login_guard.retry_after(&[&user_key, &ip_key]) {
+        tracing::warn!(user = %payload.username, ip = %ip, retry_after = wait.num_seconds(), "Login rejected: locked out");
+        let mut response = status_response(start, StatusCode::TOO_MANY_REQUESTS, "Too many failed login attempts, try again later").into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, (wait.num_seconds() + 1).into());
+        return response;
+    }
+
+    // 1. Verify credentials against the configured backend
+    let user = match state.authenticator.authenticate(&payload.username, &payload.password).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            for key in [&user_key, &ip_key] {
+                if let Some(lockout) = state.login_guard.record_failure(key) {
+                    tracing::warn!(key = %key, user = %payload.username, ip = %ip, locked_for = lockout.num_seconds(), "Login locked out after repeated failures");
+                }
+            }
+            return status_response(start, StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
+        }
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    // 2. Ask for a second factor when the account has one, or when it is required for everyone
+    let enrolled = match state.mfa.get(&user.username).await {
+        Ok(enrollment) => enrollment.is_some_and(|e| e.enabled),
+        Err(e) => return error_response(start, e).into_response(),
+    };
+    if enrolled || state.config().mfa_required {
+        // The failure counter is only reset once the second factor succeeds too.
+        tracing::info!(user = %user.username, enrollment_required = !enrolled, "Password accepted, awaiting second factor");
+        return match issue_mfa_challenge(&state, user, !enrolled) {
+            Ok(challenge) => json_response(start, challenge).into_response(),
+            Err(e) => error_response(start, e).into_response(),
+        };
+    }
+    state.login_guard.record_success(&user_key);
+
+    tracing::info!(user = %user.username, source = ?user.source, role = user.role.as_str(), groups = ?user.groups, "Login successful");
+
+    // 3. Generate JWT
+    match issue_session_token(&state, user) {
+        Ok(body) => session_response(&state, start, body, None),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+fn issue_mfa_challenge(state: &AppState, user: AuthenticatedUser, enrollment_required: bool) -> anyhow::Result<MfaChallenge> {
+    let pending = Claims {
+        sub: user.username,
+        exp: (Utc::now() + Duration::minutes(MFA_TOKEN_TTL_MINUTES)).timestamp() as usize,
+        jti: oidc::random_token(),
+        sid: String::new(),
+        auth_time: Utc::now().timestamp(),
+        token_use: TokenUse::Mfa,
+        source: user.source,
+        role: user.role,
+        namespaces: user.namespaces,
+    };
+    Ok(MfaChallenge {
+        mfa_token: state.jwt.encode(&pending)?,
+        enrollment_required,
+    })
+}
+
+/// The identity from a valid, unused `mfa_token`.
+fn pending_mfa_login(state: &AppState, mfa_token: &str) -> Option<Claims> {
+    state.jwt.decode::<Claims>(mfa_token).ok()
+        .filter(|claims| claims.token_use == TokenUse::Mfa && !state.revocations.is_revoked(&claims.jti))
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/login/mfa/enroll",
+    request_body = MfaTokenRequest,
+    responses(
+        (status = 200, description = "New authenticator secret", body = MfaSetupInfo),
+        (status = 401, description = "Invalid or expired MFA token"),
+        (status = 409, description = "MFA is already enabled")
+    )
+)]
+pub async fn login_mfa_enroll(
+    State(state): State<AppState>,
+    Json(payload): Json<MfaTokenRequest>,
+) -> Response {
+    let start = Instant::now();
+    let pending = match pending_mfa_login(&state, &payload.mfa_token) {
+        Some(claims) => claims,
+        None => return status_response(start, StatusCode::UNAUTHORIZED, "Invalid or expired MFA token").into_response(),
+    };
+    begin_mfa_enrollment(&state, start, &pending.sub).await
+}
+
+async fn begin_mfa_enrollment(state: &AppState, start: Instant, username: &str) -> Response {
+    match state.mfa.get(username).await {
+        Ok(Some(enrollment)) if enrollment.enabled => {
+            return status_response(start, StatusCode::CONFLICT, "MFA is already enabled").into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match state.mfa.begin_enrollment(username).await {
+        Ok(setup) => {
+            tracing::info!(user = %username, "MFA enrollment started");
+            json_response(start, MfaSetupInfo { secret: setup.secret, otpauth_uri: setup.otpauth_uri }).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/login/mfa",
+    request_body = MfaLoginRequest,
+    responses(
+        (status = 200, description = "Login successful", body = KanriGateRespAuthBody),
+        (status = 400, description = "MFA enrollment has not been started"),
+        (status = 401, description = "Invalid MFA token or code"),
+        (status = 429, description = "Too many failed attempts, retry later")
+    )
+)]
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    Json(payload): Json<MfaLoginRequest>,
+) -> Response {
+    let start = Instant::now();
+    let pending = match pending_mfa_login(&state, &payload.mfa_token) {
+        Some(claims) => claims,
+        None => return status_response(start, StatusCode::UNAUTHORIZED, "Invalid or expired MFA token").into_response(),
+    };
+
+    let user_key = login_guard::user_key(&pending.sub);
+    if let Some(wait) = state.login_guard.retry_after(&[&user_key]) {
+        tracing::warn!(user = %pending.sub, retry_after = wait.num_seconds(), "MFA rejected: locked out");
+        let mut response = status_response(start, StatusCode::TOO_MANY_REQUESTS, "Too many failed login attempts, try again later").into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, (wait.num_seconds() + 1).into());
+        return response;
+    }
+
+    // An enabled enrollment takes a TOTP or recovery code; a pending one is activated by its first code.
+    let verified = match state.mfa.get(&pending.sub).await {
+        Ok(Some(enrollment)) if enrollment.enabled => state.mfa.verify(&pending.sub, &payload.code).await
+            .map(|ok| ok.then_some(None)),
+        Ok(Some(_)) => state.mfa.activate(&pending.sub, &payload.code).await
+            .map(|codes| codes.map(Some)),
+        Ok(None) => return status_response(start, StatusCode::BAD_REQUEST, "MFA enrollment has not been started").into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    let recovery_codes = match verified {
+        Ok(Some(codes)) => codes,
+        Ok(None) => {
+            if let Some(lockout) = state.login_guard.record_failure(&user_key) {
+                tracing::warn!(key = %user_key, user = %pending.sub, locked_for = lockout.num_seconds(), "Login locked out after repeated failures");
+            }
+            return status_response(start, StatusCode::UNAUTHORIZED, "Invalid verification code").into_response();
+        }
+        Err(e) => return error_response(start, e).into_response(),
+    };
+    state.login_guard.record_success(&user_key);
+
+    // The challenge is spent once it has produced a session.
+    if let Err(e) = state.revocations.revoke(&pending.jti, pending.exp as i64).await {
+        return error_response(start, e).into_response();
+    }
+
+    tracing::info!(user = %pending.sub, source = ?pending.source, role = pending.role.as_str(), enrolled = recovery_codes.is_some(), "Login successful");
+    let user = AuthenticatedUser {
+        username: pending.sub,
+        source: pending.source,
+        role: pending.role,
+        namespaces: pending.namespaces,
+        groups: Vec::new(),
+    };
+    match issue_session_token(&state, user) {
+        Ok(mut body) => {
+            body.recovery_codes = recovery_codes;
+            session_response(&state, start, body, None)
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/mfa/enroll",
+    responses(
+        (status = 200, description = "New authenticator secret", body = MfaSetupInfo),
+        (status = 400, description = "Only password logins can enroll"),
+        (status = 409, description = "MFA is already enabled")
+    )
+)]
+pub async fn mfa_enroll(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    if matches!(claims.source, IdentitySource::Oidc | IdentitySource::Kubernetes) {
+        return status_response(start, StatusCode::BAD_REQUEST, "MFA is only available for password logins").into_response();
+    }
+    begin_mfa_enrollment(&state, start, &claims.sub).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/mfa/activate",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "MFA enabled; recovery codes are shown only once", body = MfaRecoveryCodes),
+        (status = 400, description = "Invalid code or no enrollment in progress")
+    )
+)]
+pub async fn mfa_activate(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<MfaCodeRequest>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    match state.mfa.get(&claims.sub).await {
+        Ok(Some(enrollment)) if !enrollment.enabled => {}
+        Ok(_) => return status_response(start, StatusCode::BAD_REQUEST, "No MFA enrollment in progress").into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match state.mfa.activate(&claims.sub, &payload.code).await {
+        Ok(Some(recovery_codes)) => {
+            tracing::info!(operator = %claims.sub, "MFA enabled");
+            json_response(start, MfaRecoveryCodes { recovery_codes }).into_response()
+        }
+        Ok(None) => status_response(start, StatusCode::BAD_REQUEST, "Invalid verification code").into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/mfa/disable",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "MFA disabled", body = KanriGateRespString),
+        (status = 400, description = "Invalid code"),
+        (status = 403, description = "MFA is required for all accounts")
+    )
+)]
+pub async fn mfa_disable(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<MfaCodeRequest>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    if state.config().mfa_required {
+        return status_response(start, StatusCode::FORBIDDEN, "MFA is required for all accounts").into_response();
+    }
+
+    match state.mfa.verify(&claims.sub, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return status_response(start, StatusCode::BAD_REQUEST, "Invalid verification code").into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match state.mfa.remove(&claims.sub).await {
+        Ok(_) => {
+            tracing::info!(operator = %claims.sub, "MFA disabled");
+            json_response(start, "MFA disabled".to_string()).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/changePassword",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = KanriGateRespString),
+        (status = 400, description = "The new password does not meet the password policy"),
+        (status = 403, description = "Wrong current password, or not a local account"),
+        (status = 429, description = "Too many failed attempts, retry later")
+    )
+)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    if claims.source != IdentitySource::Local {
+        return status_response(start, StatusCode::FORBIDDEN, "Passwords can only be changed for local accounts").into_response();
+    }
+
+    // Wrong current passwords count towards the same lockout as failed logins.
+    let user_key = login_guard::user_key(&claims.sub);
+    if let Some(wait) = state.login_guard.retry_after(&[&user_key]) {
+        let mut response = status_response(start, StatusCode::TOO_MANY_REQUESTS, "Too many failed attempts, try again later").into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, (wait.num_seconds() + 1).into());
+        return response;
+    }
+    match state.authenticator.authenticate(&claims.sub, &payload.current_password).await {
+        Ok(Some(_)) => state.login_guard.record_success(&user_key),
+        Ok(None) => {
+            if let Some(lockout) = state.login_guard.record_failure(&user_key) {
+                tracing::warn!(operator = %claims.sub, locked_for = lockout.num_seconds(), "Password change locked out after repeated failures");
+            }
+            return status_response(start, StatusCode::FORBIDDEN, "Current password is incorrect").into_response();
+        }
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    let config = state.config();
+    if payload.new_password == payload.current_password {
+        return status_response(start, StatusCode::BAD_REQUEST, "New password must differ from the current one").into_response();
+    }
+    if let Some(violation) = password_policy_violation(&payload.new_password, &claims.sub, config.password_min_length) {
+        return status_response(start, StatusCode::BAD_REQUEST, violation).into_response();
+    }
+    let hash = match hash_password(&payload.new_password) {
+        Ok(h) => h,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    // Operators live in the operators Secret; the bootstrap admin's hash is a runtime setting.
+    let store = OperatorStore::new(state.client.clone(), &config.operators_secret);
+    let result = match store.get(&claims.sub).await {
+        Ok(Some(_)) => store.set_password(&claims.sub, &hash).await.map(|_| ()),
+        Ok(None) if claims.sub == config.admin_username => state.settings.set("admin_password_hash", &hash).await,
+        Ok(None) => return status_response(start, StatusCode::FORBIDDEN, "Passwords can only be changed for local accounts").into_response(),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            tracing::info!(operator = %claims.sub, "Password changed");
+            json_response(start, "Password changed".to_string()).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/login/kubernetes",
+    request_body = KubeLoginRequest,
+    responses(
+        (status = 200, description = "Login successful", body = KanriGateRespAuthBody),
+        (status = 401, description = "The API server did not accept the token"),
+        (status = 403, description = "Cluster RBAC does not allow this user to use KanriGate"),
+        (status = 404, description = "Kubernetes token login is not enabled"),
+        (status = 429, description = "Too many failed attempts, retry later")
+    )
+)]
+pub async fn login_kubernetes(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<KubeLoginRequest>,
+) -> Response {
+    let start = Instant::now();
+    let kube_login = match &state.kube_login {
+        Some(kube_login) => kube_login,
+        None => return status_response(start, StatusCode::NOT_FOUND, "Kubernetes token login is not enabled").into_response(),
+    };
+
+    // Tokens carry no username up front, so only the client address is rate limited.
+    let ip = auth::client_ip(&headers, peer, &state.config().trusted_proxies);
+    let ip_key = login_guard::ip_key(&ip);
+    if let Some(wait) = state.login_guard.retry_after(&[&ip_key]) {
+        tracing::warn!(ip = %ip, retry_after = wait.num_seconds(), "Kubernetes login rejected: locked out");
+        let mut response = status_response(start, StatusCode::TOO_MANY_REQUESTS, "Too many failed login attempts, try again later").into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, (wait.num_seconds() + 1).into());
+        return response;
+    }
+
+    let identity = match kube_login.authenticate(payload.token.trim()).await {
+        Ok(Some(identity)) => identity,
+        Ok(None) => {
+            if let Some(lockout) = state.login_guard.record_failure(&ip_key) {
+                tracing::warn!(ip = %ip, locked_for = lockout.num_seconds(), "Login locked out after repeated failures");
+            }
+            return status_response(start, StatusCode::UNAUTHORIZED, "Invalid Kubernetes token").into_response();
+        }
+        Err(e) => return error_response(start, anyhow::anyhow!("TokenReview failed: {}", e)).into_response(),
+    };
+
+    let role = match kube_login.role_for(&identity).await {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            tracing::warn!(user = %identity.username, groups = ?identity.groups, "Kubernetes user is not allowed to use KanriGate");
+            return status_response(start, StatusCode::FORBIDDEN, "You are not allowed to access KanriGate").into_response();
+        }
+        Err(e) => return error_response(start, anyhow::anyhow!("SubjectAccessReview failed: {}", e)).into_response(),
+    };
+
+    tracing::info!(user = %identity.username, source = ?IdentitySource::Kubernetes, role = role.as_str(), groups = ?identity.groups, "Login successful");
+    let user = AuthenticatedUser {
+        username: identity.username,
+        source: IdentitySource::Kubernetes,
+        role,
+        namespaces: None,
+        groups: identity.groups,
+    };
+    match issue_session_token(&state, user) {
+        Ok(body) => session_response(&state, start, body, None),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+/// Returns `body` and also sets it as the backend-managed browser session, reusing
+/// `csrf_token` when renewing a session that already has one.
+fn session_response(state: &AppState, start: Instant, body: AuthBody, csrf_token: Option<&str>) -> Response {
+    let csrf_token = csrf_token.map(str::to_string).unwrap_or_else(oidc::random_token);
+    let cookies = auth::session_cookies(&state.config(), &body, &csrf_token);
+    let mut response = json_response(start, body).into_response();
+    for cookie in cookies {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+/// Starts a new login session for `user`.
+fn issue_session_token(state: &AppState, user: AuthenticatedUser) -> anyhow::Result<AuthBody> {
+    let session = Claims {
+        sub: user.username,
+        exp: 0,
+        jti: String::new(),
+        sid: oidc::random_token(),
+        auth_time: Utc::now().timestamp(),
+        token_use: TokenUse::Access,
+        source: user.source,
+        role: user.role,
+        namespaces: user.namespaces,
+    };
+    let refresh_expires = (Utc::now() + Duration::hours(state.config().refresh_token_ttl_hours)).timestamp();
+    issue_token_pair(state, &session, &oidc::random_token(), refresh_expires)
+}
+
+/// Mints a fresh access/refresh pair carrying the identity and session id of `session`. The
+/// refresh token gets the id `refresh_jti` and expires at `refresh_expires` (unix seconds).
+fn issue_token_pair(state: &AppState, session: &Claims, refresh_jti: &str, refresh_expires: i64) -> anyhow::Result<AuthBody> {
+    let access_ttl = Duration::minutes(state.config().access_token_ttl_minutes);
+    let sign = |token_use: TokenUse, exp: i64, jti: String| {
+        let claims = Claims {
+            exp: exp as usize,
+            jti,
+            token_use,
+            ..session.clone()
+        };
+        state.jwt.encode(&claims)
+    };
+
+    Ok(AuthBody {
+        access_token: sign(TokenUse::Access, (Utc::now() + access_ttl).timestamp(), oidc::random_token())?,
+        token_type: "Bearer".to_string(),
+        expires_in: access_ttl.num_seconds(),
+        refresh_token: sign(TokenUse::Refresh, refresh_expires, refresh_jti.to_string())?,
+        refresh_expires_in: refresh_expires - Utc::now().timestamp(),
+        recovery_codes: None,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/refresh",
+    request_body(content = RefreshRequest, description = "Omit to use the refresh cookie set by the backend"),
+    responses(
+        (status = 200, description = "New access and refresh tokens", body = KanriGateRespAuthBody),
+        (status = 401, description = "Refresh token invalid, expired or revoked"),
+        (status = 403, description = "Cookie refresh without a valid CSRF token")
+    )
+)]
+pub async fn refresh(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Option<Json<RefreshRequest>>,
+) -> Response {
+    let start = Instant::now();
+    let unauthorized = || status_response(start, StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response();
+
+    let refresh_token = match &payload {
+        Some(Json(payload)) => payload.refresh_token.as_str(),
+        None => {
+            let token = match auth::cookie_value(&headers, auth::REFRESH_COOKIE) {
+                Some(token) => token,
+                None => return unauthorized(),
+            };
+            if !auth::csrf_valid(&Method::POST, &headers) {
+                return status_response(start, StatusCode::FORBIDDEN, "Missing or invalid CSRF token").into_response();
+            }
+            token
+        }
+    };
+
+    let mut session = match state.jwt.decode::<Claims>(refresh_token) {
+        Ok(claims) if claims.token_use == TokenUse::Refresh && !claims.sid.is_empty() => claims,
+        _ => return unauthorized(),
+    };
+
+    // A stolen refresh token ends the whole session, for the thief and the victim alike.
+    let end_reused_session = || async {
+        tracing::warn!(user = %session.sub, "Refresh token reuse detected, revoking session");
+        let until = (Utc::now() + Duration::hours(state.config().refresh_token_ttl_hours)).timestamp();
+        match state.revocations.revoke(&session.sid, until).await {
+            Ok(()) => unauthorized(),
+            Err(e) => error_response(start, e).into_response(),
+        }
+    };
+
+    match state.revocations.refresh_status(&session.sid, &session.jti) {
+        RefreshStatus::Usable => {}
+        RefreshStatus::SessionRevoked => return unauthorized(),
+        RefreshStatus::Reused => return end_reused_session().await,
+    }
+
+    // Only operator accounts are looked up again below. Other identities cannot be re-checked
+    // with their source, so their sessions end one refresh lifetime after login and pick up
+    // group or role changes at the next login.
+    let refresh_ttl = Duration::hours(state.config().refresh_token_ttl_hours).num_seconds();
+    let now = Utc::now().timestamp();
+    let mut refresh_expires = now + refresh_ttl;
+    if session.source != IdentitySource::Local {
+        refresh_expires = refresh_expires.min(session.auth_time + refresh_ttl);
+        if refresh_expires <= now {
+            tracing::info!(user = %session.sub, source = ?session.source, "Refresh refused: session reached its maximum lifetime");
+            return unauthorized();
+        }
+    }
+
+    let next_jti = oidc::random_token();
+    match state.revocations.rotate(&session.sid, &session.jti, &next_jti, refresh_expires).await {
+        Ok(true) => {}
+        Ok(false) => return end_reused_session().await,
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    // Pick up role and scope changes, and stop disabled or deleted operators from renewing.
+    if session.source == IdentitySource::Local && session.sub != state.config().admin_username {
+        let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+        match store.get(&session.sub).await {
+            Ok(Some(op)) if !op.disabled => {
+                session.role = op.role;
+                session.namespaces = op.namespaces;
+            }
+            Ok(_) => {
+                tracing::warn!(user = %session.sub, "Refresh refused for disabled or deleted operator");
+                return unauthorized();
+            }
+            Err(e) => return error_response(start, e).into_response(),
+        }
+    }
+
+    match issue_token_pair(&state, &session, &next_jti, refresh_expires) {
+        Ok(body) => session_response(&state, start, body, auth::cookie_value(&headers, auth::CSRF_COOKIE)),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/logout",
+    responses(
+        (status = 200, description = "Session revoked", body = KanriGateRespString),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+pub async fn logout(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    // Revoking the session id invalidates every access and refresh token issued from this login.
+    let until = (Utc::now() + Duration::hours(state.config().refresh_token_ttl_hours)).timestamp();
+    match state.revocations.revoke(&claims.sid, until).await {
+        Ok(()) => {
+            tracing::info!(operator = %claims.sub, "Logged out");
+            let mut response = json_response(start, "Logged out".to_string()).into_response();
+            for cookie in auth::clear_session_cookies(&state.config()) {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+            response
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/oidc/login",
+    responses(
+        (status = 303, description = "Redirect to the identity provider"),
+        (status = 404, description = "OIDC is not configured")
+    )
+)]
+pub async fn oidc_login(State(state): State<AppState>) -> Response {
+    let start = Instant::now();
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return status_response(start, StatusCode::NOT_FOUND, "OIDC login is not configured").into_response(),
+    };
+
+    let flow = OidcFlowState {
+        state: oidc::random_token(),
+        nonce: oidc::random_token(),
+        code_verifier: oidc::random_token(),
+        exp: (Utc::now() + Duration::minutes(10)).timestamp() as usize,
+    };
+
+    let location = match oidc.authorization_url(&flow.state, &flow.nonce, &flow.code_verifier).await {
+        Ok(url) => url,
+        Err(e) => return error_response(start, anyhow::anyhow!("OIDC discovery failed: {}", e)).into_response(),
+    };
+
+    let flow_token = match state.jwt.encode(&flow) {
+        Ok(t) => t,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    let secure = if oidc.redirect_url().starts_with("https://") { "; Secure" } else { "" };
+    let mut headers = HeaderMap::new();
+    headers.insert(header::LOCATION, location.parse().unwrap());
+    headers.insert(
+        header::SET_COOKIE,
+        format!("{}={}; Path=/apps/oidc; HttpOnly; SameSite=Lax; Max-Age=600{}", OIDC_FLOW_COOKIE, flow_token, secure).parse().unwrap(),
+    );
+    (StatusCode::SEE_OTHER, headers).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/oidc/callback",
+    params(OidcCallbackQuery),
+    responses(
+        (status = 303, description = "Login successful, session cookie set"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "User is not in an allowed group")
+    )
+)]
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Response {
+    let start = Instant::now();
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return status_response(start, StatusCode::NOT_FOUND, "OIDC login is not configured").into_response(),
+    };
+
+    if let Some(error) = query.error {
+        let detail = query.error_description.unwrap_or_default();
+        tracing::warn!(error = %error, detail = %detail, "Identity provider returned an error");
+        return status_response(start, StatusCode::UNAUTHORIZED, format!("Identity provider error: {} {}", error, detail).trim_end().to_string()).into_response();
+    }
+
+    let flow = match auth::cookie_value(&headers, OIDC_FLOW_COOKIE).and_then(|token| {
+        state.jwt.decode::<OidcFlowState>(token).ok()
+    }) {
+        Some(flow) => flow,
+        None => return status_response(start, StatusCode::UNAUTHORIZED, "Missing or expired SSO session").into_response(),
+    };
+
+    if query.state.as_deref() != Some(flow.state.as_str()) {
+        return status_response(start, StatusCode::UNAUTHORIZED, "SSO state mismatch").into_response();
+    }
+
+    let code = match query.code {
+        Some(code) => code,
+        None => return status_response(start, StatusCode::BAD_REQUEST, "Missing authorization code").into_response(),
+    };
+
+    let identity = match oidc.authenticate(&code, &flow.code_verifier, &flow.nonce).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::warn!(error = %e, "OIDC authentication failed");
+            return status_response(start, StatusCode::UNAUTHORIZED, "SSO authentication failed").into_response();
+        }
+    };
+
+    let role = match oidc.role_for(&identity) {
+        Some(role) => role,
+        None => {
+            tracing::warn!(user = %identity.username, groups = ?identity.groups, "SSO user is not in an allowed group");
+            return status_response(start, StatusCode::FORBIDDEN, "You are not allowed to access KanriGate").into_response();
+        }
+    };
+
+    tracing::info!(user = %identity.username, role = role.as_str(), "SSO login successful");
+    let user = AuthenticatedUser {
+        username: identity.username,
+        source: IdentitySource::Oidc,
+        role,
+        namespaces: None,
+        groups: identity.groups,
+    };
+    let body = match issue_session_token(&state, user) {
+        Ok(body) => body,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    let secure = if oidc.redirect_url().starts_with("https://") { "; Secure" } else { "" };
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::LOCATION, state.config().oidc_post_login_redirect.parse().unwrap_or(header::HeaderValue::from_static("/")));
+    response_headers.append(
+        header::SET_COOKIE,
+        format!("{}=; Path=/apps/oidc; HttpOnly; SameSite=Lax; Max-Age=0{}", OIDC_FLOW_COOKIE, secure).parse().unwrap(),
+    );
+    for cookie in auth::session_cookies(&state.config(), &body, &oidc::random_token()) {
+        response_headers.append(header::SET_COOKIE, cookie);
+    }
+    (StatusCode::SEE_OTHER, response_headers).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys that verify KanriGate-issued tokens")
+    )
+)]
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.jwt.jwks().clone())
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Service is up; includes the startup security checks", body = KanriGateRespHealthInfo)
+    )
+)]
+pub async fn health(State(state): State<AppState>) -> Response {
+    let start = Instant::now();
+    // Runtime settings such as a changed admin password can resolve issues found at startup.
+    let mut security = state.posture.as_ref().clone();
+    security.issues = state.config().security_issues();
+    security.secure = security.issues.is_empty();
+    security.insecure_defaults_allowed &= !security.secure;
+    json_response(start, HealthInfo {
+        status: "ok".to_string(),
+        security,
+    }).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Caches have synced", body = KanriGateRespCacheStatus),
+        (status = 503, description = "Caches are still syncing", body = KanriGateRespCacheStatus)
+    )
+)]
+pub async fn ready(State(state): State<AppState>) -> Response {
+    let start = Instant::now();
+    let status = state.cache.status();
+    if status.ready() {
+        return json_response(start, status).into_response();
+    }
+    let duration = start.elapsed().as_secs_f64();
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(KanriGateResp::new(503, "Caches are still syncing", duration, status)),
+    ).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/getTemplates",
+    responses(
+        (status = 200, description = "Template ClusterRoles found in the cluster", body = KanriGateRespVecTemplateInfo)
+    )
+)]
+pub async fn get_templates(State(state): State<AppState>) -> Response {
+    let start = Instant::now();
+    match state.cache.templates().await {
+        Ok(templates) => json_response(start, templates).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+/// Responds with 400 when the name or rules of `payload` are invalid.
+fn invalid_template_denial(start: Instant, payload: &TemplateRequest) -> Option<Response> {
+    if let Err(e) = payload.validate() {
+        return Some(status_response(start, StatusCode::BAD_REQUEST, e.to_string()).into_response());
+    }
+    let violation = templates::rules_violation(payload.scope, &payload.rules, payload.allow_privileged_verbs)?;
+    Some(status_response(start, StatusCode::BAD_REQUEST, violation).into_response())
+}
+
+fn template_spec(payload: TemplateRequest) -> TemplateSpec {
+    TemplateSpec {
+        name: payload.name,
+        scope: payload.scope,
+        description: payload.description.filter(|d| !d.trim().is_empty()),
+        rules: payload.rules,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/getTemplate",
+    params(TemplateQuery),
+    responses(
+        (status = 200, description = "The template and its rules", body = KanriGateRespTemplateDetail),
+        (status = 404, description = "No such template")
+    )
+)]
+pub async fn get_template(
+    State(state): State<AppState>,
+    Query(query): Query<TemplateQuery>,
+) -> Response {
+    let start = Instant::now();
+    match TemplateManager::new(state.client).get(query.scope, &query.name).await {
+        Ok(Some(template)) => json_response(start, template).into_response(),
+        Ok(None) => status_response(start, StatusCode::NOT_FOUND, format!("Template {} not found", query.name)).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/createTemplate",
+    request_body = TemplateRequest,
+    responses(
+        (status = 200, description = "Created, or already present with the same rules", body = KanriGateRespApplied),
+        (status = 400, description = "Invalid name or rules, or privileged verbs not allowed"),
+        (status = 403, description = "Only cluster-wide admins can manage templates"),
+        (status = 409, description = "The template exists with different rules or description")
+    )
+)]
+pub async fn create_template(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<TemplateRequest>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if let Some(resp) = invalid_template_denial(start, &payload) {
+        return resp;
+    }
+    let privileged = payload.allow_privileged_verbs;
+    let spec = template_spec(payload);
+    match TemplateManager::new(state.client).create(&spec).await {
+        Ok(applied) => {
+            tracing::info!(operator = %claims.sub, target = %applied.name, outcome = ?applied.outcome, privileged, "Template created");
+            json_response(start, applied).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/updateTemplate",
+    params(UpdateTemplateQuery),
+    request_body = TemplateRequest,
+    responses(
+        (status = 200, description = "The updated template and the bindings it affects", body = KanriGateRespTemplateChanged),
+        (status = 400, description = "Invalid name or rules, or privileged verbs not allowed"),
+        (status = 403, description = "Only cluster-wide admins can manage templates, and only templates KanriGate created"),
+        (status = 404, description = "No such template")
+    )
+)]
+pub async fn update_template(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UpdateTemplateQuery>,
+    Json(payload): Json<TemplateRequest>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if let Some(resp) = invalid_template_denial(start, &payload) {
+        return resp;
+    }
+    let privileged = payload.allow_privileged_verbs;
+    let spec = template_spec(payload);
+    match TemplateManager::new(state.client).update(&spec, query.dry_run).await {
+        Ok(Some(changed)) => {
+            if !changed.dry_run {
+                tracing::info!(
+                    operator = %claims.sub, target = %spec.name, scope = ?spec.scope, privileged,
+                    bindings = changed.blast_radius.bindings(), "Template updated"
+                );
+            }
+            json_response(start, changed).into_response()
+        }
+        Ok(None) => status_response(start, StatusCode::NOT_FOUND, format!("Template {} not found", spec.name)).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteTemplate",
+    params(DeleteTemplateQuery),
+    responses(
+        (status = 200, description = "Deleted; lists the bindings left referencing it", body = KanriGateRespBlastRadius),
+        (status = 403, description = "Only cluster-wide admins can manage templates, and only templates KanriGate created"),
+        (status = 404, description = "No such template"),
+        (status = 409, description = "Bindings still reference the template and force is not set")
+    )
+)]
+pub async fn delete_template(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<DeleteTemplateQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    match TemplateManager::new(state.client).delete(query.scope, &query.name, query.force).await {
+        Ok(Some(dangling)) => {
+            tracing::info!(operator = %claims.sub, target = %query.name, scope = ?query.scope, bindings = dangling.bindings(), "Template deleted");
+            json_response(start, dangling).into_response()
+        }
+        Ok(None) => status_response(start, StatusCode::NOT_FOUND, format!("Template {} not found", query.name)).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/getNamespaces",
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespVecString)
+    )
+)]
+pub async fn get_namespaces(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let start = Instant::now();
+    match state.cache.namespaces().await {
+        Ok(ns) => {
+            let visible: Vec<String> = ns.into_iter().filter(|n| claims.allows_namespace(n)).collect();
+            json_response(start, visible).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/getServiceAccounts",
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespVecString)
+    )
+)]
+pub async fn get_service_accounts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let start = Instant::now();
+    let sas = match state.cache.service_accounts().await {
+        Ok(sas) => sas,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+    if claims.namespaces.is_none() {
+        return json_response(start, sas).into_response();
+    }
+    // Scoped sessions only see users they could act on.
+    let managed = match KubeOps::new(state.client.clone()).managed_service_accounts().await {
+        Ok(managed) => managed,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+    let sas: Vec<String> = sas.into_iter().filter(|sa| managed.contains(sa)).collect();
+    let checks = futures::future::try_join_all(sas.iter().map(|sa| bindings_outside_scope(&state.cache, &claims, SubjectKind::ServiceAccount, sa))).await;
+    match checks {
+        Ok(outside) => {
+            let visible: Vec<String> = sas.into_iter().zip(outside).filter(|(_, outside)| !outside).map(|(sa, _)| sa).collect();
+            json_response(start, visible).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/createServiceAccount",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "Created, or already present with the same content", body = KanriGateRespApplied),
+        (status = 409, description = "An object with the same name exists and differs")
+    )
+)]
+pub async fn create_service_account(
+    State(state): State<AppState>,
+    Query(query): Query<UsernameQuery>,
+) -> Response {
+    let start = Instant::now();
+    let kube = KubeOps::new(state.client);
+    match kube.create_service_account(&query.username).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteServiceAccount",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespString),
+        (status = 403, description = "Only cluster-wide admins can delete ServiceAccounts directly")
+    )
+)]
+pub async fn delete_service_account(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UsernameQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.delete_service_account(&query.username).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteUser",
+    params(DeleteUserQuery),
+    responses(
+        (status = 200, description = "What was deleted, or with dry_run what would be", body = KanriGateRespUserDeletion),
+        (status = 403, description = "User has access outside your delegated scope"),
+        (status = 404, description = "No KanriGate-managed objects for the user")
+    )
+)]
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<DeleteUserQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, query.kind, &query.username).await {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.delete_user(&query.username, query.kind, query.dry_run).await {
+        Ok(deletion) if deletion.objects.is_empty() => {
+            status_response(start, StatusCode::NOT_FOUND, format!("No KanriGate-managed objects for {}", query.username)).into_response()
+        }
+        Ok(deletion) => {
+            if !deletion.dry_run {
+                tracing::info!(operator = %claims.sub, target = %query.username, kind = ?query.kind, objects = deletion.objects.len(), "User deleted");
+            }
+            json_response(start, deletion).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/createSecret",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "Created, or already present with the same content", body = KanriGateRespApplied),
+        (status = 403, description = "Outside your delegated scope, or not a ServiceAccount KanriGate manages"),
+        (status = 409, description = "An object with the same name exists and differs")
+    )
+)]
+pub async fn create_secret(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UsernameQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.create_secret(&query.username).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteSecret",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespString),
+        (status = 403, description = "User has access outside your delegated scope")
+    )
+)]
+pub async fn delete_secret(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UsernameQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.delete_secret(&query.username).await {
+        Ok(names) => json_response(start, names).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/createRoleBinding",
+    params(RoleBindingQuery),
+    responses(
+        (status = 200, description = "Created, or already present with the same content", body = KanriGateRespApplied),
+        (status = 400, description = "Unknown template"),
+        (status = 409, description = "An object with the same name exists and differs")
+    )
+)]
+pub async fn create_role_binding(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RoleBindingQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = namespace_scope_denial(start, &claims, &query.namespace) {
+        return resp;
+    }
+    if let Some(resp) = unknown_template_denial(start, &state, TemplateScope::Namespaced, &query.permission).await {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.create_role_binding(&query.username, query.kind, &query.namespace, &query.permission).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteRoleBinding",
+    params(RoleBindingQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespString)
+    )
+)]
+pub async fn delete_role_binding(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RoleBindingQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = namespace_scope_denial(start, &claims, &query.namespace) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.delete_role_binding(&query.username, query.kind, &query.namespace, &query.permission).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/createClusterRoleBinding",
+    params(ClusterRoleBindingQuery),
+    responses(
+        (status = 200, description = "Created, or already present with the same content", body = KanriGateRespApplied),
+        (status = 400, description = "Unknown template"),
+        (status = 403, description = "Only cluster-wide admins can grant cluster access"),
+        (status = 409, description = "An object with the same name exists and differs")
+    )
+)]
+pub async fn create_cluster_role_binding(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ClusterRoleBindingQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if let Some(resp) = unknown_template_denial(start, &state, TemplateScope::Cluster, &query.permission).await {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.create_cluster_role_binding(&query.username, query.kind, &query.permission).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/apps/deleteClusterRoleBinding",
+    params(ClusterRoleBindingQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespString),
+        (status = 403, description = "Only cluster-wide admins can change cluster access")
+    )
+)]
+pub async fn delete_cluster_role_binding(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ClusterRoleBindingQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client);
+    match kube.delete_cluster_role_binding(&query.username, query.kind, &query.permission).await {
+        Ok(name) => json_response(start, name).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/getFilteredRoleBindings",
+    params(SubjectQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespVecString)
+    )
+)]
+pub async fn get_filtered_role_bindings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<SubjectQuery>,
+) -> Response {
+    let start = Instant::now();
+    match state.cache.filtered_role_bindings(&query.username, query.kind).await {
+        Ok(mut data) => {
+            if let Some(by_namespace) = data.as_object_mut() {
+                by_namespace.retain(|ns, _| claims.allows_namespace(ns));
+            }
+            json_response(start, data).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/getFilteredClusterRoleBindings",
+    params(SubjectQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespVecString),
+        (status = 403, description = "User has access outside your delegated scope")
+    )
+)]
+pub async fn get_filtered_cluster_role_bindings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<SubjectQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, query.kind, &query.username).await {
+        return resp;
+    }
+    match state.cache.filtered_cluster_role_bindings(&query.username, query.kind).await {
+        Ok(data) => json_response(start, data).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+/// The TokenRequest and CertificateSigningRequest APIs reject shorter lifetimes.
+const MIN_BOUND_TOKEN_SECONDS: i64 = 600;
+
+/// Resolves the token a kubeconfig request asks for against the configured default and limit.
+fn kubeconfig_token(config: &BaseConfig, token_type: Option<KubeconfigTokenType>, expiration_seconds: Option<i64>) -> Result<KubeconfigToken, String> {
+    let default_type = if config.kubeconfig_token_type == "bound" { KubeconfigTokenType::Bound } else { KubeconfigTokenType::Legacy };
+    match token_type.unwrap_or(default_type) {
+        KubeconfigTokenType::Legacy if expiration_seconds.is_some() => {
+            Err("expiration_seconds only applies to bound tokens".to_string())
+        }
+        KubeconfigTokenType::Legacy => Ok(KubeconfigToken::Legacy),
+        KubeconfigTokenType::Bound => {
+            let seconds = expiration_seconds.unwrap_or(config.kubeconfig_token_ttl_seconds);
+            if !(MIN_BOUND_TOKEN_SECONDS..=config.kubeconfig_token_max_ttl_seconds).contains(&seconds) {
+                return Err(format!(
+                    "expiration_seconds must be between {} and {}",
+                    MIN_BOUND_TOKEN_SECONDS, config.kubeconfig_token_max_ttl_seconds
+                ));
+            }
+            Ok(KubeconfigToken::Bound {
+                expiration_seconds: seconds,
+                audiences: config.kubeconfig_token_audiences.clone(),
+            })
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/generateK8sConfig",
+    params(K8sConfigQuery),
+    responses(
+        (status = 200, description = "Success", body = KanriGateRespString),
+        (status = 400, description = "Invalid token type or expiration"),
+        (status = 403, description = "Outside your delegated scope, or not a ServiceAccount KanriGate manages")
+    )
+)]
+pub async fn generate_k8s_config(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<K8sConfigQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = namespace_scope_denial(start, &claims, &query.namespace) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client.clone());
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let config = state.config();
+    let token = match kubeconfig_token(&config, query.token_type, query.expiration_seconds) {
+        Ok(token) => token,
+        Err(message) => return status_response(start, StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    match kube.generate_k8s_config(
+        &query.username, 
+        &query.namespace, 
+        &config.cluster_name,
+        &config.control_plane_address,
+        &token,
+    ).await {
+        Ok(config) => json_response(start, config).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/rotateToken",
+    params(RotateTokenQuery),
+    responses(
+        (status = 200, description = "Token Secret replaced and the old Secret tokens invalidated", body = KanriGateRespTokenRotated),
+        (status = 400, description = "Invalid token type or expiration"),
+        (status = 403, description = "Outside your delegated scope, or not a ServiceAccount KanriGate manages"),
+        (status = 404, description = "The user has no token Secret")
+    )
+)]
+pub async fn rotate_token(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RotateTokenQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = namespace_scope_denial(start, &claims, &query.namespace) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client.clone());
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let config = state.config();
+    let token = match kubeconfig_token(&config, query.token_type, query.expiration_seconds) {
+        Ok(token) => token,
+        Err(message) => return status_response(start, StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    match kube.user_token_secrets(&query.username).await {
+        Ok(secrets) if !secrets.is_empty() => {}
+        Ok(_) => return status_response(start, StatusCode::NOT_FOUND, format!("{} has no token Secret to rotate", query.username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    let rotator = TokenRotator::new(state.client.clone(), &config.token_rotations_secret);
+    let rotation = match rotator.rotate(&query.username, &claims.sub).await {
+        Ok(rotation) => rotation,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+    tracing::info!(operator = %claims.sub, target = %query.username, new_secret = %rotation.new_secret, old_secrets = ?rotation.old_secrets, "Token rotated");
+
+    match kube.generate_k8s_config(
+        &query.username,
+        &query.namespace,
+        &config.cluster_name,
+        &config.control_plane_address,
+        &token,
+    ).await {
+        Ok(kubeconfig) => json_response(start, TokenRotated {
+            kubeconfig,
+            rotation: rotation.into(),
+            // Bound tokens are tied to the ServiceAccount, not to a Secret.
+            still_valid: format!(
+                "Bound tokens issued for {} before this rotation stay valid until they expire, at most {} seconds; delete and recreate the ServiceAccount to revoke them",
+                query.username, config.kubeconfig_token_max_ttl_seconds
+            ),
+        }).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/apps/getTokenRotations",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "Rotations of the user's token, newest first", body = KanriGateRespVecTokenRotationInfo),
+        (status = 403, description = "User has access outside your delegated scope")
+    )
+)]
+pub async fn get_token_rotations(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UsernameQuery>,
+) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let rotator = TokenRotator::new(state.client.clone(), &state.config().token_rotations_secret);
+    match rotator.history(&query.username).await {
+        Ok(history) => json_response(start, history.into_iter().map(TokenRotationInfo::from).collect::<Vec<_>>()).into_response(),
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/apps/provisionUser",
+    request_body = ProvisionUserRequest,
+    responses(
+        (status = 200, description = "ServiceAccount, token Secret and bindings created", body = KanriGateRespUserProvisioned),
+        (status = 400, description = "Invalid user, unknown template or invalid token"),
+        (status = 403, description = "Namespace or cluster access outside your permissions"),
+        (status = 409, description = "The ServiceAccount already exists"),
+        (status = 500, description = "A step failed and the objects created before it were deleted")
     )
 )]
-pub async fn login(
+pub async fn provision_user(
     State(state): State<AppState>,
-    Json(payload): Json<LoginRequest>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<ProvisionUserRequest>,
 ) -> Response {
     let start = Instant::now();
-    
-    // 1. Check username
-    if payload.username != state.config.admin_username {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(KanriGateResp::new(401, "Invalid credentials", start.elapsed().as_secs_f64(), String::new()))
-        ).into_response();
+    if let Err(e) = payload.validate() {
+        return status_response(start, StatusCode::BAD_REQUEST, e.to_string()).into_response();
     }
-
-    // 2. Verify password hash
-    let parsed_hash = match PasswordHash::new(&state.config.admin_password_hash) {
-        Ok(h) => h,
-        Err(e) => return error_response(start, anyhow::anyhow!("Invalid password hash config: {}", e)).into_response(),
+    let templates = match state.cache.templates().await {
+        Ok(templates) => templates,
+        Err(e) => return error_response(start, e).into_response(),
     };
-
-    if Argon2::default().verify_password(payload.password.as_bytes(), &parsed_hash).is_err() {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(KanriGateResp::new(401, "Invalid credentials", start.elapsed().as_secs_f64(), String::new()))
-        ).into_response();
+    let known = |scope: TemplateScope, name: &str| templates.iter().any(|t| t.scope == scope && t.name == name);
+    if let Some(unknown) = payload.role_bindings.values().flatten().find(|p| !known(TemplateScope::Namespaced, p)) {
+        return status_response(start, StatusCode::BAD_REQUEST, format!("Unknown namespaced template {}", unknown)).into_response();
+    }
+    let cluster_permission = payload.cluster_permission.filter(|p| p != "none");
+    if let Some(permission) = &cluster_permission {
+        if !known(TemplateScope::Cluster, permission) {
+            return status_response(start, StatusCode::BAD_REQUEST, format!("Unknown cluster template {}", permission)).into_response();
+        }
+        if claims.role != Role::Admin || claims.namespaces.is_some() {
+            return status_response(start, StatusCode::FORBIDDEN, "Only cluster-wide admins can grant cluster permissions").into_response();
+        }
+    }
+    for namespace in std::iter::once(&payload.namespace).chain(payload.role_bindings.keys()) {
+        if let Some(resp) = namespace_scope_denial(start, &claims, namespace) {
+            return resp;
+        }
     }
 
-    // 3. Generate JWT
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
-        .expect("valid timestamp")
-        .timestamp();
-
-    let claims = Claims {
-        sub: payload.username,
-        exp: expiration as usize,
+    let config = state.config();
+    let token = match kubeconfig_token(&config, payload.token_type, payload.expiration_seconds) {
+        Ok(token) => token,
+        Err(message) => return status_response(start, StatusCode::BAD_REQUEST, message).into_response(),
     };
+    let kube = KubeOps::new(state.client.clone());
+    // Rolling back must never delete an account that existed before.
+    match kube.service_account_exists(&payload.username).await {
+        Ok(false) => {}
+        Ok(true) => return status_response(start, StatusCode::CONFLICT, format!("ServiceAccount {} already exists", payload.username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
 
-    let token = match encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    ) {
-        Ok(t) => t,
-        Err(e) => return error_response(start, anyhow::anyhow!("Token generation failed: {}", e)).into_response(),
+    let plan = ProvisionPlan {
+        username: payload.username,
+        namespace: payload.namespace,
+        role_bindings: payload.role_bindings,
+        cluster_permission,
+        token,
     };
-
-    json_response(start, AuthBody {
-        access_token: token,
-        token_type: "Bearer".to_string(),
-    }).into_response()
+    match provisioning::provision(&kube, &plan, &config.cluster_name, &config.control_plane_address).await {
+        Ok(provisioned) => {
+            tracing::info!(operator = %claims.sub, target = %plan.username, created = ?provisioned.created, "User provisioned");
+            json_response(start, UserProvisioned {
+                kubeconfig: provisioned.kubeconfig,
+                created: provisioned.created,
+            }).into_response()
+        }
+        Err(failure) => {
+            tracing::error!(operator = %claims.sub, target = %plan.username, rolled_back = ?failure.rolled_back, left_behind = ?failure.left_behind, error = %failure.error, "User provisioning failed");
+            status_response(start, StatusCode::INTERNAL_SERVER_ERROR, failure.to_string()).into_response()
+        }
+    }
 }
 
 #[utoipa::path(
-    get,
-    path = "/apps/getTemplates",
+    post,
+    path = "/apps/generateK8sCertConfig",
+    request_body = K8sCertConfigRequest,
     responses(
-        (status = 200, description = "Success", body = KanriGateRespVecString)
+        (status = 200, description = "Kubeconfig with a client certificate for the User subject", body = KanriGateRespString),
+        (status = 400, description = "Invalid user, groups or expiration"),
+        (status = 403, description = "Namespace, groups or user outside your permissions")
     )
 )]
-pub async fn get_templates() -> impl IntoResponse {
+pub async fn generate_k8s_cert_config(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<K8sCertConfigRequest>,
+) -> Response {
     let start = Instant::now();
-    let templates = permissions::TEMPLATES.to_vec();
-    json_response(start, templates)
+    if let Err(e) = payload.validate() {
+        return status_response(start, StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    if payload.groups.iter().any(|g| g.trim().is_empty() || g.starts_with("system:")) {
+        return status_response(start, StatusCode::BAD_REQUEST, "Groups must be non-empty and must not be system: groups").into_response();
+    }
+    // Group bindings are invisible to the scope checks, so only cluster-wide admins may pick groups.
+    if !payload.groups.is_empty() && (claims.role != Role::Admin || claims.namespaces.is_some()) {
+        return status_response(start, StatusCode::FORBIDDEN, "Only cluster-wide admins can issue certificates with groups").into_response();
+    }
+    if let Some(resp) = namespace_scope_denial(start, &claims, &payload.namespace) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client.clone());
+    // A self-approved certificate for a User bound outside KanriGate would impersonate that user.
+    if claims.role != Role::Admin || claims.namespaces.is_some() {
+        match kube.is_managed_user(&payload.username).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!(operator = %claims.sub, target = %payload.username, "Forbidden: certificate for a User KanriGate does not manage");
+                return status_response(start, StatusCode::FORBIDDEN, format!(
+                    "User {} has no bindings or is bound outside KanriGate; only cluster-wide admins can issue its certificate",
+                    payload.username
+                )).into_response();
+            }
+            Err(e) => return error_response(start, e).into_response(),
+        }
+    }
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::User, &payload.username).await {
+        return resp;
+    }
+
+    let config = state.config();
+    let seconds = payload.expiration_seconds.unwrap_or(config.client_cert_ttl_seconds);
+    if !(MIN_BOUND_TOKEN_SECONDS..=config.client_cert_max_ttl_seconds).contains(&seconds) {
+        return status_response(start, StatusCode::BAD_REQUEST, format!(
+            "expiration_seconds must be between {} and {}",
+            MIN_BOUND_TOKEN_SECONDS, config.client_cert_max_ttl_seconds
+        )).into_response();
+    }
+
+    match kube.generate_k8s_cert_config(
+        &payload.username,
+        &payload.groups,
+        &payload.namespace,
+        &config.cluster_name,
+        &config.control_plane_address,
+        seconds,
+    ).await {
+        Ok(kubeconfig) => {
+            tracing::info!(operator = %claims.sub, target = %payload.username, groups = ?payload.groups, expiration_seconds = seconds, "Client certificate issued");
+            json_response(start, kubeconfig).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
 }
 
 #[utoipa::path(
-    get,
-    path = "/apps/getNamespaces",
+    post,
+    path = "/apps/generateK8sConfigDownloadFile",
+    params(K8sConfigQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespVecString)
+        (status = 200, description = "Success", body = String),
+        (status = 400, description = "Invalid token type or expiration"),
+        (status = 403, description = "Outside your delegated scope, or not a ServiceAccount KanriGate manages")
     )
 )]
-pub async fn get_namespaces(State(state): State<AppState>) -> Response {
+pub async fn generate_k8s_config_download(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<K8sConfigQuery>,
+) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.get_namespaces().await {
-        Ok(ns) => json_response(start, ns).into_response(),
+    if let Some(resp) = namespace_scope_denial(start, &claims, &query.namespace) {
+        return resp;
+    }
+    let kube = KubeOps::new(state.client.clone());
+    if let Some(resp) = subject_scope_denial(start, &state, &claims, SubjectKind::ServiceAccount, &query.username).await {
+        return resp;
+    }
+    let config = state.config();
+    let token = match kubeconfig_token(&config, query.token_type, query.expiration_seconds) {
+        Ok(token) => token,
+        Err(message) => return status_response(start, StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    match kube.generate_k8s_config(
+        &query.username, 
+        &query.namespace, 
+        &config.cluster_name,
+        &config.control_plane_address,
+        &token,
+    ).await {
+        Ok(config) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, "application/x-yaml".parse().unwrap());
+            let filename = format!("kubeconfig-{}-{}.yaml", query.username, query.namespace);
+            headers.insert(
+                header::CONTENT_DISPOSITION, 
+                format!("attachment; filename=\"{}\"", filename).parse().unwrap()
+            );
+            (headers, config).into_response()
+        },
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
     get,
-    path = "/apps/getServiceAccounts",
+    path = "/apps/getOperators",
     responses(
-        (status = 200, description = "Success", body = KanriGateRespVecString)
+        (status = 200, description = "Success", body = KanriGateRespVecOperatorInfo),
+        (status = 403, description = "Only cluster-wide admins can manage operators")
     )
 )]
-pub async fn get_service_accounts(State(state): State<AppState>) -> Response {
+pub async fn get_operators(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.get_service_accounts().await {
-        Ok(sas) => json_response(start, sas).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.list().await {
+        Ok(ops) => json_response(start, ops.into_iter().map(OperatorInfo::from).collect::<Vec<_>>()).into_response(),
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
     post,
-    path = "/apps/createServiceAccount",
-    params(UsernameQuery),
+    path = "/apps/createOperator",
+    request_body = CreateOperatorRequest,
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Success", body = KanriGateRespOperatorInfo),
+        (status = 400, description = "Invalid operator"),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 409, description = "Operator already exists")
     )
 )]
-pub async fn create_service_account(
+pub async fn create_operator(
     State(state): State<AppState>,
-    Query(query): Query<UsernameQuery>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateOperatorRequest>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.create_service_account(&query.username).await {
-        Ok(name) => json_response(start, name).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if payload.username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot create an account for themselves").into_response();
+    }
+    if let Err(e) = payload.validate() {
+        return status_response(start, StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    if payload.username == state.config().admin_username {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operator name is reserved for the bootstrap admin").into_response();
+    }
+    if payload.namespaces.as_ref().is_some_and(|ns| ns.is_empty() || ns.iter().any(|n| n.trim().is_empty())) {
+        return status_response(start, StatusCode::BAD_REQUEST, "Namespace scope must list at least one non-empty namespace or glob").into_response();
+    }
+    if let Some(violation) = password_policy_violation(&payload.password, &payload.username, state.config().password_min_length) {
+        return status_response(start, StatusCode::BAD_REQUEST, violation).into_response();
+    }
+
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.get(&payload.username).await {
+        Ok(Some(_)) => return status_response(start, StatusCode::CONFLICT, format!("Operator {} already exists", payload.username)).into_response(),
+        Ok(None) => {}
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    let hash = match hash_password(&payload.password) {
+        Ok(h) => h,
+        Err(e) => return error_response(start, e).into_response(),
+    };
+
+    match store.add(&payload.username, &hash, payload.role, payload.namespaces).await {
+        Ok(op) => {
+            tracing::info!(operator = %claims.sub, target = %op.username, role = op.role.as_str(), "Operator created");
+            json_response(start, OperatorInfo::from(op)).into_response()
+        }
+        Err(e) => error_response(start, e).into_response(),
+    }
+}
+
+async fn set_operator_disabled(state: AppState, claims: Claims, username: String, disabled: bool) -> Response {
+    let start = Instant::now();
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot change their own status").into_response();
+    }
+
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.get(&username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return status_response(start, StatusCode::NOT_FOUND, format!("Operator {} not found", username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match store.set_disabled(&username, disabled).await {
+        Ok(op) => {
+            tracing::info!(operator = %claims.sub, target = %op.username, disabled, "Operator status changed");
+            if disabled {
+                revoke_api_keys_of(&state, &claims.sub, &op.username).await;
+            }
+            json_response(start, OperatorInfo::from(op)).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
-    delete,
-    path = "/apps/deleteServiceAccount",
+    post,
+    path = "/apps/disableOperator",
     params(UsernameQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Success", body = KanriGateRespOperatorInfo),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "Operator not found")
     )
 )]
-pub async fn delete_service_account(
+pub async fn disable_operator(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(query): Query<UsernameQuery>,
 ) -> Response {
-    let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.delete_service_account(&query.username).await {
-        Ok(name) => json_response(start, name).into_response(),
-        Err(e) => error_response(start, e).into_response(),
-    }
+    set_operator_disabled(state, claims, query.username, true).await
 }
 
 #[utoipa::path(
     post,
-    path = "/apps/createSecret",
+    path = "/apps/enableOperator",
     params(UsernameQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Success", body = KanriGateRespOperatorInfo),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "Operator not found")
     )
 )]
-pub async fn create_secret(
+pub async fn enable_operator(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(query): Query<UsernameQuery>,
 ) -> Response {
-    let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.create_secret(&query.username).await {
-        Ok(name) => json_response(start, name).into_response(),
-        Err(e) => error_response(start, e).into_response(),
-    }
+    set_operator_disabled(state, claims, query.username, false).await
 }
 
 #[utoipa::path(
-    delete,
-    path = "/apps/deleteSecret",
-    params(UsernameQuery),
+    post,
+    path = "/apps/setOperatorRole",
+    params(OperatorRoleQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Success", body = KanriGateRespOperatorInfo),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "Operator not found")
     )
 )]
-pub async fn delete_secret(
+pub async fn set_operator_role(
     State(state): State<AppState>,
-    Query(query): Query<UsernameQuery>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<OperatorRoleQuery>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.delete_secret(&query.username).await {
-        Ok(names) => json_response(start, names).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if query.username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot change their own role").into_response();
+    }
+
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.get(&query.username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return status_response(start, StatusCode::NOT_FOUND, format!("Operator {} not found", query.username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match store.set_role(&query.username, query.role).await {
+        Ok(op) => {
+            tracing::info!(operator = %claims.sub, target = %op.username, role = op.role.as_str(), "Operator role changed");
+            revoke_api_keys_of(&state, &claims.sub, &op.username).await;
+            json_response(start, OperatorInfo::from(op)).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
     post,
-    path = "/apps/createRoleBinding",
-    params(RoleBindingQuery),
+    path = "/apps/setOperatorNamespaces",
+    request_body = OperatorNamespacesRequest,
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Success", body = KanriGateRespOperatorInfo),
+        (status = 400, description = "Invalid scope"),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "Operator not found")
     )
 )]
-pub async fn create_role_binding(
+pub async fn set_operator_namespaces(
     State(state): State<AppState>,
-    Query(query): Query<RoleBindingQuery>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<OperatorNamespacesRequest>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.create_role_binding(&query.username, &query.namespace, &query.permission).await {
-        Ok(name) => json_response(start, name).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if payload.username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot change their own namespace scope").into_response();
+    }
+    if payload.namespaces.as_ref().is_some_and(|ns| ns.is_empty() || ns.iter().any(|n| n.trim().is_empty())) {
+        return status_response(start, StatusCode::BAD_REQUEST, "Namespace scope must list at least one non-empty namespace or glob").into_response();
+    }
+
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.get(&payload.username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return status_response(start, StatusCode::NOT_FOUND, format!("Operator {} not found", payload.username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match store.set_namespaces(&payload.username, payload.namespaces).await {
+        Ok(op) => {
+            tracing::info!(operator = %claims.sub, target = %op.username, namespaces = ?op.namespaces, "Operator namespace scope changed");
+            revoke_api_keys_of(&state, &claims.sub, &op.username).await;
+            json_response(start, OperatorInfo::from(op)).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
-    delete,
-    path = "/apps/deleteRoleBinding",
-    params(RoleBindingQuery),
+    post,
+    path = "/apps/resetOperatorMfa",
+    params(UsernameQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "MFA enrollment removed", body = KanriGateRespString),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "No MFA enrollment for that user")
     )
 )]
-pub async fn delete_role_binding(
+pub async fn reset_operator_mfa(
     State(state): State<AppState>,
-    Query(query): Query<RoleBindingQuery>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<UsernameQuery>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.delete_role_binding(&query.username, &query.namespace, &query.permission).await {
-        Ok(name) => json_response(start, name).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if query.username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot reset their own MFA; use /apps/mfa/disable").into_response();
+    }
+    match state.mfa.remove(&query.username).await {
+        Ok(true) => {
+            tracing::info!(operator = %claims.sub, target = %query.username, "Operator MFA reset");
+            json_response(start, format!("MFA for {} reset", query.username)).into_response()
+        }
+        Ok(false) => status_response(start, StatusCode::NOT_FOUND, format!("No MFA enrollment for {}", query.username)).into_response(),
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
-    post,
-    path = "/apps/createClusterRoleBinding",
-    params(ClusterRoleBindingQuery),
+    get,
+    path = "/apps/getLoginLockouts",
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Active login lockouts on this replica", body = KanriGateRespVecLockoutInfo),
+        (status = 403, description = "Only cluster-wide admins can manage operators")
     )
 )]
-pub async fn create_cluster_role_binding(
+pub async fn get_login_lockouts(
     State(state): State<AppState>,
-    Query(query): Query<ClusterRoleBindingQuery>,
+    Extension(claims): Extension<Claims>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.create_cluster_role_binding(&query.username, &query.permission).await {
-        Ok(name) => json_response(start, name).into_response(),
-        Err(e) => error_response(start, e).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
     }
+    let lockouts: Vec<LockoutInfo> = state.login_guard.lockouts();
+    json_response(start, lockouts).into_response()
 }
 
 #[utoipa::path(
-    delete,
-    path = "/apps/deleteClusterRoleBinding",
-    params(ClusterRoleBindingQuery),
+    post,
+    path = "/apps/clearLoginLockout",
+    params(ClearLockoutQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Lockout cleared", body = KanriGateRespString),
+        (status = 400, description = "Neither username nor ip given"),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "No lockout for that key")
     )
 )]
-pub async fn delete_cluster_role_binding(
+pub async fn clear_login_lockout(
     State(state): State<AppState>,
-    Query(query): Query<ClusterRoleBindingQuery>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ClearLockoutQuery>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.delete_cluster_role_binding(&query.username, &query.permission).await {
-        Ok(name) => json_response(start, name).into_response(),
-        Err(e) => error_response(start, e).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    let key = match (query.username.as_deref(), query.ip.as_deref()) {
+        (Some(username), _) => login_guard::user_key(username),
+        (None, Some(ip)) => login_guard::ip_key(ip),
+        (None, None) => return status_response(start, StatusCode::BAD_REQUEST, "Either username or ip is required").into_response(),
+    };
+
+    if !state.login_guard.clear(&key) {
+        return status_response(start, StatusCode::NOT_FOUND, format!("No failed logins recorded for {}", key)).into_response();
     }
+    tracing::info!(operator = %claims.sub, key = %key, "Login lockout cleared");
+    json_response(start, format!("Lockout for {} cleared", key)).into_response()
 }
 
 #[utoipa::path(
-    post,
-    path = "/apps/getFilteredRoleBindings",
+    delete,
+    path = "/apps/deleteOperator",
     params(UsernameQuery),
     responses(
-        (status = 200, description = "Success", body = KanriGateRespVecString)
+        (status = 200, description = "Success", body = KanriGateRespString),
+        (status = 403, description = "Only cluster-wide admins can manage operators"),
+        (status = 404, description = "Operator not found")
     )
 )]
-pub async fn get_filtered_role_bindings(
+pub async fn delete_operator(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(query): Query<UsernameQuery>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.get_filtered_role_bindings(&query.username).await {
-        Ok(data) => json_response(start, data).into_response(),
+    if let Some(resp) = cluster_admin_denial(start, &claims) {
+        return resp;
+    }
+    if query.username == claims.sub {
+        return status_response(start, StatusCode::BAD_REQUEST, "Operators cannot delete themselves").into_response();
+    }
+
+    let store = OperatorStore::new(state.client.clone(), &state.config().operators_secret);
+    match store.get(&query.username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return status_response(start, StatusCode::NOT_FOUND, format!("Operator {} not found", query.username)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match store.remove(&query.username).await {
+        Ok(()) => {
+            tracing::info!(operator = %claims.sub, target = %query.username, "Operator deleted");
+            revoke_api_keys_of(&state, &claims.sub, &query.username).await;
+            json_response(start, query.username).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
+/// Unscoped admins manage every key; everyone else only their own.
+fn manages_all_api_keys(claims: &Claims) -> bool {
+    claims.role == Role::Admin && claims.namespaces.is_none()
+}
+
 #[utoipa::path(
-    post,
-    path = "/apps/getFilteredClusterRoleBindings",
-    params(UsernameQuery),
+    get,
+    path = "/apps/getApiKeys",
     responses(
-        (status = 200, description = "Success", body = KanriGateRespVecString)
+        (status = 200, description = "Your API keys, or every key for cluster-wide admins", body = KanriGateRespVecApiKeyInfo)
     )
 )]
-pub async fn get_filtered_cluster_role_bindings(
+pub async fn get_api_keys(
     State(state): State<AppState>,
-    Query(query): Query<UsernameQuery>,
+    Extension(claims): Extension<Claims>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client);
-    match kube.get_filtered_cluster_role_bindings(&query.username).await {
-        Ok(data) => json_response(start, data).into_response(),
+    match state.api_keys.list().await {
+        Ok(keys) => {
+            let keys: Vec<ApiKeyInfo> = keys.into_iter()
+                .filter(|k| manages_all_api_keys(&claims) || k.owner == claims.sub)
+                .map(ApiKeyInfo::from)
+                .collect();
+            json_response(start, keys).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
     post,
-    path = "/apps/generateK8sConfig",
-    params(K8sConfigQuery),
+    path = "/apps/createApiKey",
+    request_body = CreateApiKeyRequest,
     responses(
-        (status = 200, description = "Success", body = KanriGateRespString)
+        (status = 200, description = "Key created; the token is shown only once", body = KanriGateRespApiKeyCreated),
+        (status = 400, description = "Invalid name, scope or lifetime"),
+        (status = 403, description = "Role or scope exceeds your own, or not an operator account session"),
+        (status = 409, description = "You already have a key with that name")
     )
 )]
-pub async fn generate_k8s_config(
+pub async fn create_api_key(
     State(state): State<AppState>,
-    Query(query): Query<K8sConfigQuery>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateApiKeyRequest>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client.clone());
-    match kube.generate_k8s_config(
-        &query.username, 
-        &query.namespace, 
-        &state.config.cluster_name,
-        &state.config.control_plane_address
-    ).await {
-        Ok(config) => json_response(start, config).into_response(),
+    if let Some(denied) = api_key_denial(start, &claims) {
+        return denied;
+    }
+    // Only operator accounts have their keys revoked when they change; an LDAP, OIDC or
+    // Kubernetes identity would keep its key's role and scope after losing them at the source.
+    if claims.source != IdentitySource::Local {
+        tracing::warn!(operator = %claims.sub, source = ?claims.source, "Forbidden: API key for an external identity");
+        return status_response(start, StatusCode::FORBIDDEN, "API keys can only be created from an operator account session").into_response();
+    }
+    if let Err(e) = payload.validate() {
+        return status_response(start, StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    let days = payload.expires_in_days.unwrap_or(state.config().api_key_default_ttl_days);
+    if days < 1 || days > state.config().api_key_max_ttl_days {
+        return status_response(start, StatusCode::BAD_REQUEST, format!("Lifetime must be between 1 and {} days", state.config().api_key_max_ttl_days)).into_response();
+    }
+
+    let role = payload.role.unwrap_or(claims.role);
+    if role > claims.role {
+        return status_response(start, StatusCode::FORBIDDEN, format!("Cannot create a {} key with a {} session", role.as_str(), claims.role.as_str())).into_response();
+    }
+
+    let namespaces = match payload.namespaces {
+        Some(ns) if ns.is_empty() || ns.iter().any(|n| n.trim().is_empty()) => {
+            return status_response(start, StatusCode::BAD_REQUEST, "Namespace scope must list at least one non-empty namespace or glob").into_response();
+        }
+        Some(ns) => Some(ns),
+        None => claims.namespaces.clone(),
+    };
+    // A scoped session may narrow its scope to listed patterns or concrete namespaces inside them, never widen it.
+    if let (Some(own), Some(requested)) = (&claims.namespaces, &namespaces) {
+        let outside = requested.iter()
+            .find(|ns| !own.contains(ns) && (ns.contains(['*', '?']) || !claims.allows_namespace(ns)));
+        if let Some(ns) = outside {
+            return status_response(start, StatusCode::FORBIDDEN, format!("Namespace {} is outside your delegated scope", ns)).into_response();
+        }
+    }
+
+    match state.api_keys.list().await {
+        Ok(keys) if keys.iter().any(|k| k.owner == claims.sub && k.name == payload.name) => {
+            return status_response(start, StatusCode::CONFLICT, format!("API key {} already exists", payload.name)).into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match state.api_keys.create(&payload.name, &claims.sub, claims.source, role, namespaces, Duration::days(days)).await {
+        Ok((key, token)) => {
+            tracing::info!(operator = %claims.sub, key = %key.id, name = %key.name, role = key.role.as_str(), expires_at = %key.expires_at, "API key created");
+            json_response(start, ApiKeyCreated { token, api_key: ApiKeyInfo::from(key) }).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
 
 #[utoipa::path(
-    post,
-    path = "/apps/generateK8sConfigDownloadFile",
-    params(K8sConfigQuery),
+    delete,
+    path = "/apps/deleteApiKey",
+    params(ApiKeyQuery),
     responses(
-        (status = 200, description = "Success", body = String)
+        (status = 200, description = "Key revoked", body = KanriGateRespString),
+        (status = 404, description = "API key not found")
     )
 )]
-pub async fn generate_k8s_config_download(
+pub async fn delete_api_key(
     State(state): State<AppState>,
-    Query(query): Query<K8sConfigQuery>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ApiKeyQuery>,
 ) -> Response {
     let start = Instant::now();
-    let kube = KubeOps::new(state.client.clone());
-    match kube.generate_k8s_config(
-        &query.username, 
-        &query.namespace, 
-        &state.config.cluster_name,
-        &state.config.control_plane_address
-    ).await {
-        Ok(config) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(header::CONTENT_TYPE, "application/x-yaml".parse().unwrap());
-            let filename = format!("kubeconfig-{}-{}.yaml", query.username, query.namespace);
-            headers.insert(
-                header::CONTENT_DISPOSITION, 
-                format!("attachment; filename=\"{}\"", filename).parse().unwrap()
-            );
-            (headers, config).into_response()
-        },
+    // Other users' keys are reported as missing rather than forbidden.
+    match state.api_keys.list().await {
+        Ok(keys) if keys.iter().any(|k| k.id == query.id && (manages_all_api_keys(&claims) || k.owner == claims.sub)) => {}
+        Ok(_) => return status_response(start, StatusCode::NOT_FOUND, format!("API key {} not found", query.id)).into_response(),
+        Err(e) => return error_response(start, e).into_response(),
+    }
+
+    match state.api_keys.remove(&query.id).await {
+        Ok(_) => {
+            tracing::info!(operator = %claims.sub, key = %query.id, "API key revoked");
+            json_response(start, query.id).into_response()
+        }
         Err(e) => error_response(start, e).into_response(),
     }
 }
@@ -1,34 +1,89 @@
 use axum::{
-    Router, http::{HeaderValue, Method, header}, middleware, routing::{delete, get, post}
+    Router, http::{HeaderName, HeaderValue, Method, header}, middleware, routing::{delete, get, post}
 };
-use jsonwebtoken::Header;
-use tower_http::{cors::{AllowOrigin, Cors}, trace::TraceLayer};
+use tower_http::{cors::AllowOrigin, trace::TraceLayer};
 use tower_http::cors::CorsLayer;
 use crate::state::AppState;
+use crate::services::authenticator::Role;
 use crate::api::{handlers, auth};
 
 pub fn app_router(state: AppState) -> Router {
-    // Routes that require authentication
-    let protected_routes = Router::new()
+    // Read-only routes, open to every role
+    let viewer_routes = Router::new()
         .route("/getTemplates", get(handlers::get_templates))
+        .route("/getTemplate", get(handlers::get_template))
         .route("/getNamespaces", get(handlers::get_namespaces))
         .route("/getServiceAccounts", get(handlers::get_service_accounts))
         .route("/getFilteredRoleBindings", post(handlers::get_filtered_role_bindings))
         .route("/getFilteredClusterRoleBindings", post(handlers::get_filtered_cluster_role_bindings))
+        .route_layer(middleware::from_fn_with_state(Role::Viewer, auth::require_role));
+
+    // Service accounts, token secrets and namespaced bindings
+    let operator_routes = Router::new()
         .route("/createServiceAccount", post(handlers::create_service_account))
         .route("/createSecret", post(handlers::create_secret))
         .route("/createRoleBinding", post(handlers::create_role_binding))
-        .route("/createClusterRoleBinding", post(handlers::create_cluster_role_binding))
         .route("/generateK8sConfig", post(handlers::generate_k8s_config))
         .route("/generateK8sConfigDownloadFile", post(handlers::generate_k8s_config_download))
+        .route("/generateK8sCertConfig", post(handlers::generate_k8s_cert_config))
+        .route("/rotateToken", post(handlers::rotate_token))
+        .route("/getTokenRotations", get(handlers::get_token_rotations))
+        .route("/provisionUser", post(handlers::provision_user))
         .route("/deleteSecret", delete(handlers::delete_secret))
-        .route("/deleteServiceAccount", delete(handlers::delete_service_account))
         .route("/deleteRoleBinding", delete(handlers::delete_role_binding))
+        .route_layer(middleware::from_fn_with_state(Role::Operator, auth::require_role));
+
+    // Cluster-wide access and KanriGate operator management
+    let admin_routes = Router::new()
+        .route("/createClusterRoleBinding", post(handlers::create_cluster_role_binding))
         .route("/deleteClusterRoleBinding", delete(handlers::delete_cluster_role_binding))
+        .route("/deleteServiceAccount", delete(handlers::delete_service_account))
+        .route("/deleteUser", delete(handlers::delete_user))
+        .route("/createTemplate", post(handlers::create_template))
+        .route("/updateTemplate", post(handlers::update_template))
+        .route("/deleteTemplate", delete(handlers::delete_template))
+        .route("/getOperators", get(handlers::get_operators))
+        .route("/createOperator", post(handlers::create_operator))
+        .route("/disableOperator", post(handlers::disable_operator))
+        .route("/enableOperator", post(handlers::enable_operator))
+        .route("/setOperatorRole", post(handlers::set_operator_role))
+        .route("/setOperatorNamespaces", post(handlers::set_operator_namespaces))
+        .route("/deleteOperator", delete(handlers::delete_operator))
+        .route("/resetOperatorMfa", post(handlers::reset_operator_mfa))
+        .route("/getLoginLockouts", get(handlers::get_login_lockouts))
+        .route("/clearLoginLockout", post(handlers::clear_login_lockout))
+        .route_layer(middleware::from_fn_with_state(Role::Admin, auth::require_role));
+
+    // Routes that require authentication
+    let protected_routes = Router::new()
+        .route("/logout", post(handlers::logout))
+        .route("/changePassword", post(handlers::change_password))
+        .route("/mfa/enroll", post(handlers::mfa_enroll))
+        .route("/mfa/activate", post(handlers::mfa_activate))
+        .route("/mfa/disable", post(handlers::mfa_disable))
+        .route("/getApiKeys", get(handlers::get_api_keys))
+        .route("/createApiKey", post(handlers::create_api_key))
+        .route("/deleteApiKey", delete(handlers::delete_api_key))
+        .merge(viewer_routes)
+        .merge(operator_routes)
+        .merge(admin_routes)
         .route_layer(middleware::from_fn_with_state(state.clone(), auth::auth_middleware));
 
+    // Only listed origins may call the API from a browser, with cookies; an empty list allows none.
+    let origins: Vec<HeaderValue> = state.config().cors_allowed_origins.iter()
+        .filter(|origin| !origin.trim().is_empty())
+        .filter_map(|origin| match origin.trim_end_matches('/').parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!(origin = %origin, "Ignoring invalid CORS origin");
+                None
+            }
+        })
+        .collect();
+
     let cors = CorsLayer::new()
-        .allow_origin(AllowOrigin::predicate(|_, _| true))
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(true)
         .allow_methods([
             Method::GET,
             Method::POST,
@@ -37,11 +92,21 @@ pub fn app_router(state: AppState) -> Router {
         ])
         .allow_headers([
             header::CONTENT_TYPE,
-            header::AUTHORIZATION
+            header::AUTHORIZATION,
+            HeaderName::from_static(auth::CSRF_HEADER),
         ]);
 
     Router::new()
         .route("/apps/login", post(handlers::login))
+        .route("/apps/refresh", post(handlers::refresh))
+        .route("/apps/login/mfa", post(handlers::login_mfa))
+        .route("/apps/login/mfa/enroll", post(handlers::login_mfa_enroll))
+        .route("/apps/login/kubernetes", post(handlers::login_kubernetes))
+        .route("/.well-known/jwks.json", get(handlers::jwks))
+        .route("/health", get(handlers::health))
+        .route("/ready", get(handlers::ready))
+        .route("/apps/oidc/login", get(handlers::oidc_login))
+        .route("/apps/oidc/callback", get(handlers::oidc_callback))
         .nest("/apps", protected_routes)
         .layer(cors)
         .layer(
@@ -52,6 +117,8 @@ pub fn app_router(state: AppState) -> Router {
                         method = %request.method(),
                         uri = %request.uri(),
                         version = ?request.version(),
+                        operator = tracing::field::Empty,
+                        api_key = tracing::field::Empty,
                     )
                 })
                 .on_request(|request: &axum::http::Request<_>, _span: &tracing::Span| {
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use clap::{Parser, Subcommand, ValueEnum};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use rand::{thread_rng, RngCore};
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+// The backend's configuration rules, shared so `validate` cannot drift from them.
+#[allow(dead_code)]
+#[path = "../config.rs"]
+mod config;
+
+/// Administration tasks for a KanriGate installation.
+#[derive(Parser)]
+#[command(name = "kanrigate-admin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Hash a password with Argon2id, e.g. for APP_ADMIN_PASSWORD_HASH.
+    ///
+    /// The password is prompted for on a terminal, or read as the first line of stdin.
+    Hash,
+    /// Check a password against an Argon2 hash; exits with 1 when it does not match.
+    Verify {
+        /// The hash to check against.
+        hash: String,
+    },
+    /// Print a random secret suitable for APP_JWT_SECRET.
+    GenJwtSecret {
+        /// Number of random bytes before base64 encoding.
+        #[arg(long, default_value_t = 48)]
+        bytes: usize,
+    },
+    /// Write a `<kid>.key` private and `<kid>.pem` public key for APP_JWT_KEYS_DIR.
+    GenKeypair {
+        /// Key id, also the file name stem.
+        #[arg(long)]
+        kid: String,
+        #[arg(long, value_enum, default_value_t = KeyAlgorithm::Ed25519)]
+        algorithm: KeyAlgorithm,
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+    /// Load the configuration the way the backend does and report problems.
+    ///
+    /// Variables already set in the environment take precedence over the file, as they do
+    /// for the backend. Exits with 1 when the configuration is invalid, or when APP_ENV is
+    /// production and it has insecure settings.
+    Validate {
+        /// Dotenv file to read before the environment.
+        #[arg(long, default_value = ".env")]
+        env_file: PathBuf,
+    },
+    /// Print a Secret manifest for `kubectl apply -f -`.
+    ///
+    /// By default it holds a freshly generated APP_JWT_SECRET and the hash of a prompted
+    /// admin password. With --keys-dir it holds the key files of that directory instead,
+    /// for the Helm value jwtKeys.existingSecret.
+    Secret {
+        #[arg(long, default_value = "kanrigate-secrets")]
+        name: String,
+        #[arg(long)]
+        namespace: Option<String>,
+        /// Also generate APP_MFA_ENCRYPTION_KEY.
+        #[arg(long)]
+        mfa_key: bool,
+        #[arg(long, conflicts_with = "mfa_key")]
+        keys_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyAlgorithm {
+    /// EdDSA signatures over Ed25519.
+    Ed25519,
+    /// RS256 with a 2048-bit RSA key.
+    Rs256,
+}
+
+/// Reads a password without echoing it on a terminal, or the first line of piped stdin.
+fn read_password(prompt: &str) -> Result<String> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut line = String::new();
+        stdin.lock().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let hidden = Command::new("stty").arg("-echo").status().is_ok_and(|s| s.success());
+    let mut line = String::new();
+    let read = stdin.lock().read_line(&mut line);
+    if hidden {
+        let _ = Command::new("stty").arg("echo").status();
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Like `read_password`, but asks twice on a terminal so a typo is not silently hashed.
+fn read_new_password() -> Result<String> {
+    let password = read_password("New password: ")?;
+    if password.is_empty() {
+        bail!("Password must not be empty");
+    }
+    if std::io::stdin().is_terminal() && read_password("Repeat password: ")? != password {
+        bail!("Passwords do not match");
+    }
+    Ok(password)
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut thread_rng());
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+fn random_secret(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    thread_rng().fill_bytes(&mut buffer);
+    BASE64.encode(buffer)
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = BASE64.encode(der);
+    let lines: Vec<&str> = encoded.as_bytes()
+        .chunks(64)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base64 is ASCII"))
+        .collect();
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+/// PKCS#8 private and SPKI public key, both PEM-encoded.
+fn generate_keypair(algorithm: KeyAlgorithm) -> Result<(String, String)> {
+    match algorithm {
+        KeyAlgorithm::Ed25519 => {
+            use ring::signature::{Ed25519KeyPair, KeyPair};
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .map_err(|_| anyhow!("Failed to generate an Ed25519 key"))?;
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                .map_err(|_| anyhow!("Failed to parse the generated Ed25519 key"))?;
+            // SubjectPublicKeyInfo for id-Ed25519 (RFC 8410) is a fixed prefix plus the raw key.
+            let mut spki = vec![0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+            spki.extend_from_slice(pair.public_key().as_ref());
+            Ok((pem("PRIVATE KEY", pkcs8.as_ref()), pem("PUBLIC KEY", &spki)))
+        }
+        KeyAlgorithm::Rs256 => {
+            use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+            let private = rsa::RsaPrivateKey::new(&mut thread_rng(), 2048)?;
+            let private_pem = private.to_pkcs8_pem(LineEnding::LF)?.to_string();
+            let public_pem = private.to_public_key().to_public_key_pem(LineEnding::LF)?;
+            Ok((private_pem, public_pem))
+        }
+    }
+}
+
+fn write_new_file(path: &Path, contents: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).with_context(|| format!("Creating {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn validate(env_file: &Path) -> Result<bool> {
+    match dotenvy::from_path(env_file) {
+        Ok(()) => eprintln!("Loaded {}", env_file.display()),
+        Err(e) if e.not_found() => eprintln!("{} not found; checking the environment only", env_file.display()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", env_file.display())),
+    }
+
+    let config = match config::BaseConfig::new() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("invalid: {}", e);
+            return Ok(false);
+        }
+    };
+    let issues = config.security_issues();
+    for issue in &issues {
+        println!("insecure: {}", issue);
+    }
+    let production = std::env::var("APP_ENV").is_ok_and(|env| env == "production");
+    if issues.is_empty() {
+        println!("ok");
+    } else if production {
+        println!("The backend will refuse to start in production with these settings");
+    }
+    Ok(issues.is_empty() || !production)
+}
+
+fn secret_manifest(name: String, namespace: Option<String>, mfa_key: bool, keys_dir: Option<PathBuf>) -> Result<String> {
+    let mut data = BTreeMap::new();
+    match keys_dir {
+        Some(dir) => {
+            for entry in std::fs::read_dir(&dir).with_context(|| format!("Reading {}", dir.display()))? {
+                let path = entry?.path();
+                if !matches!(path.extension().and_then(|e| e.to_str()), Some("pem" | "key")) {
+                    continue;
+                }
+                let key = path.file_name().and_then(|f| f.to_str()).unwrap_or_default().to_string();
+                data.insert(key, ByteString(std::fs::read(&path)?));
+            }
+            if data.is_empty() {
+                bail!("No .pem or .key files in {}", dir.display());
+            }
+        }
+        None => {
+            let hash = hash_password(&read_new_password()?)?;
+            data.insert("APP_ADMIN_PASSWORD_HASH".to_string(), ByteString(hash.into_bytes()));
+            data.insert("APP_JWT_SECRET".to_string(), ByteString(random_secret(48).into_bytes()));
+            if mfa_key {
+                data.insert("APP_MFA_ENCRYPTION_KEY".to_string(), ByteString(random_secret(32).into_bytes()));
+            }
+        }
+    }
+
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(name),
+            namespace,
+            ..Default::default()
+        },
+        type_: Some("Opaque".to_string()),
+        data: Some(data),
+        ..Default::default()
+    };
+    Ok(serde_yaml::to_string(&secret)?)
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    match cli.command {
+        Commands::Hash => {
+            println!("{}", hash_password(&read_new_password()?)?);
+        }
+        Commands::Verify { hash } => {
+            let parsed = PasswordHash::new(&hash).map_err(|e| anyhow!("Invalid password hash: {}", e))?;
+            let password = read_password("Password: ")?;
+            let matches = Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok();
+            println!("{}", if matches { "match" } else { "no match" });
+            return Ok(matches);
+        }
+        Commands::GenJwtSecret { bytes } => {
+            if bytes < 32 {
+                bail!("Use at least 32 bytes");
+            }
+            println!("{}", random_secret(bytes));
+        }
+        Commands::GenKeypair { kid, algorithm, out_dir } => {
+            let (private_pem, public_pem) = generate_keypair(algorithm)?;
+            let private_path = out_dir.join(format!("{}.key", kid));
+            let public_path = out_dir.join(format!("{}.pem", kid));
+            write_new_file(&private_path, &private_pem)?;
+            write_new_file(&public_path, &public_pem)?;
+            eprintln!("Wrote {} and {}", private_path.display(), public_path.display());
+        }
+        Commands::Validate { env_file } => return validate(&env_file),
+        Commands::Secret { name, namespace, mfa_key, keys_dir } => {
+            print!("{}", secret_manifest(name, namespace, mfa_key, keys_dir)?);
+        }
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
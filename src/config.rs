@@ -2,29 +2,47 @@ use config::Config;
 use serde::Deserialize;
 use validator::{Validate, ValidationError};
 use reqwest::blocking::Client;
+use std::collections::{BTreeMap, HashSet};
 use std::time::Duration;
 use regex::Regex;
 use lazy_static::lazy_static;
 
-pub struct Constants;
-
 pub mod rbac {
-    pub const API_VERSION: &str = "rbac.authorization.k8s.io/v1";
     pub const API_GROUP: &str = "rbac.authorization.k8s.io";
 }
 
 pub mod sa {
     pub const ANNOTATIONS_NAME: &str = "kubernetes.io/service-account.name";
     pub const TYPE_TOKEN: &str = "kubernetes.io/service-account-token";
+    // Records which user a KanriGate-created CertificateSigningRequest was for.
+    pub const ANNOTATIONS_USER: &str = "kanrigate.io/user";
+}
+
+// Labels on every object KanriGate creates, so lookups can use label selectors.
+pub mod labels {
+    pub const MANAGED_BY: &str = "app.kubernetes.io/managed-by";
+    pub const MANAGED_BY_VALUE: &str = "kanrigate";
+    // The user an object belongs to; names that are not valid label values are hashed.
+    pub const USER: &str = "kanrigate.io/user";
 }
 
-pub mod permissions {
-    pub const TEMPLATES: [&str; 3] = ["operation", "monitoring", "developer"];
-    pub const CLUSTER: [&str; 3] = ["admin", "read-only", "none"];
+// Permission templates are ClusterRoles named `template-<scope>-resources___<name>`.
+pub mod templates {
+    pub const NAMESPACED_PREFIX: &str = "template-namespaced-resources___";
+    pub const CLUSTER_PREFIX: &str = "template-cluster-resources___";
+    pub const ANNOTATIONS_DESCRIPTION: &str = "kanrigate.io/description";
 }
 
 lazy_static! {
     static ref RE_CLUSTER_NAME: Regex = Regex::new(r"^[a-zA-Z0-9-@]+$").unwrap();
+    // Operator names double as data keys in the operators Secret.
+    pub static ref RE_OPERATOR_NAME: Regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9._-]{0,62}$").unwrap();
+    // Client certificate users; no ':' so the reserved `system:` names cannot be requested.
+    pub static ref RE_CERT_USERNAME: Regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9._@-]{0,252}$").unwrap();
+    // Template names end up in ClusterRole and binding names.
+    pub static ref RE_TEMPLATE_NAME: Regex = Regex::new(r"^[a-z0-9]([-a-z0-9]{0,61}[a-z0-9])?$").unwrap();
+    // ServiceAccount names are DNS subdomains.
+    pub static ref RE_SERVICE_ACCOUNT_NAME: Regex = Regex::new(r"^[a-z0-9]([-a-z0-9.]{0,251}[a-z0-9])?$").unwrap();
 }
 
 fn validate_cluster_name(name: &str) -> Result<(), ValidationError> {
@@ -36,6 +54,24 @@ fn validate_cluster_name(name: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+fn validate_same_site(value: &str) -> Result<(), ValidationError> {
+    if !matches!(value, "Strict" | "Lax" | "None") {
+        let mut error = ValidationError::new("invalid_same_site");
+        error.message = Some(std::borrow::Cow::from("Session cookie SameSite must be Strict, Lax or None"));
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_kubeconfig_token_type(value: &str) -> Result<(), ValidationError> {
+    if !matches!(value, "legacy" | "bound") {
+        let mut error = ValidationError::new("invalid_kubeconfig_token_type");
+        error.message = Some(std::borrow::Cow::from("Kubeconfig token type must be legacy or bound"));
+        return Err(error);
+    }
+    Ok(())
+}
+
 fn validate_control_plane_address(url: &str) -> Result<(), ValidationError> {
     if !url.starts_with("http://") && !url.starts_with("https://") {
         let mut error = ValidationError::new("invalid_protocol");
@@ -72,6 +108,14 @@ fn validate_control_plane_address(url: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+pub const DEFAULT_JWT_SECRET: &str = "replace-with-a-secure-secret-key";
+// Hash of the password 'admin'
+pub const DEFAULT_ADMIN_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$Z3YxeXJ3emx6cWZ6Z3YxeXJ3emx6cWZ6$R0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0";
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+const MIN_JWT_SECRET_DISTINCT_CHARS: usize = 10;
+/// Keys that `/apps/changePassword` and friends may persist in `settings_secret`.
+pub const RELOADABLE_KEYS: [&str; 1] = ["admin_password_hash"];
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct BaseConfig {
     #[validate(range(min=1024, max=65535, message="Port must be between 1024 and 65535"))]
@@ -86,24 +130,193 @@ pub struct BaseConfig {
     pub admin_username: String,
     pub admin_password_hash: String,
     pub jwt_secret: String,
+    // Directory of `<kid>.pem` public and `<kid>.key` private keys; HS256 with `jwt_secret` when unset
+    pub jwt_keys_dir: Option<String>,
+    pub jwt_signing_kid: Option<String>,
+
+    pub operators_secret: String,
+
+    // Tokens in generated kubeconfigs: "legacy" Secret tokens that never expire, or "bound" TokenRequest tokens
+    #[validate(custom(function="validate_kubeconfig_token_type"))]
+    pub kubeconfig_token_type: String,
+    pub kubeconfig_token_ttl_seconds: i64,
+    pub kubeconfig_token_max_ttl_seconds: i64,
+    pub kubeconfig_token_audiences: Vec<String>,
+    // Client certificates for `User` subjects, issued through the kube-apiserver-client signer
+    pub client_cert_ttl_seconds: i64,
+    pub client_cert_max_ttl_seconds: i64,
+    // Token Secret rotation; the background job only runs when `token_rotation_max_age_days` is set
+    pub token_rotations_secret: String,
+    pub token_rotation_max_age_days: Option<i64>,
+    pub token_rotation_interval_minutes: u64,
+    // Runtime changes such as a new admin password, applied on top of the environment without a restart
+    pub settings_secret: String,
+    pub password_min_length: usize,
+    // One-off migrations, such as labelling objects created by older versions, recorded once done
+    pub migrations_secret: String,
+
+    // Session lifetimes; access tokens are short-lived and renewed with a rotating refresh token
+    pub access_token_ttl_minutes: i64,
+    pub refresh_token_ttl_hours: i64,
+    pub revocation_secret: String,
+
+    // TOTP second factor for password logins
+    pub mfa_required: bool,
+    pub mfa_secret: String,
+    pub mfa_encryption_key: Option<String>,
+
+    // Brute-force protection for `/apps/login`
+    pub login_max_attempts_per_user: u32,
+    pub login_max_attempts_per_ip: u32,
+    pub login_lockout_base_seconds: i64,
+    pub login_lockout_max_seconds: i64,
+    // Peers whose X-Forwarded-For header is trusted, e.g. the bundled UI proxy
+    pub trusted_proxies: Vec<String>,
+
+    // Browser sessions held in backend-set cookies, and origins allowed to call the API cross-site
+    pub session_cookie_secure: bool,
+    #[validate(custom(function="validate_same_site"))]
+    pub session_cookie_same_site: String,
+    pub cors_allowed_origins: Vec<String>,
+
+    // Personal API keys for automation, sent as `Authorization: Bearer kg_...`
+    pub api_keys_secret: String,
+    pub api_key_default_ttl_days: i64,
+    pub api_key_max_ttl_days: i64,
+
+    // Username/password backend for `/apps/login`: "local" or "ldap"
+    pub auth_backend: String,
+
+    // OpenID Connect single sign-on; disabled unless `oidc_issuer` is set
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: Option<String>,
+    pub oidc_client_secret: Option<String>,
+    pub oidc_redirect_url: Option<String>,
+    pub oidc_scopes: Vec<String>,
+    pub oidc_username_claim: String,
+    pub oidc_groups_claim: String,
+    /// Accepted ID token algorithms; empty accepts the asymmetric ones the provider advertises.
+    pub oidc_signing_algorithms: Vec<String>,
+    pub oidc_admin_groups: Vec<String>,
+    pub oidc_operator_groups: Vec<String>,
+    pub oidc_viewer_groups: Vec<String>,
+    pub oidc_allowed_groups: Vec<String>,
+    pub oidc_post_login_redirect: String,
+
+    // Login with a Kubernetes bearer token; the role is whichever verb cluster RBAC allows on the resource
+    pub kube_login_enabled: bool,
+    pub kube_login_audiences: Vec<String>,
+    pub kube_login_api_group: String,
+    pub kube_login_resource: String,
+    pub kube_login_admin_verb: String,
+    pub kube_login_operator_verb: String,
+    pub kube_login_viewer_verb: String,
+
+    // LDAP / Active Directory bind authentication, used when `auth_backend` is "ldap"
+    pub ldap_url: Option<String>,
+    pub ldap_starttls: bool,
+    pub ldap_tls_insecure: bool,
+    pub ldap_bind_dn_template: Option<String>,
+    pub ldap_base_dn: Option<String>,
+    pub ldap_user_filter: String,
+    pub ldap_group_base_dn: Option<String>,
+    pub ldap_group_filter: String,
+    pub ldap_admin_groups: Vec<String>,
+    pub ldap_operator_groups: Vec<String>,
+    pub ldap_viewer_groups: Vec<String>,
 }
 
 impl BaseConfig {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load(&BTreeMap::new())
+    }
+
+    /// Loads the configuration with `overrides` (from `settings_secret`) taking precedence
+    /// over the environment.
+    pub fn load(overrides: &BTreeMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
         dotenvy::dotenv().ok();
 
-        let s = Config::builder()
+        let mut builder = Config::builder()
             .set_default("port", 3232)?
             .set_default("cluster_name", "kubernetes-admin@kubernetes")?
             .set_default("control_plane_address", "https://172.17.0.3:6443")?
             .set_default("admin_username", "admin")?
-            .set_default("admin_password_hash", "$argon2id$v=19$m=19456,t=2,p=1$Z3YxeXJ3emx6cWZ6Z3YxeXJ3emx6cWZ6$R0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0U0p0")? // default 'admin' password hash
-            .set_default("jwt_secret", "replace-with-a-secure-secret-key")?
+            .set_default("admin_password_hash", DEFAULT_ADMIN_PASSWORD_HASH)?
+            .set_default("jwt_secret", DEFAULT_JWT_SECRET)?
+            .set_default("operators_secret", "kanrigate-operators")?
+            .set_default("kubeconfig_token_type", "legacy")?
+            .set_default("kubeconfig_token_ttl_seconds", 28800)?
+            .set_default("kubeconfig_token_max_ttl_seconds", 604800)?
+            .set_default("kubeconfig_token_audiences", Vec::<String>::new())?
+            .set_default("client_cert_ttl_seconds", 86400)?
+            .set_default("client_cert_max_ttl_seconds", 2592000)?
+            .set_default("token_rotations_secret", "kanrigate-token-rotations")?
+            .set_default("token_rotation_interval_minutes", 60)?
+            .set_default("settings_secret", "kanrigate-settings")?
+            .set_default("password_min_length", 12)?
+            .set_default("migrations_secret", "kanrigate-migrations")?
+            .set_default("access_token_ttl_minutes", 15)?
+            .set_default("refresh_token_ttl_hours", 24)?
+            .set_default("revocation_secret", "kanrigate-revocations")?
+            .set_default("mfa_required", false)?
+            .set_default("mfa_secret", "kanrigate-mfa")?
+            .set_default("login_max_attempts_per_user", 5)?
+            .set_default("login_max_attempts_per_ip", 20)?
+            .set_default("login_lockout_base_seconds", 30)?
+            .set_default("login_lockout_max_seconds", 900)?
+            .set_default("trusted_proxies", vec!["127.0.0.1", "::1"])?
+            .set_default("session_cookie_secure", true)?
+            .set_default("session_cookie_same_site", "Lax")?
+            .set_default("cors_allowed_origins", Vec::<String>::new())?
+            .set_default("api_keys_secret", "kanrigate-api-keys")?
+            .set_default("api_key_default_ttl_days", 90)?
+            .set_default("api_key_max_ttl_days", 365)?
+            .set_default("auth_backend", "local")?
+            .set_default("oidc_scopes", vec!["openid", "profile", "email"])?
+            .set_default("oidc_username_claim", "preferred_username")?
+            .set_default("oidc_groups_claim", "groups")?
+            .set_default("oidc_signing_algorithms", Vec::<String>::new())?
+            .set_default("oidc_admin_groups", Vec::<String>::new())?
+            .set_default("oidc_operator_groups", Vec::<String>::new())?
+            .set_default("oidc_viewer_groups", Vec::<String>::new())?
+            .set_default("oidc_allowed_groups", Vec::<String>::new())?
+            .set_default("oidc_post_login_redirect", "/")?
+            .set_default("kube_login_enabled", false)?
+            .set_default("kube_login_audiences", Vec::<String>::new())?
+            .set_default("kube_login_api_group", "kanrigate.io")?
+            .set_default("kube_login_resource", "kanrigate")?
+            .set_default("kube_login_admin_verb", "admin")?
+            .set_default("kube_login_operator_verb", "operate")?
+            .set_default("kube_login_viewer_verb", "view")?
+            .set_default("ldap_starttls", false)?
+            .set_default("ldap_tls_insecure", false)?
+            .set_default("ldap_user_filter", "(uid={username})")?
+            .set_default("ldap_group_filter", "(|(member={user_dn})(uniqueMember={user_dn}))")?
+            .set_default("ldap_admin_groups", Vec::<String>::new())?
+            .set_default("ldap_operator_groups", Vec::<String>::new())?
+            .set_default("ldap_viewer_groups", Vec::<String>::new())?
             .add_source(
                 config::Environment::with_prefix("APP")
                     .try_parsing(true)
-            )
-            .build()?;
+                    .list_separator(",")
+                    .with_list_parse_key("trusted_proxies")
+                    .with_list_parse_key("cors_allowed_origins")
+                    .with_list_parse_key("kubeconfig_token_audiences")
+                    .with_list_parse_key("oidc_scopes")
+                    .with_list_parse_key("oidc_signing_algorithms")
+                    .with_list_parse_key("oidc_admin_groups")
+                    .with_list_parse_key("oidc_operator_groups")
+                    .with_list_parse_key("oidc_viewer_groups")
+                    .with_list_parse_key("oidc_allowed_groups")
+                    .with_list_parse_key("kube_login_audiences")
+                    .with_list_parse_key("ldap_admin_groups")
+                    .with_list_parse_key("ldap_operator_groups")
+                    .with_list_parse_key("ldap_viewer_groups")
+            );
+        for (key, value) in overrides {
+            builder = builder.set_override(key.as_str(), value.as_str())?;
+        }
+        let s = builder.build()?;
 
         let config: BaseConfig = s.try_deserialize()?;
         
@@ -111,4 +324,69 @@ impl BaseConfig {
 
         Ok(config)
     }
-}
\ No newline at end of file
+
+    /// Settings that must not reach production: the shipped defaults and a guessable JWT secret.
+    pub fn security_issues(&self) -> Vec<String> {
+        jwt_secret_issue(&self.jwt_secret).into_iter()
+            .chain(admin_password_hash_issue(&self.admin_password_hash))
+            .collect()
+    }
+}
+
+/// Why `hash` must not guard the bootstrap admin, if it must not.
+fn admin_password_hash_issue(hash: &str) -> Option<String> {
+    if hash == DEFAULT_ADMIN_PASSWORD_HASH {
+        return Some("admin_password_hash is the shipped default for the password 'admin'".to_string());
+    }
+    if argon2::PasswordHash::new(hash).is_err() {
+        return Some("admin_password_hash is empty or not a valid password hash, so the admin account cannot log in".to_string());
+    }
+    None
+}
+
+/// Why `secret` must not sign production tokens, if it must not.
+fn jwt_secret_issue(secret: &str) -> Option<String> {
+    if secret == DEFAULT_JWT_SECRET {
+        return Some("jwt_secret is the shipped default".to_string());
+    }
+    let distinct = secret.chars().collect::<HashSet<_>>().len();
+    if secret.len() < MIN_JWT_SECRET_LENGTH || distinct < MIN_JWT_SECRET_DISTINCT_CHARS {
+        return Some(format!(
+            "jwt_secret is weak; use at least {} characters of random data (e.g. `openssl rand -base64 48`)",
+            MIN_JWT_SECRET_LENGTH,
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_default_secret_is_reported_as_such() {
+        assert_eq!(jwt_secret_issue(DEFAULT_JWT_SECRET).as_deref(), Some("jwt_secret is the shipped default"));
+    }
+
+    #[test]
+    fn short_or_repetitive_secrets_are_weak() {
+        assert!(jwt_secret_issue("Zx8!kQ2#vB7$").unwrap().contains("weak"));
+        assert!(jwt_secret_issue(&"ab".repeat(32)).unwrap().contains("weak"));
+        assert!(jwt_secret_issue(&"abcdefghi".repeat(4)).unwrap().contains("weak"));
+    }
+
+    #[test]
+    fn empty_or_malformed_admin_hash_is_reported() {
+        assert!(admin_password_hash_issue("").unwrap().contains("not a valid"));
+        assert!(admin_password_hash_issue("admin").unwrap().contains("not a valid"));
+        assert!(admin_password_hash_issue(DEFAULT_ADMIN_PASSWORD_HASH).unwrap().contains("shipped default"));
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$Jd0ZQ8hXx0W7q0mW6cH7vJ3fRrYx1kP9cB2nL4sT5uE";
+        assert_eq!(admin_password_hash_issue(hash), None);
+    }
+
+    #[test]
+    fn random_secret_passes() {
+        assert_eq!(jwt_secret_issue("3q2+7w8hYkL0pZ9xV5nB1mC4rT6sU/aE"), None);
+        assert_eq!(jwt_secret_issue(&"abcdefghij".repeat(4)), None);
+    }
+}
@@ -17,12 +17,29 @@ use utoipa_swagger_ui::SwaggerUi;
 struct KubeConfigArgs {
     #[arg(short, long, env = "KUBECONFIG")]
     kube_config: Option<String>,
+    /// Start in production even with the shipped default secrets or a weak JWT secret.
+    #[arg(long, env = "APP_INSECURE_ALLOW_DEFAULTS")]
+    insecure_allow_defaults: bool,
 }
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         api::handlers::login,
+        api::handlers::refresh,
+        api::handlers::logout,
+        api::handlers::change_password,
+        api::handlers::login_mfa,
+        api::handlers::login_mfa_enroll,
+        api::handlers::mfa_enroll,
+        api::handlers::mfa_activate,
+        api::handlers::mfa_disable,
+        api::handlers::login_kubernetes,
+        api::handlers::oidc_login,
+        api::handlers::oidc_callback,
+        api::handlers::jwks,
+        api::handlers::health,
+        api::handlers::ready,
         api::handlers::get_templates,
         api::handlers::get_namespaces,
         api::handlers::get_service_accounts,
@@ -38,18 +55,105 @@ struct KubeConfigArgs {
         api::handlers::get_filtered_cluster_role_bindings,
         api::handlers::generate_k8s_config,
         api::handlers::generate_k8s_config_download,
+        api::handlers::generate_k8s_cert_config,
+        api::handlers::rotate_token,
+        api::handlers::get_token_rotations,
+        api::handlers::provision_user,
+        api::handlers::delete_user,
+        api::handlers::get_template,
+        api::handlers::create_template,
+        api::handlers::update_template,
+        api::handlers::delete_template,
+        api::handlers::get_operators,
+        api::handlers::create_operator,
+        api::handlers::disable_operator,
+        api::handlers::enable_operator,
+        api::handlers::set_operator_role,
+        api::handlers::set_operator_namespaces,
+        api::handlers::delete_operator,
+        api::handlers::reset_operator_mfa,
+        api::handlers::get_login_lockouts,
+        api::handlers::clear_login_lockout,
+        api::handlers::get_api_keys,
+        api::handlers::create_api_key,
+        api::handlers::delete_api_key,
     ),
     components(
         schemas(
             models::MetaData,
             models::UsernameQuery,
+            models::SubjectQuery,
             models::RoleBindingQuery,
             models::ClusterRoleBindingQuery,
             models::K8sConfigQuery,
+            models::KubeconfigTokenType,
+            models::K8sCertConfigRequest,
+            models::RotateTokenQuery,
+            services::kubernetes::SubjectKind,
             models::LoginRequest,
+            models::RefreshRequest,
+            models::KubeLoginRequest,
+            models::ChangePasswordRequest,
+            models::MfaChallenge,
+            models::MfaLoginRequest,
+            models::MfaTokenRequest,
+            models::MfaCodeRequest,
+            models::MfaSetupInfo,
+            models::MfaRecoveryCodes,
+            models::OidcCallbackQuery,
             models::AuthBody,
             models::KanriGateRespString,
             models::KanriGateRespVecString,
+            models::CreateOperatorRequest,
+            models::OperatorInfo,
+            models::OperatorRoleQuery,
+            models::OperatorNamespacesRequest,
+            services::authenticator::Role,
+            models::KanriGateRespOperatorInfo,
+            models::KanriGateRespVecOperatorInfo,
+            models::ClearLockoutQuery,
+            models::LockoutInfo,
+            models::KanriGateRespVecLockoutInfo,
+            models::CreateApiKeyRequest,
+            models::ApiKeyQuery,
+            models::ApiKeyInfo,
+            models::ApiKeyCreated,
+            models::KanriGateRespVecApiKeyInfo,
+            models::KanriGateRespApiKeyCreated,
+            models::SecurityPosture,
+            models::HealthInfo,
+            models::KanriGateRespHealthInfo,
+            models::TokenRotationInfo,
+            models::TokenRotated,
+            models::KanriGateRespTokenRotated,
+            models::KanriGateRespVecTokenRotationInfo,
+            models::ProvisionUserRequest,
+            models::UserProvisioned,
+            models::KanriGateRespUserProvisioned,
+            models::DeleteUserQuery,
+            services::kubernetes::UserDeletion,
+            services::kubernetes::DeletedObject,
+            services::kubernetes::DeletionOutcome,
+            models::KanriGateRespUserDeletion,
+            services::kubernetes::Applied,
+            services::kubernetes::ApplyOutcome,
+            models::KanriGateRespApplied,
+            services::cache::CacheStatus,
+            models::KanriGateRespCacheStatus,
+            services::kubernetes::TemplateInfo,
+            services::kubernetes::TemplateScope,
+            models::KanriGateRespVecTemplateInfo,
+            models::TemplateQuery,
+            models::TemplateRequest,
+            models::UpdateTemplateQuery,
+            models::DeleteTemplateQuery,
+            services::templates::TemplateRule,
+            services::templates::TemplateDetail,
+            services::templates::BlastRadius,
+            services::templates::TemplateChanged,
+            models::KanriGateRespTemplateDetail,
+            models::KanriGateRespTemplateChanged,
+            models::KanriGateRespBlastRadius,
         )
     ),
     tags(
@@ -83,11 +187,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async {
-        run(config, args.kube_config).await
+        run(config, args.kube_config, env, args.insecure_allow_defaults).await
     })
 }
 
-async fn run(config: Arc<BaseConfig>, kube_config_path: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+async fn run(
+    config: Arc<BaseConfig>,
+    kube_config_path: Option<String>,
+    env: String,
+    insecure_allow_defaults: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Initializing Kubernetes client...");
     
     let k8s_config = if let Some(path) = kube_config_path {
@@ -99,9 +208,93 @@ async fn run(config: Arc<BaseConfig>, kube_config_path: Option<String>) -> Resul
     
     let client = Client::try_from(k8s_config)?;
 
+    // Settings changed at runtime (e.g. the admin password) take precedence over the environment.
+    let settings = Arc::new(services::settings::SettingsStore::new(client.clone(), config));
+    if let Err(e) = settings.sync().await {
+        tracing::warn!(error = %e, "Failed to load persisted settings; using the environment only");
+    }
+    settings.spawn_watch();
+    let config = settings.current();
+
+    let issues = config.security_issues();
+    for issue in &issues {
+        tracing::warn!(issue = %issue, "Insecure configuration");
+    }
+    if env == "production" && !issues.is_empty() {
+        if !insecure_allow_defaults {
+            tracing::error!("Refusing to start in production with insecure settings; fix them or pass --insecure-allow-defaults");
+            return Err("insecure configuration in production".into());
+        }
+        tracing::warn!("--insecure-allow-defaults is set; starting in production despite insecure settings");
+    }
+
+    // Lookups rely on labels, so objects from older versions are labelled before serving.
+    if let Err(e) = services::migrations::run(client.clone(), &config).await {
+        tracing::warn!(error = %e, "Migrations failed; they are retried on the next start");
+    }
+
+    let jwt = Arc::new(services::jwt::JwtKeys::from_config(&config)?);
+    let posture = models::SecurityPosture {
+        environment: env,
+        secure: issues.is_empty(),
+        insecure_defaults_allowed: insecure_allow_defaults && !issues.is_empty(),
+        issues,
+        token_signing: format!("{:?}", jwt.algorithm()),
+    };
+
+    let authenticator = services::authenticator::from_config(client.clone(), &settings)?;
+    tracing::info!(backend = %config.auth_backend, "Password authentication backend configured");
+
+    let oidc = services::oidc::OidcClient::from_config(&config)?.map(Arc::new);
+    if oidc.is_some() {
+        tracing::info!("OIDC single sign-on enabled");
+    }
+
+    let kube_login = services::token_review::KubeTokenLogin::from_config(client.clone(), &config).map(Arc::new);
+    if kube_login.is_some() {
+        tracing::info!(resource = %config.kube_login_resource, group = %config.kube_login_api_group, "Kubernetes token login enabled");
+    }
+
+    let revocations = Arc::new(services::revocation::RevocationList::new(client.clone(), &config.revocation_secret));
+    if let Err(e) = revocations.sync().await {
+        tracing::warn!(error = %e, "Failed to load the token revocation list; relying on the watch to catch up");
+    }
+    revocations.clone().spawn_watch();
+
+    let login_guard = Arc::new(services::login_guard::LoginGuard::from_config(&config));
+    let mfa = Arc::new(services::mfa::MfaStore::new(client.clone(), &config));
+    if config.mfa_encryption_key.is_none() {
+        tracing::warn!("mfa_encryption_key is not set; MFA secrets are encrypted with a key derived from jwt_secret");
+    }
+
+    let api_keys = Arc::new(services::api_keys::ApiKeyStore::new(client.clone(), &config.api_keys_secret));
+    if let Err(e) = api_keys.sync().await {
+        tracing::warn!(error = %e, "Failed to load API keys; relying on the watch to catch up");
+    }
+    api_keys.spawn_watch();
+
+    if let Some(days) = config.token_rotation_max_age_days {
+        let rotator = Arc::new(services::token_rotation::TokenRotator::new(client.clone(), &config.token_rotations_secret));
+        let interval = std::time::Duration::from_secs(config.token_rotation_interval_minutes.max(1) * 60);
+        rotator.spawn_schedule(chrono::Duration::days(days), interval);
+        tracing::info!(max_age_days = days, interval_minutes = config.token_rotation_interval_minutes, "Scheduled token rotation enabled");
+    }
+
+    let cache = services::cache::KubeCache::spawn(client.clone());
+
     let state = AppState {
         client,
-        config: config.clone(),
+        cache,
+        settings,
+        authenticator,
+        jwt,
+        oidc,
+        kube_login,
+        revocations,
+        login_guard,
+        mfa,
+        api_keys,
+        posture: Arc::new(posture),
     };
 
     let app = api::routes::app_router(state)
@@ -112,7 +305,8 @@ async fn run(config: Arc<BaseConfig>, kube_config_path: Option<String>) -> Resul
     tracing::info!("Starting KanriGate API server on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    // Peer addresses feed the per-IP login limits.
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await?;
 
     Ok(())
 }
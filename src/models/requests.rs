@@ -1,28 +1,164 @@
 use serde::{Deserialize, Serialize};
 use utoipa::{ToSchema, IntoParams};
+use validator::Validate;
+use std::collections::BTreeMap;
+use crate::config::{RE_CERT_USERNAME, RE_OPERATOR_NAME, RE_SERVICE_ACCOUNT_NAME, RE_TEMPLATE_NAME};
+use crate::services::{authenticator::Role, kubernetes::{SubjectKind, TemplateScope}, templates::TemplateRule};
 
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct UsernameQuery {
     pub username: String,
 }
 
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct SubjectQuery {
+    pub username: String,
+    /// `ServiceAccount` (default) or `User`.
+    #[serde(default)]
+    pub kind: SubjectKind,
+}
+
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct RoleBindingQuery {
     pub username: String,
     pub namespace: String,
     pub permission: String,
+    /// `ServiceAccount` (default) or `User`.
+    #[serde(default)]
+    pub kind: SubjectKind,
 }
 
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct ClusterRoleBindingQuery {
     pub username: String,
     pub permission: String,
+    /// `ServiceAccount` (default) or `User`.
+    #[serde(default)]
+    pub kind: SubjectKind,
+}
+
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KubeconfigTokenType {
+    /// Long-lived token from the ServiceAccount's token Secret.
+    Legacy,
+    /// Expiring token from the TokenRequest API.
+    Bound,
 }
 
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct K8sConfigQuery {
     pub username: String,
     pub namespace: String,
+    /// Defaults to `kubeconfig_token_type`.
+    #[serde(default)]
+    pub token_type: Option<KubeconfigTokenType>,
+    /// Lifetime of a bound token; defaults to `kubeconfig_token_ttl_seconds`.
+    #[serde(default)]
+    pub expiration_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct DeleteUserQuery {
+    pub username: String,
+    /// `ServiceAccount` (default) or `User`.
+    #[serde(default)]
+    pub kind: SubjectKind,
+    /// Report what would be deleted without deleting anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct RotateTokenQuery {
+    pub username: String,
+    /// Namespace of the returned kubeconfig's context.
+    pub namespace: String,
+    /// Token of the returned kubeconfig; defaults to `kubeconfig_token_type`.
+    #[serde(default)]
+    pub token_type: Option<KubeconfigTokenType>,
+    /// Lifetime of a bound token; defaults to `kubeconfig_token_ttl_seconds`.
+    #[serde(default)]
+    pub expiration_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct K8sCertConfigRequest {
+    /// Common name of the certificate, i.e. the Kubernetes `User` subject.
+    #[validate(regex(path = "RE_CERT_USERNAME", message = "User name must be 1-253 characters of letters, digits, '.', '_', '@' or '-'"))]
+    pub username: String,
+    pub namespace: String,
+    /// Organizations of the certificate, which Kubernetes treats as groups.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Defaults to `client_cert_ttl_seconds`.
+    #[serde(default)]
+    pub expiration_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ProvisionUserRequest {
+    /// Name of the ServiceAccount to create; it must not exist yet.
+    #[validate(regex(path = "RE_SERVICE_ACCOUNT_NAME", message = "User name must be a lowercase DNS subdomain of at most 253 characters"))]
+    pub username: String,
+    /// Namespace of the returned kubeconfig's context.
+    pub namespace: String,
+    /// Templates to bind per namespace, e.g. `{ "dev": ["developer", "monitoring"] }`.
+    #[serde(default)]
+    pub role_bindings: BTreeMap<String, Vec<String>>,
+    /// `admin` or `read-only`; `none` or absent for no cluster-wide access.
+    #[serde(default)]
+    pub cluster_permission: Option<String>,
+    /// Defaults to `kubeconfig_token_type`.
+    #[serde(default)]
+    pub token_type: Option<KubeconfigTokenType>,
+    /// Lifetime of a bound token; defaults to `kubeconfig_token_ttl_seconds`.
+    #[serde(default)]
+    pub expiration_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct TemplateQuery {
+    pub name: String,
+    pub scope: TemplateScope,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct DeleteTemplateQuery {
+    pub name: String,
+    pub scope: TemplateScope,
+    /// Delete even when bindings still reference the template.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct UpdateTemplateQuery {
+    /// Report the change and its blast radius without applying it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct TemplateRequest {
+    /// The `permission` the binding endpoints will take, e.g. `data-engineer`.
+    #[validate(regex(path = "RE_TEMPLATE_NAME", message = "Template name must be lowercase alphanumerics and '-', at most 63 characters"))]
+    pub name: String,
+    pub scope: TemplateScope,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub rules: Vec<TemplateRule>,
+    /// Permit the `escalate`, `bind` and `impersonate` verbs, and `*`, on resources.
+    #[serde(default)]
+    pub allow_privileged_verbs: bool,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -31,8 +167,128 @@ pub struct LoginRequest {
     pub password: String
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct KubeLoginRequest {
+    /// A Kubernetes bearer token, e.g. from `kubectl create token` or an OIDC kubeconfig.
+    pub token: String,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct ClearLockoutQuery {
+    pub username: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct AuthBody {
     pub access_token: String,
-    pub token_type: String
-}
\ No newline at end of file
+    pub token_type: String,
+    /// Lifetime of `access_token` in seconds.
+    pub expires_in: i64,
+    /// Single-use token for `/apps/refresh`; every refresh returns a new one.
+    pub refresh_token: String,
+    /// Lifetime of `refresh_token` in seconds.
+    pub refresh_expires_in: i64,
+    /// One-time recovery codes, returned only by the login that completes MFA enrollment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// Returned by `/apps/login` instead of tokens when a second factor is needed.
+#[derive(Serialize, ToSchema)]
+pub struct MfaChallenge {
+    /// Short-lived token for `/apps/login/mfa` and `/apps/login/mfa/enroll`.
+    pub mfa_token: String,
+    /// The account has no authenticator yet and must enroll before finishing the login.
+    pub enrollment_required: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    /// A TOTP code, or a recovery code.
+    pub code: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaTokenRequest {
+    pub mfa_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MfaSetupInfo {
+    /// Base32 secret for manual entry into an authenticator app.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MfaRecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct CreateOperatorRequest {
+    #[validate(regex(path = "RE_OPERATOR_NAME", message = "Operator name must be 1-63 characters of letters, digits, '.', '_' or '-'"))]
+    pub username: String,
+    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
+    pub password: String,
+    #[serde(default = "default_operator_role")]
+    pub role: Role,
+    /// Limits the operator to these namespace names or globs (e.g. `team-a-*`).
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+}
+
+fn default_operator_role() -> Role {
+    Role::Operator
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OperatorNamespacesRequest {
+    pub username: String,
+    /// Namespace names or globs; `null` removes the restriction.
+    pub namespaces: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct OperatorRoleQuery {
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(regex(path = "RE_OPERATOR_NAME", message = "API key name must be 1-63 characters of letters, digits, '.', '_' or '-'"))]
+    pub name: String,
+    /// Defaults to the creator's role and may not exceed it.
+    #[serde(default)]
+    pub role: Option<Role>,
+    /// Defaults to the creator's scope and must stay within it.
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+    /// Defaults to `api_key_default_ttl_days`; at most `api_key_max_ttl_days`.
+    #[serde(default)]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct ApiKeyQuery {
+    pub id: String,
+}
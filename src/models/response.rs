@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use crate::services::{api_keys::ApiKey, authenticator::Role, cache::CacheStatus, kubernetes::{Applied, TemplateInfo, UserDeletion}, operators::Operator, templates::{BlastRadius, TemplateChanged, TemplateDetail}, token_rotation::TokenRotation};
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct MetaData {
@@ -15,6 +17,22 @@ pub struct MetaData {
 #[aliases(
     KanriGateRespString = KanriGateResp<String>,
     KanriGateRespVecString = KanriGateResp<Vec<String>>,
+    KanriGateRespOperatorInfo = KanriGateResp<OperatorInfo>,
+    KanriGateRespVecOperatorInfo = KanriGateResp<Vec<OperatorInfo>>,
+    KanriGateRespVecLockoutInfo = KanriGateResp<Vec<LockoutInfo>>,
+    KanriGateRespVecApiKeyInfo = KanriGateResp<Vec<ApiKeyInfo>>,
+    KanriGateRespApiKeyCreated = KanriGateResp<ApiKeyCreated>,
+    KanriGateRespHealthInfo = KanriGateResp<HealthInfo>,
+    KanriGateRespTokenRotated = KanriGateResp<TokenRotated>,
+    KanriGateRespVecTokenRotationInfo = KanriGateResp<Vec<TokenRotationInfo>>,
+    KanriGateRespUserProvisioned = KanriGateResp<UserProvisioned>,
+    KanriGateRespUserDeletion = KanriGateResp<UserDeletion>,
+    KanriGateRespApplied = KanriGateResp<Applied>,
+    KanriGateRespCacheStatus = KanriGateResp<CacheStatus>,
+    KanriGateRespVecTemplateInfo = KanriGateResp<Vec<TemplateInfo>>,
+    KanriGateRespTemplateDetail = KanriGateResp<TemplateDetail>,
+    KanriGateRespTemplateChanged = KanriGateResp<TemplateChanged>,
+    KanriGateRespBlastRadius = KanriGateResp<BlastRadius>,
 )]
 pub struct KanriGateResp<T> {
     pub meta_data: MetaData,
@@ -34,3 +52,133 @@ impl<T> KanriGateResp<T> {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct OperatorInfo {
+    pub username: String,
+    pub role: Role,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    pub disabled: bool,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct LockoutInfo {
+    /// `user:<name>` or `ip:<address>`.
+    pub key: String,
+    pub failures: u32,
+    #[schema(value_type = String, format = DateTime)]
+    pub locked_until: DateTime<Utc>,
+}
+
+impl From<Operator> for OperatorInfo {
+    fn from(op: Operator) -> Self {
+        Self {
+            username: op.username,
+            role: op.role,
+            namespaces: op.namespaces,
+            disabled: op.disabled,
+            created_at: op.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub role: Role,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub expires_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiKeyCreated {
+    /// The full key; it is not stored and cannot be shown again.
+    pub token: String,
+    pub api_key: ApiKeyInfo,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            owner: key.owner,
+            role: key.role,
+            namespaces: key.namespaces,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TokenRotationInfo {
+    #[schema(value_type = String, format = DateTime)]
+    pub rotated_at: DateTime<Utc>,
+    /// Operator who rotated the token, or `scheduler` for the background job.
+    pub rotated_by: String,
+    pub new_secret: String,
+    pub old_secrets: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<TokenRotation> for TokenRotationInfo {
+    fn from(rotation: TokenRotation) -> Self {
+        Self {
+            rotated_at: rotation.rotated_at,
+            rotated_by: rotation.rotated_by,
+            new_secret: rotation.new_secret,
+            old_secrets: rotation.old_secrets,
+            error: rotation.error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TokenRotated {
+    /// Kubeconfig with a token of the requested type.
+    pub kubeconfig: String,
+    /// The Secret rotation; the tokens of `old_secrets` no longer work.
+    pub rotation: TokenRotationInfo,
+    /// Tokens the rotation could not invalidate.
+    pub still_valid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UserProvisioned {
+    pub kubeconfig: String,
+    /// Objects created, in order, as `Kind/name` or `Kind/namespace/name`.
+    pub created: Vec<String>,
+}
+
+/// Startup security checks, reported by `/health`.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SecurityPosture {
+    pub environment: String,
+    /// True when none of the checks found a problem.
+    pub secure: bool,
+    pub issues: Vec<String>,
+    /// Started with `--insecure-allow-defaults` despite the issues.
+    pub insecure_defaults_allowed: bool,
+    /// `HS256` with the shared secret, or the asymmetric algorithm of the signing key.
+    pub token_signing: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct HealthInfo {
+    pub status: String,
+    pub security: SecurityPosture,
+}
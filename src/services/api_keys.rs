@@ -0,0 +1,267 @@
+use anyhow::{bail, Result};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::HEXLOWER;
+use kube::Client;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use crate::services::{authenticator::{IdentitySource, Role}, secret_store::JsonSecretStore};
+
+/// Keys look like `kg_<id>_<secret>`; the id is hex, so the first `_` after the prefix ends it.
+const TOKEN_PREFIX: &str = "kg_";
+const ID_BYTES: usize = 6;
+const SECRET_BYTES: usize = 32;
+/// `last_used_at` is only persisted when it is older than this, so busy keys do not
+/// write to the Secret on every request.
+const LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+
+/// A personal API key. Only the SHA-256 of the full key is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// User who created the key; requests made with it act as this user.
+    pub owner: String,
+    /// How the owner had logged in when creating the key. Only operator accounts are
+    /// re-checked, by revoking their keys when they change, so only their keys are honoured.
+    #[serde(default)]
+    pub source: IdentitySource,
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+fn digest(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// A new key id and the full token embedding it.
+fn generate() -> (String, String) {
+    let mut id = [0u8; ID_BYTES];
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut id);
+    rand::thread_rng().fill_bytes(&mut secret);
+    let id = HEXLOWER.encode(&id);
+    let token = format!("{}{}_{}", TOKEN_PREFIX, id, URL_SAFE_NO_PAD.encode(secret));
+    (id, token)
+}
+
+/// API keys, persisted in the `api_keys_secret` Secret keyed by id.
+///
+/// Authentication is served from an in-memory copy that a watch on the Secret keeps
+/// current, so removing a key takes effect on every replica without a restart.
+pub struct ApiKeyStore {
+    store: JsonSecretStore<ApiKey>,
+    keys: RwLock<BTreeMap<String, ApiKey>>,
+}
+
+impl ApiKeyStore {
+    pub fn new(client: Client, secret_name: &str) -> Self {
+        Self {
+            store: JsonSecretStore::new(client, secret_name),
+            keys: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Loads the persisted keys, replacing the in-memory copy.
+    pub async fn sync(&self) -> Result<()> {
+        let (records, _) = self.store.load().await?;
+        *self.keys.write().expect("api key lock poisoned") = records;
+        Ok(())
+    }
+
+    /// Follows keys created and removed by other replicas.
+    pub fn spawn_watch(self: &Arc<Self>) {
+        let this = Arc::clone(self);
+        self.store.spawn_watch(move |records| {
+            *this.keys.write().expect("api key lock poisoned") = records;
+        });
+    }
+
+    pub async fn list(&self) -> Result<Vec<ApiKey>> {
+        let (records, _) = self.store.load().await?;
+        Ok(records.into_values().collect())
+    }
+
+    /// Creates a key and returns it with the full token, which is not recoverable afterwards.
+    pub async fn create(
+        &self,
+        name: &str,
+        owner: &str,
+        source: IdentitySource,
+        role: Role,
+        namespaces: Option<Vec<String>>,
+        ttl: Duration,
+    ) -> Result<(ApiKey, String)> {
+        let (records, rv) = self.store.load().await?;
+        if records.values().any(|k| k.owner == owner && k.name == name) {
+            bail!("{} already has an API key named {}", owner, name);
+        }
+
+        let (id, token) = generate();
+        let now = Utc::now();
+        let key = ApiKey {
+            id: id.clone(),
+            name: name.to_string(),
+            owner: owner.to_string(),
+            source,
+            role,
+            namespaces,
+            hash: digest(&token),
+            created_at: now,
+            expires_at: now + ttl,
+            last_used_at: None,
+        };
+        self.store.write(rv, &id, Some(&key)).await?;
+        self.keys.write().expect("api key lock poisoned").insert(id, key.clone());
+        Ok((key, token))
+    }
+
+    /// Deletes a key; returns whether it existed.
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let (records, rv) = self.store.load().await?;
+        if !records.contains_key(id) {
+            return Ok(false);
+        }
+        self.store.write(rv, id, None).await?;
+        self.keys.write().expect("api key lock poisoned").remove(id);
+        Ok(true)
+    }
+
+    /// Deletes every key owned by `owner` and returns how many there were.
+    pub async fn remove_owned_by(&self, owner: &str) -> Result<usize> {
+        let ids: Vec<String> = self.list().await?
+            .into_iter()
+            .filter(|k| k.owner == owner)
+            .map(|k| k.id)
+            .collect();
+        for id in &ids {
+            self.remove(id).await?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Resolves a presented `kg_` token to its key, if it exists, has not expired and belongs
+    /// to an operator account.
+    pub fn authenticate(self: &Arc<Self>, token: &str) -> Option<ApiKey> {
+        let (id, _) = token.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+        let now = Utc::now();
+
+        let mut keys = self.keys.write().expect("api key lock poisoned");
+        let key = keys.get_mut(id)?;
+        if !constant_time_eq(key.hash.as_bytes(), digest(token).as_bytes()) || key.expires_at <= now || key.source != IdentitySource::Local {
+            return None;
+        }
+
+        let stale = key.last_used_at
+            .is_none_or(|used| now - used >= Duration::minutes(LAST_USED_RESOLUTION_MINUTES));
+        if stale {
+            key.last_used_at = Some(now);
+            let this = Arc::clone(self);
+            let id = id.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = this.touch(&id, now).await {
+                    tracing::debug!(key = %id, error = %e, "Failed to record API key use");
+                }
+            });
+        }
+        Some(key.clone())
+    }
+
+    async fn touch(&self, id: &str, used_at: DateTime<Utc>) -> Result<()> {
+        let (mut records, rv) = self.store.load().await?;
+        let mut key = match records.remove(id) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        key.last_used_at = Some(used_at);
+        self.store.write(rv, id, Some(&key)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store holding one key for `token`, expiring at `expires_at`.
+    fn store(token: &str, expires_at: DateTime<Utc>) -> Arc<ApiKeyStore> {
+        let config = kube::Config::new("http://127.0.0.1:1".parse().unwrap());
+        let store = ApiKeyStore::new(Client::try_from(config).unwrap(), "kanrigate-api-keys");
+        let (id, _) = token.strip_prefix(TOKEN_PREFIX).unwrap().split_once('_').unwrap();
+        let now = Utc::now();
+        let key = ApiKey {
+            id: id.to_string(),
+            name: "ci".to_string(),
+            owner: "alice".to_string(),
+            source: IdentitySource::Local,
+            role: Role::Operator,
+            namespaces: None,
+            hash: digest(token),
+            created_at: now,
+            expires_at,
+            // Recent enough that authenticating does not try to persist it.
+            last_used_at: Some(now),
+        };
+        store.keys.write().unwrap().insert(id.to_string(), key);
+        Arc::new(store)
+    }
+
+    #[test]
+    fn tokens_embed_their_id_and_only_their_digest_is_stored() {
+        let (id, token) = generate();
+        assert!(is_api_key(&token));
+        assert_eq!(id.len(), ID_BYTES * 2);
+        assert_eq!(token.strip_prefix(TOKEN_PREFIX).unwrap().split_once('_').unwrap().0, id);
+        assert_eq!(digest(&token).len(), 64);
+        assert!(!digest(&token).contains(&token[TOKEN_PREFIX.len()..]));
+        assert_ne!(generate().1, token);
+    }
+
+    #[tokio::test]
+    async fn valid_key_authenticates_as_its_owner() {
+        let (_, token) = generate();
+        let key = store(&token, Utc::now() + Duration::days(1)).authenticate(&token).unwrap();
+        assert_eq!(key.owner, "alice");
+        assert_eq!(key.role, Role::Operator);
+    }
+
+    #[tokio::test]
+    async fn wrong_secret_for_a_known_id_is_rejected() {
+        let (_, token) = generate();
+        let store = store(&token, Utc::now() + Duration::days(1));
+        let (prefix, _) = token.rsplit_once('_').unwrap();
+        assert!(store.authenticate(&format!("{}_{}", prefix, "forged")).is_none());
+        assert!(store.authenticate(&generate().1).is_none());
+        assert!(store.authenticate(&token[TOKEN_PREFIX.len()..]).is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_key_is_rejected() {
+        let (_, token) = generate();
+        assert!(store(&token, Utc::now() - Duration::seconds(1)).authenticate(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn key_of_an_external_identity_is_rejected() {
+        let (id, token) = generate();
+        let store = store(&token, Utc::now() + Duration::days(1));
+        store.keys.write().unwrap().get_mut(&id).unwrap().source = IdentitySource::Oidc;
+        assert!(store.authenticate(&token).is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, bail, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use lazy_static::lazy_static;
+use rand::thread_rng;
+use async_trait::async_trait;
+use kube::Client;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::sync::Arc;
+use tokio::sync::watch;
+use crate::config::BaseConfig;
+use crate::services::{ldap::LdapAuthenticator, operators::OperatorStore, settings::SettingsStore};
+
+/// Where the identity in a session token was established.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentitySource {
+    #[default]
+    Local,
+    Oidc,
+    Ldap,
+    /// A Kubernetes bearer token validated with a TokenReview.
+    Kubernetes,
+    /// A personal API key acting on behalf of its owner.
+    ApiKey,
+}
+
+/// KanriGate access level, ordered from least to most privileged.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May only call the list/get endpoints.
+    #[default]
+    Viewer,
+    /// May manage service accounts, secrets and namespaced bindings.
+    Operator,
+    /// Full access, including cluster-wide bindings and operator management.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Highest role granted by any of the user's groups, given per-role group lists.
+pub fn role_from_groups(groups: &[String], admin: &[String], operator: &[String], viewer: &[String]) -> Option<Role> {
+    let member = |allowed: &[String]| groups.iter().any(|g| allowed.iter().any(|a| a.eq_ignore_ascii_case(g)));
+    if member(admin) {
+        Some(Role::Admin)
+    } else if member(operator) {
+        Some(Role::Operator)
+    } else if member(viewer) {
+        Some(Role::Viewer)
+    } else {
+        None
+    }
+}
+
+/// Matches `name` against a glob `pattern` supporting `*` (any run) and `?` (any one character).
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n) = (pattern.as_bytes(), name.as_bytes());
+    let (mut pi, mut ni) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            backtrack = Some((pi, ni));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ni = matched + 1;
+            backtrack = Some((star, ni));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+/// Whether `namespace` falls inside a delegated scope; `None` means cluster-wide.
+pub fn namespace_in_scope(scope: Option<&[String]>, namespace: &str) -> bool {
+    match scope {
+        None => true,
+        Some(patterns) => patterns.iter().any(|p| glob_match(p, namespace)),
+    }
+}
+
+/// The result of a successful username/password authentication.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub source: IdentitySource,
+    pub role: Role,
+    /// Namespaces a delegated administrator is limited to; `None` for cluster-wide access.
+    pub namespaces: Option<Vec<String>>,
+    pub groups: Vec<String>,
+}
+
+/// A username/password backend used by `/apps/login`.
+///
+/// Implementations return `Ok(None)` for rejected credentials and reserve `Err` for
+/// backend failures, so the handler can tell a 401 from a 500.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>>;
+}
+
+lazy_static! {
+    // Verified against when the account does not exist, so unknown usernames take as long as wrong passwords.
+    static ref DUMMY_HASH: String = Argon2::default()
+        .hash_password(b"kanrigate-dummy-password", &SaltString::generate(&mut thread_rng()))
+        .expect("hashing the dummy password")
+        .to_string();
+}
+
+pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
+    let parsed_hash = PasswordHash::new(hash)
+        .map_err(|e| anyhow!("Invalid password hash config: {}", e))?;
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok())
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut thread_rng());
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+/// Why `password` is not acceptable for `username`, or `None` if it meets the policy:
+/// at least `min_length` characters, three of four character classes, and not containing the username.
+pub fn password_policy_violation(password: &str, username: &str, min_length: usize) -> Option<String> {
+    if password.chars().count() < min_length {
+        return Some(format!("Password must be at least {} characters", min_length));
+    }
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|present| **present).count() < 3 {
+        return Some("Password must mix at least three of lowercase letters, uppercase letters, digits and symbols".to_string());
+    }
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        return Some("Password must not contain the username".to_string());
+    }
+    None
+}
+
+/// Operator accounts from the operators Secret, falling back to the bootstrap admin in `BaseConfig`.
+pub struct LocalAuthenticator {
+    client: Client,
+    config: watch::Receiver<Arc<BaseConfig>>,
+}
+
+impl LocalAuthenticator {
+    /// `config` follows runtime settings, so a changed admin password applies immediately.
+    pub fn new(client: Client, config: watch::Receiver<Arc<BaseConfig>>) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl Authenticator for LocalAuthenticator {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>> {
+        let config = self.config.borrow().clone();
+        let store = OperatorStore::new(self.client.clone(), &config.operators_secret);
+
+        let account = match store.get(username).await? {
+            Some(op) if !op.disabled => Some((op.password_hash, op.role, op.namespaces)),
+            Some(_) => {
+                tracing::warn!(operator = %username, "Login attempt for disabled operator");
+                None
+            }
+            None if username == config.admin_username => Some((config.admin_password_hash.clone(), Role::Admin, None)),
+            None => None,
+        };
+
+        // Every rejection goes through one Argon2 verification so timing does not reveal which accounts exist.
+        let (password_hash, role, namespaces) = match account {
+            Some(account) => account,
+            None => {
+                let _ = verify_password(password, &DUMMY_HASH);
+                return Ok(None);
+            }
+        };
+
+        if !verify_password(password, &password_hash)? {
+            return Ok(None);
+        }
+
+        Ok(Some(AuthenticatedUser {
+            username: username.to_string(),
+            source: IdentitySource::Local,
+            role,
+            namespaces,
+            groups: Vec::new(),
+        }))
+    }
+}
+
+/// Builds the authenticator selected by `auth_backend`.
+pub fn from_config(client: Client, settings: &SettingsStore) -> Result<Arc<dyn Authenticator>> {
+    let config = settings.current();
+    match config.auth_backend.as_str() {
+        "local" => Ok(Arc::new(LocalAuthenticator::new(client, settings.subscribe()))),
+        "ldap" => Ok(Arc::new(LdapAuthenticator::from_config(&config)?)),
+        other => bail!("Unknown auth_backend '{}', expected 'local' or 'ldap'", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn glob_supports_star_and_question_mark() {
+        assert!(glob_match("team-a-*", "team-a-dev"));
+        assert!(glob_match("team-a-*", "team-a-"));
+        assert!(!glob_match("team-a-*", "team-b-dev"));
+        assert!(glob_match("*-prod", "payments-prod"));
+        assert!(!glob_match("*-prod", "payments-prod-eu"));
+        assert!(glob_match("team-?", "team-a"));
+        assert!(!glob_match("team-?", "team-ab"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "dev"));
+    }
+
+    #[test]
+    fn glob_without_wildcards_is_an_exact_match() {
+        assert!(glob_match("dev", "dev"));
+        assert!(!glob_match("dev", "dev2"));
+        assert!(!glob_match("dev", "de"));
+    }
+
+    #[test]
+    fn namespace_matches_any_pattern_of_its_scope() {
+        assert!(namespace_in_scope(None, "kube-system"));
+        let patterns = scope(&["team-a-*", "shared"]);
+        assert!(namespace_in_scope(Some(&patterns), "team-a-staging"));
+        assert!(namespace_in_scope(Some(&patterns), "shared"));
+        assert!(!namespace_in_scope(Some(&patterns), "kube-system"));
+        assert!(!namespace_in_scope(Some(&[]), "team-a-staging"));
+    }
+
+    #[test]
+    fn password_needs_length_and_three_character_classes() {
+        assert!(password_policy_violation("Ab1!", "alice", 12).unwrap().contains("at least 12"));
+        assert!(password_policy_violation("correcthorsebattery", "alice", 12).unwrap().contains("three of"));
+        assert!(password_policy_violation("CorrectHorseBattery", "alice", 12).unwrap().contains("three of"));
+        assert_eq!(password_policy_violation("CorrectHorse9Battery", "alice", 12), None);
+        assert_eq!(password_policy_violation("correct-horse-9-battery", "alice", 12), None);
+    }
+
+    #[test]
+    fn password_length_counts_characters_not_bytes() {
+        assert!(password_policy_violation("Pässwörd1!", "alice", 11).is_some());
+        assert_eq!(password_policy_violation("Pässwörd1!", "alice", 10), None);
+    }
+
+    #[test]
+    fn password_must_not_contain_the_username() {
+        assert!(password_policy_violation("Hello-ALICE-2024", "alice", 12).unwrap().contains("username"));
+        assert_eq!(password_policy_violation("Hello-ALICE-2024", "", 12), None);
+    }
+}
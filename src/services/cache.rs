@@ -0,0 +1,147 @@
+use anyhow::Result;
+use futures::{FutureExt, StreamExt};
+use k8s_openapi::api::core::v1::{Namespace, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, RoleBinding};
+use kube::{
+    api::Api,
+    runtime::{reflector, reflector::Store, watcher, WatchStreamExt},
+    Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use utoipa::ToSchema;
+use crate::services::kubernetes::{
+    cluster_role_binding_permissions, get_current_namespace, role_binding_permissions, templates_of, users_label_selector,
+    KubeOps, SubjectKind, TemplateInfo, TemplateScope,
+};
+
+/// Whether each cache has completed its initial list, as reported by `/ready`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStatus {
+    pub namespaces: bool,
+    pub service_accounts: bool,
+    pub role_bindings: bool,
+    pub cluster_role_bindings: bool,
+    pub cluster_roles: bool,
+}
+
+impl CacheStatus {
+    pub fn ready(&self) -> bool {
+        self.namespaces && self.service_accounts && self.role_bindings && self.cluster_role_bindings && self.cluster_roles
+    }
+}
+
+/// Watch-backed copies of the objects the read endpoints list: all Namespaces and
+/// ClusterRoles (the permission templates are among them), plus the ServiceAccounts,
+/// RoleBindings and ClusterRoleBindings KanriGate manages for users.
+///
+/// Until a cache has synced, its reads fall through to the API server.
+#[derive(Clone)]
+pub struct KubeCache {
+    kube: KubeOps,
+    namespaces: Store<Namespace>,
+    service_accounts: Store<ServiceAccount>,
+    role_bindings: Store<RoleBinding>,
+    cluster_role_bindings: Store<ClusterRoleBinding>,
+    cluster_roles: Store<ClusterRole>,
+}
+
+/// Starts a reflector for `api` that runs for as long as the process does.
+fn reflect<K>(api: Api<K>, config: watcher::Config) -> Store<K>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+{
+    let (reader, writer) = reflector::store();
+    tokio::spawn(async move {
+        let mut stream = watcher(api, config)
+            .default_backoff()
+            .reflect(writer)
+            .applied_objects()
+            .boxed();
+        while let Some(event) = stream.next().await {
+            if let Err(e) = event {
+                tracing::warn!(kind = %K::kind(&()), error = %e, "Cache watch error");
+            }
+        }
+    });
+    reader
+}
+
+fn synced<K>(store: &Store<K>) -> bool
+where
+    K: Resource<DynamicType = ()> + Clone + 'static,
+{
+    store.wait_until_ready().now_or_never().is_some_and(|r| r.is_ok())
+}
+
+impl KubeCache {
+    pub fn spawn(client: Client) -> Self {
+        let managed = watcher::Config::default().labels(&users_label_selector());
+        Self {
+            kube: KubeOps::new(client.clone()),
+            namespaces: reflect(Api::all(client.clone()), watcher::Config::default()),
+            service_accounts: reflect(Api::namespaced(client.clone(), &get_current_namespace()), managed.clone()),
+            role_bindings: reflect(Api::all(client.clone()), managed.clone()),
+            cluster_role_bindings: reflect(Api::all(client.clone()), managed),
+            cluster_roles: reflect(Api::all(client), watcher::Config::default()),
+        }
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        CacheStatus {
+            namespaces: synced(&self.namespaces),
+            service_accounts: synced(&self.service_accounts),
+            role_bindings: synced(&self.role_bindings),
+            cluster_role_bindings: synced(&self.cluster_role_bindings),
+            cluster_roles: synced(&self.cluster_roles),
+        }
+    }
+
+    pub async fn namespaces(&self) -> Result<Vec<String>> {
+        if !synced(&self.namespaces) {
+            return self.kube.get_namespaces().await;
+        }
+        let mut names: Vec<String> = self.namespaces.state().iter().map(|ns| ns.name_any()).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub async fn templates(&self) -> Result<Vec<TemplateInfo>> {
+        if !synced(&self.cluster_roles) {
+            return self.kube.get_templates().await;
+        }
+        let roles = self.cluster_roles.state();
+        Ok(templates_of(roles.iter().map(|role| role.as_ref())))
+    }
+
+    /// The template `name` of `scope`, if the cluster has it.
+    pub async fn template(&self, scope: TemplateScope, name: &str) -> Result<Option<TemplateInfo>> {
+        Ok(self.templates().await?.into_iter().find(|t| t.scope == scope && t.name == name))
+    }
+
+    pub async fn service_accounts(&self) -> Result<Vec<String>> {
+        if !synced(&self.service_accounts) {
+            return self.kube.get_service_accounts().await;
+        }
+        let mut names: Vec<String> = self.service_accounts.state().iter().map(|sa| sa.name_any()).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub async fn filtered_role_bindings(&self, username: &str, kind: SubjectKind) -> Result<serde_json::Value> {
+        if !synced(&self.role_bindings) {
+            return self.kube.get_filtered_role_bindings(username, kind).await;
+        }
+        let bindings = self.role_bindings.state();
+        Ok(role_binding_permissions(bindings.iter().map(|rb| rb.as_ref()), kind, username))
+    }
+
+    pub async fn filtered_cluster_role_bindings(&self, username: &str, kind: SubjectKind) -> Result<serde_json::Value> {
+        if !synced(&self.cluster_role_bindings) {
+            return self.kube.get_filtered_cluster_role_bindings(username, kind).await;
+        }
+        let bindings = self.cluster_role_bindings.state();
+        Ok(cluster_role_binding_permissions(bindings.iter().map(|crb| crb.as_ref()), kind, username))
+    }
+}
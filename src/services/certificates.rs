@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+// Object identifiers, DER-encoded without tag and length.
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+
+/// A freshly generated P-256 key and a PKCS#10 request for it, both PEM-encoded.
+pub struct ClientKey {
+    pub private_key_pem: String,
+    pub csr_pem: String,
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &parts.concat())
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    der(0x03, &[&[0u8][..], bytes].concat())
+}
+
+/// One relative distinguished name holding a single UTF8String attribute.
+fn rdn(oid: &[u8], value: &str) -> Vec<u8> {
+    der(0x31, &sequence(&[der(0x06, oid), der(0x0c, value.as_bytes())]))
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = BASE64.encode(der);
+    let lines: Vec<&str> = encoded.as_bytes()
+        .chunks(64)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base64 is ASCII"))
+        .collect();
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+/// Generates a key and a certificate request for `CN=<username>` with one `O=` per group,
+/// which Kubernetes maps to the user name and groups of the client certificate.
+pub fn client_key(username: &str, groups: &[String]) -> Result<ClientKey> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(|_| anyhow!("Failed to generate a P-256 key"))?;
+    let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+        .map_err(|_| anyhow!("Failed to load the generated P-256 key"))?;
+
+    let mut subject: Vec<Vec<u8>> = groups.iter().map(|g| rdn(OID_ORGANIZATION, g)).collect();
+    subject.push(rdn(OID_COMMON_NAME, username));
+    let public_key_info = sequence(&[
+        sequence(&[der(0x06, OID_EC_PUBLIC_KEY), der(0x06, OID_PRIME256V1)]),
+        bit_string(pair.public_key().as_ref()),
+    ]);
+    // RFC 2986 CertificationRequestInfo: version 0, subject, key, and no attributes.
+    let info = sequence(&[der(0x02, &[0]), sequence(&subject), public_key_info, der(0xa0, &[])]);
+
+    let signature = pair.sign(&rng, &info).map_err(|_| anyhow!("Failed to sign the certificate request"))?;
+    let request = sequence(&[info, sequence(&[der(0x06, OID_ECDSA_WITH_SHA256)]), bit_string(signature.as_ref())]);
+
+    Ok(ClientKey {
+        private_key_pem: pem("PRIVATE KEY", pkcs8.as_ref()),
+        csr_pem: pem("CERTIFICATE REQUEST", &request),
+    })
+}
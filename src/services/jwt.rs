@@ -0,0 +1,166 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use crate::config::BaseConfig;
+
+/// Public keys live in `<kid>.pem`, private keys in `<kid>.key`.
+const PUBLIC_KEY_EXT: &str = "pem";
+const PRIVATE_KEY_EXT: &str = "key";
+
+struct VerifyingKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Signs and verifies every token KanriGate issues.
+///
+/// With `jwt_keys_dir` set, tokens are signed with the RS256 or EdDSA key named by
+/// `jwt_signing_kid` and carry its `kid`; every public key in the directory is accepted,
+/// so a new key can be rolled out before it starts signing and an old one kept until
+/// its tokens have expired. Without it, the HS256 `jwt_secret` is used.
+pub struct JwtKeys {
+    signing_kid: Option<String>,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verifying: HashMap<String, VerifyingKey>,
+    hmac: Option<DecodingKey>,
+    jwks: JwkSet,
+}
+
+fn public_key(pem: &[u8], kid: &str) -> Result<(Algorithm, DecodingKey, Jwk)> {
+    let common = |algorithm: KeyAlgorithm| CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    };
+
+    let text = std::str::from_utf8(pem)?;
+    let rsa = RsaPublicKey::from_public_key_pem(text).or_else(|_| RsaPublicKey::from_pkcs1_pem(text));
+    if let Ok(rsa) = rsa {
+        let (n, e) = (rsa.n().to_bytes_be(), rsa.e().to_bytes_be());
+        let jwk = Jwk {
+            common: common(KeyAlgorithm::RS256),
+            algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(&n),
+                e: URL_SAFE_NO_PAD.encode(&e),
+            }),
+        };
+        return Ok((Algorithm::RS256, DecodingKey::from_rsa_raw_components(&n, &e), jwk));
+    }
+
+    if let Ok(key) = DecodingKey::from_ed_pem(pem) {
+        let jwk = Jwk {
+            common: common(KeyAlgorithm::EdDSA),
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+            }),
+        };
+        return Ok((Algorithm::EdDSA, key, jwk));
+    }
+
+    bail!("Key {} is neither an RSA nor an Ed25519 public key", kid)
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &BaseConfig) -> Result<Self> {
+        let dir = match &config.jwt_keys_dir {
+            Some(dir) => dir,
+            None => {
+                tracing::warn!("jwt_keys_dir is not set; signing tokens with the shared HS256 jwt_secret");
+                return Ok(Self {
+                    signing_kid: None,
+                    signing_algorithm: Algorithm::HS256,
+                    signing_key: EncodingKey::from_secret(config.jwt_secret.as_ref()),
+                    verifying: HashMap::new(),
+                    hmac: Some(DecodingKey::from_secret(config.jwt_secret.as_ref())),
+                    jwks: JwkSet { keys: Vec::new() },
+                });
+            }
+        };
+        let signing_kid = config.jwt_signing_kid.clone()
+            .ok_or_else(|| anyhow!("jwt_signing_kid is required when jwt_keys_dir is set"))?;
+
+        let mut verifying = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Reading jwt_keys_dir {}", dir))? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PUBLIC_KEY_EXT) {
+                continue;
+            }
+            let kid = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(kid) => kid.to_string(),
+                None => continue,
+            };
+            let pem = std::fs::read(&path).with_context(|| format!("Reading {}", path.display()))?;
+            let (algorithm, key, jwk) = public_key(&pem, &kid)?;
+            verifying.insert(kid, VerifyingKey { algorithm, key });
+            jwks.keys.push(jwk);
+        }
+
+        let signing_algorithm = verifying.get(&signing_kid)
+            .map(|k| k.algorithm)
+            .ok_or_else(|| anyhow!("No public key {}.{} in {}", signing_kid, PUBLIC_KEY_EXT, dir))?;
+        let private_path = Path::new(dir).join(format!("{}.{}", signing_kid, PRIVATE_KEY_EXT));
+        let private_pem = std::fs::read(&private_path)
+            .with_context(|| format!("Reading signing key {}", private_path.display()))?;
+        let signing_key = match signing_algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_pem)?,
+            _ => EncodingKey::from_ed_pem(&private_pem)?,
+        };
+
+        tracing::info!(kid = %signing_kid, algorithm = ?signing_algorithm, keys = verifying.len(), "Loaded JWT signing keys");
+        Ok(Self {
+            signing_kid: Some(signing_kid),
+            signing_algorithm,
+            signing_key,
+            verifying,
+            hmac: None,
+            jwks,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = self.signing_kid.clone();
+        encode(&header, claims, &self.signing_key)
+            .map_err(|e| anyhow!("Token generation failed: {}", e))
+    }
+
+    /// Verifies the signature and expiry of `token` and returns its claims.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T> {
+        let header = decode_header(token)?;
+        let (key, algorithm) = match (&self.hmac, &header.kid) {
+            (Some(secret), _) => (secret, Algorithm::HS256),
+            (None, Some(kid)) => {
+                let verifying = self.verifying.get(kid).ok_or_else(|| anyhow!("Unknown key id {}", kid))?;
+                (&verifying.key, verifying.algorithm)
+            }
+            (None, None) => bail!("Token has no key id"),
+        };
+        Ok(decode::<T>(token, key, &Validation::new(algorithm))?.claims)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.signing_algorithm
+    }
+
+    /// Public verification keys; empty while tokens are signed with the shared secret.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
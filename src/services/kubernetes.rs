@@ -1,16 +1,84 @@
 use anyhow::{anyhow, Result};
-use k8s_openapi::api::core::v1::{Namespace, Secret, ServiceAccount};
-use k8s_openapi::api::rbac::v1::{ClusterRoleBinding, RoleBinding, RoleRef, Subject};
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::authentication::v1::{TokenRequest, TokenRequestSpec};
+use k8s_openapi::api::certificates::v1::{CertificateSigningRequest, CertificateSigningRequestSpec};
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace, Secret, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use k8s_openapi::ByteString;
 use kube::{
-    api::{Api, DeleteParams, ListParams, PostParams},
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams, PostParams},
     Client, ResourceExt,
 };
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
-use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use crate::config::{rbac, sa};
+use utoipa::ToSchema;
+use crate::config::{labels, rbac, sa, templates};
+use crate::services::certificates;
+
+const ROOT_CA_CONFIGMAP: &str = "kube-root-ca.crt";
+const CLIENT_SIGNER: &str = "kubernetes.io/kube-apiserver-client";
+/// How long to wait for controllers to sign an approved certificate or fill in a token Secret.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const POLL_ATTEMPTS: u32 = 60;
+
+/// Kind of subject a binding grants a template to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum SubjectKind {
+    /// A ServiceAccount in the KanriGate namespace.
+    #[default]
+    ServiceAccount,
+    /// A user authenticated by client certificate, as issued by `generateK8sCertConfig`.
+    User,
+}
+
+impl SubjectKind {
+    fn subject(self, username: &str, namespace: &str) -> Subject {
+        match self {
+            SubjectKind::ServiceAccount => Subject {
+                kind: "ServiceAccount".to_string(),
+                name: username.to_string(),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            SubjectKind::User => Subject {
+                api_group: Some(rbac::API_GROUP.to_string()),
+                kind: "User".to_string(),
+                name: username.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Whether `subject` is `username` of this kind; a User and a ServiceAccount of the same name are different subjects.
+    fn matches(self, subject: &Subject, username: &str) -> bool {
+        let kind = match self {
+            SubjectKind::ServiceAccount => "ServiceAccount",
+            SubjectKind::User => "User",
+        };
+        subject.kind == kind && subject.name == username
+    }
+
+    /// Prefix of binding names; `:` cannot occur in ServiceAccount names, so the two never collide.
+    fn binding_prefix(self, username: &str) -> String {
+        match self {
+            SubjectKind::ServiceAccount => username.to_string(),
+            SubjectKind::User => format!("user:{}", username),
+        }
+    }
+}
+
+/// Where the token in a generated kubeconfig comes from.
+pub enum KubeconfigToken {
+    /// The ServiceAccount's `kubernetes.io/service-account-token` Secret; never expires.
+    Legacy,
+    /// A bound token minted through `serviceaccounts/token`.
+    Bound { expiration_seconds: i64, audiences: Vec<String> },
+}
 
 // Template Helpers
 fn role_binding_name(username: &str, namespace: &str, permission: &str) -> String {
@@ -18,7 +86,7 @@ fn role_binding_name(username: &str, namespace: &str, permission: &str) -> Strin
 }
 
 fn role_name(permission: &str) -> String {
-    format!("template-namespaced-resources___{}", permission)
+    TemplateScope::Namespaced.role_name(permission)
 }
 
 fn cluster_role_binding_name(username: &str, permission: &str) -> String {
@@ -26,10 +94,335 @@ fn cluster_role_binding_name(username: &str, permission: &str) -> String {
 }
 
 fn cluster_role_name(permission: &str) -> String {
-    format!("template-cluster-resources___{}", permission)
+    TemplateScope::Cluster.role_name(permission)
+}
+
+/// Where a permission template can be bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateScope {
+    /// Bound per namespace with `createRoleBinding`.
+    Namespaced,
+    /// Bound cluster-wide with `createClusterRoleBinding`.
+    Cluster,
+}
+
+impl TemplateScope {
+    /// Name of the ClusterRole behind the template `name`.
+    pub fn role_name(self, name: &str) -> String {
+        match self {
+            TemplateScope::Namespaced => format!("{}{}", templates::NAMESPACED_PREFIX, name),
+            TemplateScope::Cluster => format!("{}{}", templates::CLUSTER_PREFIX, name),
+        }
+    }
+}
+
+/// A permission template, i.e. a ClusterRole following the template naming convention.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TemplateInfo {
+    /// The `permission` to pass to the binding endpoints.
+    pub name: String,
+    pub scope: TemplateScope,
+    /// From the `kanrigate.io/description` annotation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub rule_count: usize,
+}
+
+impl TemplateInfo {
+    /// The template `role` stands for, if its name follows the convention.
+    pub fn from_cluster_role(role: &ClusterRole) -> Option<Self> {
+        let full_name = role.name_any();
+        let (scope, name) = if let Some(name) = full_name.strip_prefix(templates::NAMESPACED_PREFIX) {
+            (TemplateScope::Namespaced, name)
+        } else if let Some(name) = full_name.strip_prefix(templates::CLUSTER_PREFIX) {
+            (TemplateScope::Cluster, name)
+        } else {
+            return None;
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            scope,
+            description: role.annotations().get(templates::ANNOTATIONS_DESCRIPTION).cloned(),
+            rule_count: role.rules.as_ref().map_or(0, Vec::len),
+        })
+    }
+}
+
+/// Templates among `roles`, sorted by scope and name.
+pub(crate) fn templates_of<'a>(roles: impl IntoIterator<Item = &'a ClusterRole>) -> Vec<TemplateInfo> {
+    let mut found: Vec<TemplateInfo> = roles.into_iter().filter_map(TemplateInfo::from_cluster_role).collect();
+    found.sort_by(|a, b| (a.scope == TemplateScope::Cluster, &a.name).cmp(&(b.scope == TemplateScope::Cluster, &b.name)));
+    found
 }
 
-fn get_current_namespace() -> String {
+/// `username` as a label value; names that are too long or contain other characters, such
+/// as certificate users with an `@`, become a hash. Lookups by this value still compare the
+/// full name.
+fn user_label_value(username: &str) -> String {
+    let valid = username.len() <= 63
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && username.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && username.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if valid {
+        return username.to_string();
+    }
+    let digest: String = Sha256::digest(username.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256-{}", &digest[..56])
+}
+
+/// Labels of an object that belongs to `username`.
+fn user_labels(username: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (labels::MANAGED_BY.to_string(), labels::MANAGED_BY_VALUE.to_string()),
+        (labels::USER.to_string(), user_label_value(username)),
+    ])
+}
+
+/// Labels of an object KanriGate keeps for itself rather than for a user.
+pub(crate) fn managed_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([(labels::MANAGED_BY.to_string(), labels::MANAGED_BY_VALUE.to_string())])
+}
+
+fn user_selector(username: &str) -> ListParams {
+    ListParams::default().labels(&format!(
+        "{}={},{}={}",
+        labels::MANAGED_BY, labels::MANAGED_BY_VALUE, labels::USER, user_label_value(username)
+    ))
+}
+
+/// Label selector matching every object that belongs to some user.
+pub(crate) fn users_label_selector() -> String {
+    format!("{}={},{}", labels::MANAGED_BY, labels::MANAGED_BY_VALUE, labels::USER)
+}
+
+fn users_selector() -> ListParams {
+    ListParams::default().labels(&users_label_selector())
+}
+
+/// Whether `labels` mark an object as KanriGate's, belonging to `username`.
+fn has_user_labels(labels: &BTreeMap<String, String>, username: &str) -> bool {
+    user_labels(username).iter().all(|(k, v)| labels.get(k) == Some(v))
+}
+
+/// For each `kind` subject named in `bindings`, whether every binding that names it carries
+/// KanriGate's labels for it.
+fn subject_bindings<'a>(
+    bindings: impl IntoIterator<Item = (&'a Option<Vec<Subject>>, &'a BTreeMap<String, String>)>,
+    kind: SubjectKind,
+) -> BTreeMap<String, bool> {
+    let mut managed = BTreeMap::new();
+    for (subjects, labels) in bindings {
+        for subject in subjects.iter().flatten().filter(|s| kind.matches(s, &s.name)) {
+            let labelled = has_user_labels(labels, &subject.name);
+            *managed.entry(subject.name.clone()).or_insert(true) &= labelled;
+        }
+    }
+    managed
+}
+
+fn has_subject(subjects: &Option<Vec<Subject>>, kind: SubjectKind, username: &str) -> bool {
+    subjects.as_ref().is_some_and(|subs| subs.iter().any(|s| kind.matches(s, username)))
+}
+
+fn secret_owner(secret: &Secret) -> Option<&String> {
+    secret.metadata.annotations.as_ref().and_then(|a| a.get(sa::ANNOTATIONS_NAME))
+}
+
+/// Template part of a template role name, or the whole name if it does not follow the convention.
+fn permission_of(role_ref: &RoleRef) -> &str {
+    role_ref.name.split("___").nth(1).unwrap_or(&role_ref.name)
+}
+
+/// Permissions the `kind` subject `username` holds through `bindings`, per namespace, e.g. `{ "dev": { "developer": true } }`.
+pub(crate) fn role_binding_permissions<'a>(bindings: impl IntoIterator<Item = &'a RoleBinding>, kind: SubjectKind, username: &str) -> serde_json::Value {
+    let mut ns_permissions = serde_json::Map::new();
+    for rb in bindings {
+        if !has_subject(&rb.subjects, kind, username) {
+            continue;
+        }
+        let entry = ns_permissions.entry(rb.namespace().unwrap_or_default()).or_insert(json!({}));
+        if let Some(obj) = entry.as_object_mut() {
+            obj.insert(permission_of(&rb.role_ref).to_string(), json!(true));
+        }
+    }
+    serde_json::Value::Object(ns_permissions)
+}
+
+/// Cluster permissions the `kind` subject `username` holds through `bindings`, e.g. `{ "read-only": true }`.
+pub(crate) fn cluster_role_binding_permissions<'a>(bindings: impl IntoIterator<Item = &'a ClusterRoleBinding>, kind: SubjectKind, username: &str) -> serde_json::Value {
+    let mut cluster_permissions = serde_json::Map::new();
+    for crb in bindings {
+        if has_subject(&crb.subjects, kind, username) {
+            cluster_permissions.insert(permission_of(&crb.role_ref).to_string(), json!(true));
+        }
+    }
+    serde_json::Value::Object(cluster_permissions)
+}
+
+/// The single subject of a binding KanriGate created for a template.
+fn template_binding_subject<'a>(name: &str, role_ref: &RoleRef, subjects: &'a Option<Vec<Subject>>) -> Option<&'a Subject> {
+    if !role_ref.name.starts_with("template-") || !name.contains("___template-") {
+        return None;
+    }
+    match subjects.as_deref() {
+        Some([subject]) => Some(subject),
+        _ => None,
+    }
+}
+
+/// Adds `labels` to `object` unless it already has them; returns whether it patched.
+async fn ensure_labels<K>(api: &Api<K>, object: &K, labels: BTreeMap<String, String>) -> Result<bool>
+where
+    K: kube::Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    let current = object.labels();
+    if labels.iter().all(|(key, value)| current.get(key) == Some(value)) {
+        return Ok(false);
+    }
+    let patch = json!({ "metadata": { "labels": labels } });
+    api.patch(&object.name_any(), &PatchParams::default(), &Patch::Merge(&patch)).await?;
+    Ok(true)
+}
+
+/// What a `KubeOps::create_*` call did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplyOutcome {
+    Created,
+    /// An identical object already existed.
+    Unchanged,
+    /// An equivalent object existed and only got KanriGate's labels.
+    Updated,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Applied {
+    pub name: String,
+    pub outcome: ApplyOutcome,
+}
+
+/// An object to be created already exists and differs from the requested one.
+#[derive(Debug)]
+pub struct Conflict(pub String);
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// The object exists but was not created by KanriGate for a user, so KanriGate will not
+/// hand out credentials for it.
+#[derive(Debug)]
+pub struct NotManaged(pub String);
+
+impl std::fmt::Display for NotManaged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotManaged {}
+
+/// Creates `object`. When an object with its name exists, `difference` names the field in
+/// which it differs meaningfully, which is a `Conflict`; otherwise the existing object is
+/// accepted, with KanriGate's labels added if it lacks them.
+pub(crate) async fn create_or_accept<K, F>(api: &Api<K>, object: &K, difference: F) -> Result<Applied>
+where
+    K: kube::Resource<DynamicType = ()> + Clone + DeserializeOwned + Serialize + std::fmt::Debug,
+    F: Fn(&K) -> Option<&'static str>,
+{
+    let name = object.name_any();
+    match api.create(&PostParams::default(), object).await {
+        Ok(_) => return Ok(Applied { name, outcome: ApplyOutcome::Created }),
+        Err(kube::Error::Api(e)) if e.code == 409 => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let existing = api.get(&name).await?;
+    if let Some(field) = difference(&existing) {
+        return Err(Conflict(format!("{} {} already exists with a different {}", K::kind(&()), name, field)).into());
+    }
+    let outcome = if ensure_labels(api, &existing, object.labels().clone()).await? {
+        ApplyOutcome::Updated
+    } else {
+        ApplyOutcome::Unchanged
+    };
+    Ok(Applied { name, outcome })
+}
+
+/// What `KubeOps::delete_user` did, or would do, with one object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletionOutcome {
+    Deleted,
+    /// Owned by the ServiceAccount, so the garbage collector deletes it with the account.
+    Collected,
+    /// Left alone; `error` says why.
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeletedObject {
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+    pub outcome: DeletionOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DeletedObject {
+    fn from_result<T>(kind: &str, namespace: Option<String>, name: String, result: kube::Result<T>) -> Self {
+        let (outcome, error) = match result {
+            Ok(_) => (DeletionOutcome::Deleted, None),
+            Err(e) => (DeletionOutcome::Failed, Some(e.to_string())),
+        };
+        Self { kind: kind.to_string(), namespace, name, outcome, error }
+    }
+
+    /// Outcome of a Secret owned by a ServiceAccount, which only goes once `account` has.
+    fn owned_by(account: &DeletedObject, name: String, dry_run: bool) -> Self {
+        let (outcome, error) = match account.outcome {
+            DeletionOutcome::Deleted if dry_run => {
+                (DeletionOutcome::Skipped, Some("Dry run; the garbage collector would delete it with the ServiceAccount".to_string()))
+            }
+            DeletionOutcome::Deleted => (DeletionOutcome::Collected, None),
+            _ => (DeletionOutcome::Failed, Some(format!("ServiceAccount {} was not deleted", account.name))),
+        };
+        Self { kind: "Secret".to_string(), namespace: account.namespace.clone(), name, outcome, error }
+    }
+}
+
+/// Itemized result of `KubeOps::delete_user`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserDeletion {
+    pub username: String,
+    pub kind: SubjectKind,
+    /// Nothing was deleted; the API server only validated each deletion.
+    pub dry_run: bool,
+    pub objects: Vec<DeletedObject>,
+}
+
+/// Objects labelled by `KubeOps::label_managed_objects`, per kind.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LabelMigration {
+    pub service_accounts: usize,
+    pub secrets: usize,
+    pub role_bindings: usize,
+    pub cluster_role_bindings: usize,
+    pub certificate_signing_requests: usize,
+}
+
+pub(crate) fn get_current_namespace() -> String {
     std::fs::read_to_string("/var/run/secrets/kubernetes.io/serviceaccount/namespace")
         .unwrap_or_else(|_| "kanrigate".to_string())
         .trim()
@@ -81,9 +474,14 @@ struct NamedUser {
     user: UserConfig,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct UserConfig {
-    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(rename = "client-certificate-data", skip_serializing_if = "Option::is_none")]
+    client_certificate_data: Option<String>,
+    #[serde(rename = "client-key-data", skip_serializing_if = "Option::is_none")]
+    client_key_data: Option<String>,
 }
 
 #[derive(Clone)]
@@ -106,23 +504,50 @@ impl KubeOps {
         Ok(list.iter().map(|ns| ns.name_any()).collect())
     }
 
+    pub async fn get_templates(&self) -> Result<Vec<TemplateInfo>> {
+        let api: Api<ClusterRole> = Api::all(self.client.clone());
+        let list = api.list(&ListParams::default()).await?;
+        Ok(templates_of(&list.items))
+    }
+
     pub async fn get_service_accounts(&self) -> Result<Vec<String>> {
         let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
-        let list = api.list(&ListParams::default()).await?;
+        let list = api.list(&users_selector()).await?;
         Ok(list.iter().map(|sa| sa.name_any()).collect())
     }
 
-    pub async fn create_service_account(&self, username: &str) -> Result<String> {
+    pub async fn create_service_account(&self, username: &str) -> Result<Applied> {
         let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
         let sa = ServiceAccount {
             metadata: ObjectMeta {
                 name: Some(username.to_string()),
+                labels: Some(user_labels(username)),
                 ..Default::default()
             },
             ..Default::default()
         };
-        api.create(&PostParams::default(), &sa).await?;
-        Ok(username.to_string())
+        // Adopting someone else's account would let the token endpoints issue credentials for it.
+        create_or_accept(&api, &sa, |existing| (!has_user_labels(existing.labels(), username)).then_some("owner")).await
+    }
+
+    pub async fn service_account_exists(&self, username: &str) -> Result<bool> {
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        Ok(api.get_opt(username).await?.is_some())
+    }
+
+    /// Makes a token Secret owned by the ServiceAccount `username`, so deleting the account
+    /// deletes the Secret too; `None` when the account does not exist.
+    async fn service_account_owner(&self, username: &str) -> Result<Option<Vec<OwnerReference>>> {
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        Ok(api.get_opt(username).await?.and_then(|account| {
+            Some(vec![OwnerReference {
+                api_version: "v1".to_string(),
+                kind: "ServiceAccount".to_string(),
+                name: account.name_any(),
+                uid: account.uid()?,
+                ..Default::default()
+            }])
+        }))
     }
 
     pub async fn delete_service_account(&self, username: &str) -> Result<String> {
@@ -131,21 +556,18 @@ impl KubeOps {
         Ok(username.to_string())
     }
 
-    pub async fn create_secret(&self, username: &str) -> Result<String> {
+    pub async fn create_secret(&self, username: &str) -> Result<Applied> {
+        self.require_managed_account(username).await?;
         let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
-        
+
         // Check if secret exists via annotation
-        let list = api.list(&ListParams::default()).await?;
-        let secret_name = list.items.iter().find_map(|s| {
-            s.metadata.annotations.as_ref()
-                .and_then(|a| a.get(sa::ANNOTATIONS_NAME))
-                .filter(|v| *v == username)
-                .map(|_| s.name_any())
-        });
+        let list = api.list(&user_selector(username)).await?;
+        let secret_name = list.items.iter()
+            .find(|s| secret_owner(s).is_some_and(|v| v == username))
+            .map(|s| s.name_any());
 
         if let Some(name) = secret_name {
-            // Already exists
-            return Ok(name);
+            return Ok(Applied { name, outcome: ApplyOutcome::Unchanged });
         }
 
         let token_secret_name = format!("{}-token", username);
@@ -155,44 +577,122 @@ impl KubeOps {
         let secret = Secret {
             metadata: ObjectMeta {
                 name: Some(token_secret_name.clone()),
+                labels: Some(user_labels(username)),
                 annotations: Some(annotations),
+                owner_references: self.service_account_owner(username).await?,
                 ..Default::default()
             },
             type_: Some(sa::TYPE_TOKEN.to_string()),
             ..Default::default()
         };
 
-        api.create(&PostParams::default(), &secret).await?;
-        Ok(token_secret_name)
+        create_or_accept(&api, &secret, |existing| {
+            if existing.type_.as_deref() != Some(sa::TYPE_TOKEN) {
+                Some("type")
+            } else if secret_owner(existing).is_none_or(|v| v != username) {
+                Some("ServiceAccount")
+            } else {
+                None
+            }
+        }).await
     }
 
     pub async fn delete_secret(&self, username: &str) -> Result<Vec<String>> {
         let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
-        let list = api.list(&ListParams::default()).await?;
+        let list = api.list(&user_selector(username)).await?;
         
         let mut deleted = Vec::new();
         for secret in list.items {
-            if let Some(annotations) = &secret.metadata.annotations {
-                if let Some(sa_name) = annotations.get(sa::ANNOTATIONS_NAME) {
-                    if sa_name == username {
-                        let name = secret.name_any();
-                        api.delete(&name, &DeleteParams::default()).await?;
-                        deleted.push(name);
-                    }
-                }
+            if secret_owner(&secret).is_some_and(|v| v == username) {
+                let name = secret.name_any();
+                api.delete(&name, &DeleteParams::default()).await?;
+                deleted.push(name);
             }
         }
         Ok(deleted)
     }
 
-    pub async fn create_role_binding(&self, username: &str, namespace: &str, permission: &str) -> Result<String> {
+    /// Token Secrets in the KanriGate namespace, grouped by the ServiceAccount they belong to.
+    pub async fn token_secrets(&self) -> Result<BTreeMap<String, Vec<Secret>>> {
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let list = api.list(&users_selector()).await?;
+
+        let mut by_user: BTreeMap<String, Vec<Secret>> = BTreeMap::new();
+        for secret in list.items {
+            if let Some(owner) = secret_owner(&secret).cloned() {
+                by_user.entry(owner).or_default().push(secret);
+            }
+        }
+        Ok(by_user)
+    }
+
+    /// Token Secrets of the ServiceAccount `username`.
+    pub async fn user_token_secrets(&self, username: &str) -> Result<Vec<Secret>> {
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let list = api.list(&user_selector(username)).await?;
+        Ok(list.items.into_iter().filter(|s| secret_owner(s).is_some_and(|v| v == username)).collect())
+    }
+
+    /// Creates the token Secret `new_name` for `username`, waits until the token controller
+    /// has filled it in, then deletes the user's other token Secrets, which invalidates their
+    /// tokens. Returns the names of the deleted Secrets.
+    pub async fn replace_token_secret(&self, username: &str, new_name: &str) -> Result<Vec<String>> {
+        self.require_managed_account(username).await?;
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let old: Vec<String> = self.user_token_secrets(username).await?
+            .iter()
+            .map(|s| s.name_any())
+            .collect();
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert(sa::ANNOTATIONS_NAME.to_string(), username.to_string());
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(new_name.to_string()),
+                labels: Some(user_labels(username)),
+                annotations: Some(annotations),
+                owner_references: self.service_account_owner(username).await?,
+                ..Default::default()
+            },
+            type_: Some(sa::TYPE_TOKEN.to_string()),
+            ..Default::default()
+        };
+        api.create(&PostParams::default(), &secret).await?;
+
+        if let Err(e) = self.wait_for_token(username, new_name).await {
+            // Keep the old tokens working rather than leave the user with none.
+            let _ = api.delete(new_name, &DeleteParams::default()).await;
+            return Err(e);
+        }
+
+        for name in &old {
+            api.delete(name, &DeleteParams::default()).await?;
+        }
+        Ok(old)
+    }
+
+    /// Waits until the token controller has filled in the token Secret `secret_name`.
+    pub async fn wait_for_token(&self, username: &str, secret_name: &str) -> Result<()> {
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        for _ in 0..POLL_ATTEMPTS {
+            let secret = api.get(secret_name).await?;
+            if secret.data.as_ref().is_some_and(|d| d.contains_key("token")) {
+                return Ok(());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Err(anyhow!("Timed out waiting for a token in Secret {}; does ServiceAccount {} exist?", secret_name, username))
+    }
+
+    pub async fn create_role_binding(&self, username: &str, kind: SubjectKind, namespace: &str, permission: &str) -> Result<Applied> {
         let api: Api<RoleBinding> = Api::namespaced(self.client.clone(), namespace);
-        let name = role_binding_name(username, namespace, permission);
+        let name = role_binding_name(&kind.binding_prefix(username), namespace, permission);
         let role_ref = role_name(permission);
 
         let rb = RoleBinding {
             metadata: ObjectMeta {
                 name: Some(name.clone()),
+                labels: Some(user_labels(username)),
                 ..Default::default()
             },
             role_ref: RoleRef {
@@ -200,33 +700,36 @@ impl KubeOps {
                 kind: "ClusterRole".to_string(),
                 name: role_ref,
             },
-            subjects: Some(vec![Subject {
-                kind: "ServiceAccount".to_string(),
-                name: username.to_string(),
-                namespace: Some(self.current_ns.clone()),
-                ..Default::default()
-            }]),
+            subjects: Some(vec![kind.subject(username, &self.current_ns)]),
         };
 
-        api.create(&PostParams::default(), &rb).await?;
-        Ok(name)
+        create_or_accept(&api, &rb, |existing| {
+            if existing.role_ref != rb.role_ref {
+                Some("roleRef")
+            } else if existing.subjects != rb.subjects {
+                Some("subjects")
+            } else {
+                None
+            }
+        }).await
     }
 
-    pub async fn delete_role_binding(&self, username: &str, namespace: &str, permission: &str) -> Result<String> {
+    pub async fn delete_role_binding(&self, username: &str, kind: SubjectKind, namespace: &str, permission: &str) -> Result<String> {
         let api: Api<RoleBinding> = Api::namespaced(self.client.clone(), namespace);
-        let name = role_binding_name(username, namespace, permission);
+        let name = role_binding_name(&kind.binding_prefix(username), namespace, permission);
         api.delete(&name, &DeleteParams::default()).await?;
         Ok(name)
     }
 
-    pub async fn create_cluster_role_binding(&self, username: &str, permission: &str) -> Result<String> {
+    pub async fn create_cluster_role_binding(&self, username: &str, kind: SubjectKind, permission: &str) -> Result<Applied> {
         let api: Api<ClusterRoleBinding> = Api::all(self.client.clone());
-        let name = cluster_role_binding_name(username, permission);
+        let name = cluster_role_binding_name(&kind.binding_prefix(username), permission);
         let role_ref = cluster_role_name(permission);
 
         let crb = ClusterRoleBinding {
             metadata: ObjectMeta {
                 name: Some(name.clone()),
+                labels: Some(user_labels(username)),
                 ..Default::default()
             },
             role_ref: RoleRef {
@@ -234,84 +737,255 @@ impl KubeOps {
                 kind: "ClusterRole".to_string(),
                 name: role_ref,
             },
-            subjects: Some(vec![Subject {
-                kind: "ServiceAccount".to_string(),
-                name: username.to_string(),
-                namespace: Some(self.current_ns.clone()),
-                ..Default::default()
-            }]),
+            subjects: Some(vec![kind.subject(username, &self.current_ns)]),
         };
 
-        api.create(&PostParams::default(), &crb).await?;
-        Ok(name)
+        create_or_accept(&api, &crb, |existing| {
+            if existing.role_ref != crb.role_ref {
+                Some("roleRef")
+            } else if existing.subjects != crb.subjects {
+                Some("subjects")
+            } else {
+                None
+            }
+        }).await
     }
 
-    pub async fn delete_cluster_role_binding(&self, username: &str, permission: &str) -> Result<String> {
+    pub async fn delete_cluster_role_binding(&self, username: &str, kind: SubjectKind, permission: &str) -> Result<String> {
         let api: Api<ClusterRoleBinding> = Api::all(self.client.clone());
-        let name = cluster_role_binding_name(username, permission);
+        let name = cluster_role_binding_name(&kind.binding_prefix(username), permission);
         api.delete(&name, &DeleteParams::default()).await?;
         Ok(name)
     }
 
-    pub async fn get_filtered_role_bindings(&self, username: &str) -> Result<serde_json::Value> {
-        // This iterates all namespaces and checks RBs.
-        let namespaces = self.get_namespaces().await?;
-        let mut ns_permissions = serde_json::Map::new();
-
-        for ns in namespaces {
-            let api: Api<RoleBinding> = Api::namespaced(self.client.clone(), &ns);
-            // This is n+1 but mimicking logic. Optimized implementations would use label selectors if possible
-            if let Ok(list) = api.list(&ListParams::default()).await {
-                for rb in list.items {
-                    let has_user = rb.subjects.as_ref().map_or(false, |subs| {
-                        subs.iter().any(|s| s.name == username)
-                    });
-
-                    if has_user {
-                        let role_ref_name = rb.role_ref.name;
-                        // Attempt to parse permission from role name if it follows convention
-                        let parts: Vec<&str> = role_ref_name.split("___").collect();
-                        let role_short = if parts.len() > 1 { parts[1] } else { &role_ref_name };
-                        
-                        let entry = ns_permissions.entry(ns.clone()).or_insert(json!({}));
-                        if let Some(obj) = entry.as_object_mut() {
-                            obj.insert(role_short.to_string(), json!(true));
-                        }
-                    }
+    /// Whether the User `username` is one KanriGate manages: some binding grants it access, and
+    /// every RoleBinding and ClusterRoleBinding in the cluster that names it carries KanriGate's
+    /// labels for it. A User bound by anyone else is a real cluster identity, not KanriGate's.
+    pub async fn is_managed_user(&self, username: &str) -> Result<bool> {
+        Ok(self.all_subject_bindings(SubjectKind::User).await?.get(username) == Some(&true))
+    }
+
+    /// `subject_bindings` over every RoleBinding and ClusterRoleBinding in the cluster,
+    /// including the ones KanriGate did not create and so does not cache.
+    async fn all_subject_bindings(&self, kind: SubjectKind) -> Result<BTreeMap<String, bool>> {
+        let role_bindings: Api<RoleBinding> = Api::all(self.client.clone());
+        let cluster_role_bindings: Api<ClusterRoleBinding> = Api::all(self.client.clone());
+        let params = ListParams::default();
+        let (rbs, crbs) = tokio::try_join!(role_bindings.list(&params), cluster_role_bindings.list(&params))?;
+        let bindings = rbs.iter().map(|rb| (&rb.subjects, rb.labels()))
+            .chain(crbs.iter().map(|crb| (&crb.subjects, crb.labels())));
+        Ok(subject_bindings(bindings, kind))
+    }
+
+    /// ServiceAccounts KanriGate created for a user that no binding from elsewhere names.
+    pub async fn managed_service_accounts(&self) -> Result<BTreeSet<String>> {
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let params = users_selector();
+        let (accounts, bindings) = tokio::try_join!(
+            async { api.list(&params).await.map_err(anyhow::Error::from) },
+            self.all_subject_bindings(SubjectKind::ServiceAccount),
+        )?;
+        Ok(accounts.iter()
+            .filter(|sa| has_user_labels(sa.labels(), &sa.name_any()))
+            .map(|sa| sa.name_any())
+            .filter(|name| bindings.get(name) != Some(&false))
+            .collect())
+    }
+
+    /// Whether KanriGate created the `kind` subject `username` and every binding that names
+    /// it, so that the bindings KanriGate sees are all the access it has.
+    pub async fn is_managed_subject(&self, kind: SubjectKind, username: &str) -> Result<bool> {
+        match kind {
+            SubjectKind::User => self.is_managed_user(username).await,
+            SubjectKind::ServiceAccount => {
+                let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+                let (account, bindings) = tokio::try_join!(
+                    async { api.get_opt(username).await.map_err(anyhow::Error::from) },
+                    self.all_subject_bindings(SubjectKind::ServiceAccount),
+                )?;
+                Ok(account.is_some_and(|sa| has_user_labels(sa.labels(), username)) && bindings.get(username) != Some(&false))
+            }
+        }
+    }
+
+    /// Fails with `NotManaged` unless KanriGate created the ServiceAccount `username` for that
+    /// user. Credentials for any other account in the namespace, KanriGate's own included,
+    /// would carry permissions that KanriGate neither granted nor can see.
+    async fn require_managed_account(&self, username: &str) -> Result<()> {
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        match api.get_opt(username).await? {
+            Some(account) if has_user_labels(account.labels(), username) => Ok(()),
+            _ => Err(NotManaged(format!("ServiceAccount {} does not exist or is not managed by KanriGate", username)).into()),
+        }
+    }
+
+    pub async fn get_filtered_role_bindings(&self, username: &str, kind: SubjectKind) -> Result<serde_json::Value> {
+        let api: Api<RoleBinding> = Api::all(self.client.clone());
+        let list = api.list(&user_selector(username)).await?;
+        Ok(role_binding_permissions(&list.items, kind, username))
+    }
+
+    pub async fn get_filtered_cluster_role_bindings(&self, username: &str, kind: SubjectKind) -> Result<serde_json::Value> {
+        let api: Api<ClusterRoleBinding> = Api::all(self.client.clone());
+        let list = api.list(&user_selector(username)).await?;
+        Ok(cluster_role_binding_permissions(&list.items, kind, username))
+    }
+
+    /// Deletes every KanriGate-managed object of `username`: bindings first, so access ends
+    /// before anything else goes, then certificate requests, token Secrets and the
+    /// ServiceAccount. Token Secrets owned by the account are left to the garbage collector,
+    /// and are reported once the account's own deletion is known. With `dry_run`, each
+    /// deletion is only validated by the API server.
+    pub async fn delete_user(&self, username: &str, kind: SubjectKind, dry_run: bool) -> Result<UserDeletion> {
+        let params = DeleteParams { dry_run, ..Default::default() };
+        let mut objects = Vec::new();
+
+        let api: Api<RoleBinding> = Api::all(self.client.clone());
+        for rb in api.list(&user_selector(username)).await?.items {
+            if !rb.subjects.iter().flatten().any(|s| kind.matches(s, username)) {
+                continue;
+            }
+            let namespace = rb.namespace().unwrap_or_default();
+            let name = rb.name_any();
+            let api: Api<RoleBinding> = Api::namespaced(self.client.clone(), &namespace);
+            let result = api.delete(&name, &params).await;
+            objects.push(DeletedObject::from_result("RoleBinding", Some(namespace), name, result));
+        }
+
+        let api: Api<ClusterRoleBinding> = Api::all(self.client.clone());
+        for crb in api.list(&user_selector(username)).await?.items {
+            if !crb.subjects.iter().flatten().any(|s| kind.matches(s, username)) {
+                continue;
+            }
+            let name = crb.name_any();
+            let result = api.delete(&name, &params).await;
+            objects.push(DeletedObject::from_result("ClusterRoleBinding", None, name, result));
+        }
+
+        if kind == SubjectKind::User {
+            // Issued certificates stay valid until they expire; this only removes the requests.
+            let api: Api<CertificateSigningRequest> = Api::all(self.client.clone());
+            for csr in api.list(&user_selector(username)).await?.items {
+                if csr.annotations().get(sa::ANNOTATIONS_USER).is_none_or(|u| u != username) {
+                    continue;
                 }
+                let name = csr.name_any();
+                let result = api.delete(&name, &params).await;
+                objects.push(DeletedObject::from_result("CertificateSigningRequest", None, name, result));
+            }
+            return Ok(UserDeletion { username: username.to_string(), kind, dry_run, objects });
+        }
+
+        let accounts: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        // An account without the label was not created through KanriGate; leave it alone.
+        let account = accounts.get_opt(username).await?
+            .filter(|a| a.labels().get(labels::MANAGED_BY).is_some_and(|v| v == labels::MANAGED_BY_VALUE));
+        let account_uid = account.as_ref().and_then(|a| a.uid());
+
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let mut owned = Vec::new();
+        for secret in self.user_token_secrets(username).await? {
+            let name = secret.name_any();
+            if account_uid.as_ref().is_some_and(|uid| secret.owner_references().iter().any(|o| &o.uid == uid)) {
+                owned.push(name);
+                continue;
             }
+            let result = api.delete(&name, &params).await;
+            objects.push(DeletedObject::from_result("Secret", Some(self.current_ns.clone()), name, result));
         }
-        Ok(serde_json::Value::Object(ns_permissions))
+
+        if account.is_some() {
+            let result = accounts.delete(username, &params).await;
+            let account = DeletedObject::from_result("ServiceAccount", Some(self.current_ns.clone()), username.to_string(), result);
+            objects.extend(owned.into_iter().map(|name| DeletedObject::owned_by(&account, name, dry_run)));
+            objects.push(account);
+        }
+
+        Ok(UserDeletion { username: username.to_string(), kind, dry_run, objects })
     }
 
-    pub async fn get_filtered_cluster_role_bindings(&self, username: &str) -> Result<serde_json::Value> {
+    /// Labels what older versions created without labels: template bindings, token Secrets,
+    /// the ServiceAccounts those belong to, client certificate requests, and the KanriGate
+    /// state Secrets named in `state_secrets`. Objects that already have the labels are left
+    /// alone, so running it again is harmless.
+    pub async fn label_managed_objects(&self, state_secrets: &[String]) -> Result<LabelMigration> {
+        let mut migrated = LabelMigration::default();
+        // ServiceAccounts that own a token Secret or are bound to a template.
+        let mut users = BTreeSet::new();
+
+        let api: Api<RoleBinding> = Api::all(self.client.clone());
+        for rb in api.list(&ListParams::default()).await?.items {
+            let subject = match template_binding_subject(&rb.name_any(), &rb.role_ref, &rb.subjects) {
+                Some(subject) => subject,
+                None => continue,
+            };
+            if subject.kind == "ServiceAccount" && subject.namespace.as_deref() == Some(self.current_ns.as_str()) {
+                users.insert(subject.name.clone());
+            }
+            let api: Api<RoleBinding> = Api::namespaced(self.client.clone(), &rb.namespace().unwrap_or_default());
+            if ensure_labels(&api, &rb, user_labels(&subject.name)).await? {
+                migrated.role_bindings += 1;
+            }
+        }
+
         let api: Api<ClusterRoleBinding> = Api::all(self.client.clone());
-        let list = api.list(&ListParams::default()).await?;
-        let mut cluster_permissions = serde_json::Map::new();
-
-        for crb in list.items {
-            let has_user = crb.subjects.as_ref().map_or(false, |subs| {
-                subs.iter().any(|s| s.name == username)
-            });
-
-            if has_user {
-                let role_ref_name = crb.role_ref.name;
-                let parts: Vec<&str> = role_ref_name.split("___").collect();
-                let role_short = if parts.len() > 1 { parts[1] } else { &role_ref_name };
-                cluster_permissions.insert(role_short.to_string(), json!(true));
+        for crb in api.list(&ListParams::default()).await?.items {
+            let subject = match template_binding_subject(&crb.name_any(), &crb.role_ref, &crb.subjects) {
+                Some(subject) => subject,
+                None => continue,
+            };
+            if subject.kind == "ServiceAccount" && subject.namespace.as_deref() == Some(self.current_ns.as_str()) {
+                users.insert(subject.name.clone());
+            }
+            if ensure_labels(&api, &crb, user_labels(&subject.name)).await? {
+                migrated.cluster_role_bindings += 1;
+            }
+        }
+
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
+        for secret in api.list(&ListParams::default()).await?.items {
+            let labels = match secret_owner(&secret) {
+                Some(owner) if secret.type_.as_deref() == Some(sa::TYPE_TOKEN) => {
+                    users.insert(owner.clone());
+                    user_labels(owner)
+                }
+                _ if state_secrets.contains(&secret.name_any()) => managed_labels(),
+                _ => continue,
+            };
+            if ensure_labels(&api, &secret, labels).await? {
+                migrated.secrets += 1;
+            }
+        }
+
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        for account in api.list(&ListParams::default()).await?.items {
+            let name = account.name_any();
+            if users.contains(&name) && ensure_labels(&api, &account, user_labels(&name)).await? {
+                migrated.service_accounts += 1;
             }
         }
-        Ok(serde_json::Value::Object(cluster_permissions))
+
+        let api: Api<CertificateSigningRequest> = Api::all(self.client.clone());
+        for csr in api.list(&ListParams::default()).await?.items {
+            let user = match csr.annotations().get(sa::ANNOTATIONS_USER) {
+                Some(user) => user.clone(),
+                None => continue,
+            };
+            if ensure_labels(&api, &csr, user_labels(&user)).await? {
+                migrated.certificate_signing_requests += 1;
+            }
+        }
+
+        Ok(migrated)
     }
 
-    pub async fn generate_k8s_config(&self, username: &str, namespace: &str, cluster_name: &str, control_plane: &str) -> Result<String> {
+    /// Token and CA certificate from the user's `kubernetes.io/service-account-token` Secret.
+    async fn legacy_token(&self, username: &str) -> Result<(String, Vec<u8>)> {
         let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.current_ns);
-        let list = api.list(&ListParams::default()).await?;
+        let list = api.list(&user_selector(username)).await?;
         
         let secret = list.items.iter().find(|s| {
-            s.metadata.annotations.as_ref()
-                .and_then(|a| a.get(sa::ANNOTATIONS_NAME))
-                .map_or(false, |v| v == username)
+            secret_owner(s).is_some_and(|v| v == username)
         }).ok_or_else(|| anyhow!("No secret found for user {}", username))?;
 
         let data = secret.data.as_ref().ok_or_else(|| anyhow!("Secret has no data"))?;
@@ -319,38 +993,211 @@ impl KubeOps {
         let token_bytes = &data.get("token").ok_or_else(|| anyhow!("Secret missing token"))?.0;
         let ca_cert_bytes = &data.get("ca.crt").ok_or_else(|| anyhow!("Secret missing ca.crt"))?.0;
 
-        let token = String::from_utf8(token_bytes.clone())?;
-        let ca_cert_b64 = BASE64.encode(ca_cert_bytes);
-
-        let context_name = format!("{}@{}", username, cluster_name);
-
-        let config = KubeConfig {
-            api_version: "v1".to_string(),
-            kind: "Config".to_string(),
-            clusters: vec![NamedCluster {
-                name: cluster_name.to_string(),
-                cluster: ClusterConfig {
-                    certificate_authority_data: ca_cert_b64,
-                    server: control_plane.to_string(),
-                },
-            }],
-            contexts: vec![NamedContext {
-                name: context_name.clone(),
-                context: ContextConfig {
-                    cluster: cluster_name.to_string(),
-                    namespace: namespace.to_string(),
-                    user: username.to_string(),
-                },
-            }],
-            current_context: context_name,
-            users: vec![NamedUser {
-                name: username.to_string(),
-                user: UserConfig {
-                    token,
-                },
-            }],
+        Ok((String::from_utf8(token_bytes.clone())?, ca_cert_bytes.clone()))
+    }
+
+    /// A bound token for the user's ServiceAccount from the TokenRequest API, with its expiry.
+    async fn bound_token(&self, username: &str, expiration_seconds: i64, audiences: &[String]) -> Result<(String, DateTime<Utc>)> {
+        let api: Api<ServiceAccount> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let request = TokenRequest {
+            spec: TokenRequestSpec {
+                // An empty list means the API server's own audiences.
+                audiences: audiences.to_vec(),
+                expiration_seconds: Some(expiration_seconds),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = api.create_token_request(username, &PostParams::default(), &request).await?;
+        let status = response.status.ok_or_else(|| anyhow!("TokenRequest for {} returned no token", username))?;
+        Ok((status.token, status.expiration_timestamp.0))
+    }
+
+    /// The cluster CA that every namespace receives in the `kube-root-ca.crt` ConfigMap.
+    async fn cluster_ca(&self) -> Result<Vec<u8>> {
+        let api: Api<ConfigMap> = Api::namespaced(self.client.clone(), &self.current_ns);
+        let cm = api.get(ROOT_CA_CONFIGMAP).await?;
+        cm.data.as_ref()
+            .and_then(|d| d.get("ca.crt"))
+            .map(|ca| ca.as_bytes().to_vec())
+            .ok_or_else(|| anyhow!("ConfigMap {} missing ca.crt", ROOT_CA_CONFIGMAP))
+    }
+
+    pub async fn generate_k8s_config(
+        &self,
+        username: &str,
+        namespace: &str,
+        cluster_name: &str,
+        control_plane: &str,
+        token_source: &KubeconfigToken,
+    ) -> Result<String> {
+        self.require_managed_account(username).await?;
+        let (token, ca_cert_bytes, expires_at) = match token_source {
+            KubeconfigToken::Legacy => {
+                let (token, ca) = self.legacy_token(username).await?;
+                (token, ca, None)
+            }
+            KubeconfigToken::Bound { expiration_seconds, audiences } => {
+                let (token, expires_at) = self.bound_token(username, *expiration_seconds, audiences).await?;
+                (token, self.cluster_ca().await?, Some(expires_at))
+            }
+        };
+        let user = UserConfig {
+            token: Some(token),
+            ..Default::default()
         };
+        let yaml = kubeconfig_yaml(username, namespace, cluster_name, control_plane, &ca_cert_bytes, user)?;
+        Ok(match expires_at {
+            Some(at) => format!("# Token expires at {}\n{}", at.to_rfc3339(), yaml),
+            None => yaml,
+        })
+    }
+
+    /// Has the `kubernetes.io/kube-apiserver-client` signer issue a certificate for the `User`
+    /// `username` in `groups`, approving the request itself. Returns the certificate and key PEM
+    /// and the certificate's expiry.
+    async fn client_certificate(&self, username: &str, groups: &[String], expiration_seconds: i64) -> Result<(Vec<u8>, String, DateTime<Utc>)> {
+        let key = certificates::client_key(username, groups)?;
+        let api: Api<CertificateSigningRequest> = Api::all(self.client.clone());
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert(sa::ANNOTATIONS_USER.to_string(), username.to_string());
+        let csr = CertificateSigningRequest {
+            metadata: ObjectMeta {
+                generate_name: Some("kanrigate-".to_string()),
+                labels: Some(user_labels(username)),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            spec: CertificateSigningRequestSpec {
+                request: ByteString(key.csr_pem.into_bytes()),
+                signer_name: CLIENT_SIGNER.to_string(),
+                expiration_seconds: Some(i32::try_from(expiration_seconds)?),
+                usages: Some(vec!["client auth".to_string()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let name = api.create(&PostParams::default(), &csr).await?.name_any();
+
+        let approval = json!({
+            "status": {
+                "conditions": [{
+                    "type": "Approved",
+                    "status": "True",
+                    "reason": "KanriGateApproved",
+                    "message": format!("Approved by KanriGate for {}", username),
+                }]
+            }
+        });
+        api.patch_approval(&name, &PatchParams::default(), &Patch::Merge(&approval)).await?;
+
+        for _ in 0..POLL_ATTEMPTS {
+            let status = api.get(&name).await?.status.unwrap_or_default();
+            if let Some(ByteString(certificate)) = status.certificate.filter(|c| !c.0.is_empty()) {
+                let expires_at = certificate_expiry(&certificate)?;
+                return Ok((certificate, key.private_key_pem, expires_at));
+            }
+            if let Some(c) = status.conditions.iter().flatten().find(|c| c.type_ == "Denied" || c.type_ == "Failed") {
+                return Err(anyhow!("CertificateSigningRequest {} {}: {}", name, c.type_.to_lowercase(), c.message.clone().unwrap_or_default()));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Err(anyhow!("Timed out waiting for CertificateSigningRequest {} to be signed", name))
+    }
+
+    pub async fn generate_k8s_cert_config(
+        &self,
+        username: &str,
+        groups: &[String],
+        namespace: &str,
+        cluster_name: &str,
+        control_plane: &str,
+        expiration_seconds: i64,
+    ) -> Result<String> {
+        let (certificate, private_key, expires_at) = self.client_certificate(username, groups, expiration_seconds).await?;
+        let user = UserConfig {
+            client_certificate_data: Some(BASE64.encode(certificate)),
+            client_key_data: Some(BASE64.encode(private_key)),
+            ..Default::default()
+        };
+        let yaml = kubeconfig_yaml(username, namespace, cluster_name, control_plane, &self.cluster_ca().await?, user)?;
+        Ok(format!("# Certificate expires at {}\n{}", expires_at.to_rfc3339(), yaml))
+    }
+}
+
+fn certificate_expiry(pem: &[u8]) -> Result<DateTime<Utc>> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem).map_err(|e| anyhow!("Invalid certificate PEM: {}", e))?;
+    let certificate = pem.parse_x509().map_err(|e| anyhow!("Invalid certificate: {}", e))?;
+    DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("Certificate expiry out of range"))
+}
+
+fn kubeconfig_yaml(username: &str, namespace: &str, cluster_name: &str, control_plane: &str, ca_cert: &[u8], user: UserConfig) -> Result<String> {
+    let ca_cert_b64 = BASE64.encode(ca_cert);
+
+    let context_name = format!("{}@{}", username, cluster_name);
+
+    let config = KubeConfig {
+        api_version: "v1".to_string(),
+        kind: "Config".to_string(),
+        clusters: vec![NamedCluster {
+            name: cluster_name.to_string(),
+            cluster: ClusterConfig {
+                certificate_authority_data: ca_cert_b64,
+                server: control_plane.to_string(),
+            },
+        }],
+        contexts: vec![NamedContext {
+            name: context_name.clone(),
+            context: ContextConfig {
+                cluster: cluster_name.to_string(),
+                namespace: namespace.to_string(),
+                user: username.to_string(),
+            },
+        }],
+        current_context: context_name,
+        users: vec![NamedUser {
+            name: username.to_string(),
+            user,
+        }],
+    };
+
+    serde_yaml::to_string(&config).map_err(|e| anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(outcome: DeletionOutcome) -> DeletedObject {
+        DeletedObject {
+            kind: "ServiceAccount".to_string(),
+            namespace: Some("kanrigate".to_string()),
+            name: "alice".to_string(),
+            outcome,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn owned_secret_is_collected_only_when_the_account_is_deleted() {
+        let secret = DeletedObject::owned_by(&account(DeletionOutcome::Deleted), "alice-token".to_string(), false);
+        assert_eq!(secret.outcome, DeletionOutcome::Collected);
+        assert_eq!(secret.namespace.as_deref(), Some("kanrigate"));
+        assert!(secret.error.is_none());
+
+        let secret = DeletedObject::owned_by(&account(DeletionOutcome::Failed), "alice-token".to_string(), false);
+        assert_eq!(secret.outcome, DeletionOutcome::Failed);
+        assert!(secret.error.is_some());
+    }
+
+    #[test]
+    fn owned_secret_is_skipped_on_a_dry_run() {
+        let secret = DeletedObject::owned_by(&account(DeletionOutcome::Deleted), "alice-token".to_string(), true);
+        assert_eq!(secret.outcome, DeletionOutcome::Skipped);
 
-        serde_yaml::to_string(&config).map_err(|e| anyhow!(e))
+        let secret = DeletedObject::owned_by(&account(DeletionOutcome::Failed), "alice-token".to_string(), true);
+        assert_eq!(secret.outcome, DeletionOutcome::Failed);
     }
 }
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::time::Duration;
+use crate::config::BaseConfig;
+use crate::services::authenticator::{role_from_groups, AuthenticatedUser, Authenticator, IdentitySource};
+
+/// LDAP result code for a failed simple bind (RFC 4511 section 4.1.9).
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+/// Authenticates by binding to the directory as the user, then maps the user's groups
+/// to a KanriGate role. Users outside every configured group are rejected.
+pub struct LdapAuthenticator {
+    url: String,
+    starttls: bool,
+    tls_insecure: bool,
+    bind_dn_template: String,
+    base_dn: String,
+    user_filter: String,
+    group_base_dn: String,
+    group_filter: String,
+    admin_groups: Vec<String>,
+    operator_groups: Vec<String>,
+    viewer_groups: Vec<String>,
+}
+
+impl LdapAuthenticator {
+    pub fn from_config(config: &BaseConfig) -> Result<Self> {
+        let url = config.ldap_url.clone()
+            .ok_or_else(|| anyhow!("ldap_url is required when auth_backend is 'ldap'"))?;
+        let bind_dn_template = config.ldap_bind_dn_template.clone()
+            .ok_or_else(|| anyhow!("ldap_bind_dn_template is required when auth_backend is 'ldap'"))?;
+        let base_dn = config.ldap_base_dn.clone()
+            .ok_or_else(|| anyhow!("ldap_base_dn is required when auth_backend is 'ldap'"))?;
+
+        if config.ldap_admin_groups.is_empty() && config.ldap_operator_groups.is_empty() && config.ldap_viewer_groups.is_empty() {
+            tracing::warn!("LDAP is enabled but no ldap_*_groups are configured; every login will be rejected");
+        }
+
+        Ok(Self {
+            url,
+            starttls: config.ldap_starttls,
+            tls_insecure: config.ldap_tls_insecure,
+            bind_dn_template,
+            group_base_dn: config.ldap_group_base_dn.clone().unwrap_or_else(|| base_dn.clone()),
+            base_dn,
+            user_filter: config.ldap_user_filter.clone(),
+            group_filter: config.ldap_group_filter.clone(),
+            admin_groups: config.ldap_admin_groups.clone(),
+            operator_groups: config.ldap_operator_groups.clone(),
+            viewer_groups: config.ldap_viewer_groups.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Authenticator for LdapAuthenticator {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>> {
+        // An empty password would turn the bind into an unauthenticated bind, which most servers accept.
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(5))
+            .set_starttls(self.starttls)
+            .set_no_tls_verify(self.tls_insecure);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(conn);
+
+        let bind_dn = self.bind_dn_template.replace("{username}", &dn_escape(username));
+        let bind = ldap.simple_bind(&bind_dn, password).await?;
+        if bind.rc == LDAP_INVALID_CREDENTIALS {
+            return Ok(None);
+        }
+        bind.success()?;
+
+        let user_filter = self.user_filter.replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .search(&self.base_dn, Scope::Subtree, &user_filter, vec!["dn"])
+            .await?
+            .success()?;
+        let user_dn = entries.into_iter()
+            .next()
+            .map(|e| SearchEntry::construct(e).dn)
+            .unwrap_or(bind_dn);
+
+        let group_filter = self.group_filter
+            .replace("{user_dn}", &ldap_escape(user_dn.as_str()))
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .search(&self.group_base_dn, Scope::Subtree, &group_filter, vec!["cn"])
+            .await?
+            .success()?;
+        let _ = ldap.unbind().await;
+
+        // Groups are matched by either their full DN or their `cn`.
+        let mut groups = Vec::new();
+        for entry in entries {
+            let entry = SearchEntry::construct(entry);
+            groups.extend(entry.attrs.get("cn").cloned().unwrap_or_default());
+            groups.push(entry.dn);
+        }
+
+        let role = match role_from_groups(&groups, &self.admin_groups, &self.operator_groups, &self.viewer_groups) {
+            Some(role) => role,
+            None => {
+                tracing::warn!(user = %username, groups = ?groups, "LDAP user is not a member of any KanriGate group");
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(AuthenticatedUser {
+            username: username.to_string(),
+            source: IdentitySource::Ldap,
+            role,
+            namespaces: None,
+            groups,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The `#[ignore]`d tests need the directory in `tests/ldap`; see its docker-compose.yml.
+    use super::*;
+    use crate::services::authenticator::Role;
+
+    const BASE_DN: &str = "dc=example,dc=org";
+
+    fn authenticator(url: &str, starttls: bool, tls_insecure: bool) -> LdapAuthenticator {
+        LdapAuthenticator {
+            url: url.to_string(),
+            starttls,
+            tls_insecure,
+            bind_dn_template: format!("uid={{username}},ou=people,{}", BASE_DN),
+            base_dn: BASE_DN.to_string(),
+            user_filter: "(uid={username})".to_string(),
+            group_base_dn: format!("ou=groups,{}", BASE_DN),
+            group_filter: "(|(member={user_dn})(uniqueMember={user_dn}))".to_string(),
+            admin_groups: vec!["kanrigate-admins".to_string()],
+            operator_groups: vec![format!("cn=kanrigate-operators,ou=groups,{}", BASE_DN)],
+            viewer_groups: Vec::new(),
+        }
+    }
+
+    fn directory() -> LdapAuthenticator {
+        let url = std::env::var("KANRIGATE_LDAP_TEST_URL")
+            .expect("KANRIGATE_LDAP_TEST_URL must point at the directory from tests/ldap/docker-compose.yml");
+        authenticator(&url, false, false)
+    }
+
+    #[tokio::test]
+    async fn empty_credentials_are_rejected_without_contacting_the_directory() {
+        // Nothing listens on port 1, so any connection attempt would be an error.
+        let ldap = authenticator("ldap://127.0.0.1:1", false, false);
+        assert!(ldap.authenticate("alice", "").await.unwrap().is_none());
+        assert!(ldap.authenticate("", "alice-password").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the OpenLDAP container in tests/ldap"]
+    async fn admin_group_member_binds_as_admin() {
+        let user = directory().authenticate("alice", "alice-password").await.unwrap().unwrap();
+        assert_eq!(user.role, Role::Admin);
+        assert_eq!(user.source, IdentitySource::Ldap);
+        assert!(user.groups.contains(&"kanrigate-admins".to_string()), "{:?}", user.groups);
+        assert!(user.groups.contains(&format!("cn=kanrigate-admins,ou=groups,{}", BASE_DN)), "{:?}", user.groups);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the OpenLDAP container in tests/ldap"]
+    async fn unique_member_group_is_matched_by_dn() {
+        let user = directory().authenticate("bob", "bob-password").await.unwrap().unwrap();
+        assert_eq!(user.role, Role::Operator);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the OpenLDAP container in tests/ldap"]
+    async fn user_outside_every_group_is_rejected() {
+        assert!(directory().authenticate("carol", "carol-password").await.unwrap().is_none());
+
+        let mut ldap = directory();
+        ldap.admin_groups = vec!["other-team".to_string()];
+        assert!(ldap.authenticate("alice", "alice-password").await.unwrap().is_none());
+        assert_eq!(ldap.authenticate("carol", "carol-password").await.unwrap().unwrap().role, Role::Admin);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the OpenLDAP container in tests/ldap"]
+    async fn wrong_password_or_unknown_user_is_rejected() {
+        let ldap = directory();
+        assert!(ldap.authenticate("alice", "bob-password").await.unwrap().is_none());
+        assert!(ldap.authenticate("mallory", "alice-password").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the OpenLDAP container in tests/ldap"]
+    async fn starttls_upgrades_the_connection() {
+        let url = directory().url;
+        // The container's certificate is self-signed, so only an unverified upgrade succeeds.
+        let user = authenticator(&url, true, true).authenticate("alice", "alice-password").await.unwrap().unwrap();
+        assert_eq!(user.role, Role::Admin);
+        assert!(authenticator(&url, true, false).authenticate("alice", "alice-password").await.is_err());
+    }
+}
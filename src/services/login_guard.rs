@@ -0,0 +1,176 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::config::BaseConfig;
+use crate::models::LockoutInfo;
+
+#[derive(Debug, Clone)]
+struct Attempts {
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// Counts failed logins per client IP and per username and locks a key out, with
+/// exponentially growing lockouts, once it passes its threshold.
+///
+/// Counters are kept in memory, so each replica enforces its own limits.
+pub struct LoginGuard {
+    entries: Mutex<HashMap<String, Attempts>>,
+    max_user_attempts: u32,
+    max_ip_attempts: u32,
+    base_lockout: Duration,
+    max_lockout: Duration,
+}
+
+pub fn user_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+impl LoginGuard {
+    pub fn from_config(config: &BaseConfig) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_user_attempts: config.login_max_attempts_per_user,
+            max_ip_attempts: config.login_max_attempts_per_ip,
+            base_lockout: Duration::seconds(config.login_lockout_base_seconds),
+            max_lockout: Duration::seconds(config.login_lockout_max_seconds),
+        }
+    }
+
+    fn threshold(&self, key: &str) -> u32 {
+        if key.starts_with("ip:") { self.max_ip_attempts } else { self.max_user_attempts }
+    }
+
+    /// Time left on the longest active lockout among `keys`.
+    pub fn retry_after(&self, keys: &[&str]) -> Option<Duration> {
+        let now = Utc::now();
+        let entries = self.entries.lock().expect("login guard lock poisoned");
+        keys.iter()
+            .filter_map(|key| entries.get(*key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Records a failed attempt and returns the lockout it triggered, if any.
+    pub fn record_failure(&self, key: &str) -> Option<Duration> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().expect("login guard lock poisoned");
+
+        // Counters are forgotten once a key has been quiet for the longest lockout.
+        let max_lockout = self.max_lockout;
+        entries.retain(|_, a| a.locked_until.is_some_and(|u| u > now) || now - a.last_failure < max_lockout);
+
+        let attempts = entries.entry(key.to_string()).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        attempts.failures += 1;
+        attempts.last_failure = now;
+
+        let threshold = self.threshold(key);
+        if attempts.failures < threshold {
+            return None;
+        }
+        let doublings = (attempts.failures - threshold).min(16);
+        let lockout = (self.base_lockout * 2i32.pow(doublings)).min(self.max_lockout);
+        attempts.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    pub fn record_success(&self, key: &str) {
+        self.entries.lock().expect("login guard lock poisoned").remove(key);
+    }
+
+    pub fn lockouts(&self) -> Vec<LockoutInfo> {
+        let now = Utc::now();
+        let entries = self.entries.lock().expect("login guard lock poisoned");
+        let mut lockouts: Vec<LockoutInfo> = entries.iter()
+            .filter_map(|(key, a)| {
+                let until = a.locked_until.filter(|u| *u > now)?;
+                Some(LockoutInfo { key: key.clone(), failures: a.failures, locked_until: until })
+            })
+            .collect();
+        lockouts.sort_by(|a, b| a.key.cmp(&b.key));
+        lockouts
+    }
+
+    /// Forgets the counter for `key`; returns whether there was one.
+    pub fn clear(&self, key: &str) -> bool {
+        self.entries.lock().expect("login guard lock poisoned").remove(key).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> LoginGuard {
+        LoginGuard {
+            entries: Mutex::new(HashMap::new()),
+            max_user_attempts: 3,
+            max_ip_attempts: 5,
+            base_lockout: Duration::seconds(30),
+            max_lockout: Duration::seconds(300),
+        }
+    }
+
+    #[test]
+    fn lockout_starts_at_the_threshold_and_doubles_up_to_the_cap() {
+        let guard = guard();
+        let key = user_key("alice");
+        assert_eq!(guard.record_failure(&key), None);
+        assert_eq!(guard.record_failure(&key), None);
+        let lockouts: Vec<i64> = (0..5).filter_map(|_| guard.record_failure(&key)).map(|d| d.num_seconds()).collect();
+        assert_eq!(lockouts, [30, 60, 120, 240, 300]);
+    }
+
+    #[test]
+    fn ip_and_user_keys_have_their_own_thresholds() {
+        let guard = guard();
+        let ip = ip_key("10.0.0.1");
+        assert!((0..4).all(|_| guard.record_failure(&ip).is_none()));
+        assert_eq!(guard.record_failure(&ip), Some(Duration::seconds(30)));
+        assert_eq!(guard.record_failure(&user_key("alice")), None);
+    }
+
+    #[test]
+    fn retry_after_reports_the_longest_lockout() {
+        let guard = guard();
+        let (user, ip) = (user_key("alice"), ip_key("10.0.0.1"));
+        assert!(guard.retry_after(&[&user, &ip]).is_none());
+        for _ in 0..4 {
+            guard.record_failure(&user);
+        }
+        for _ in 0..5 {
+            guard.record_failure(&ip);
+        }
+        let left = guard.retry_after(&[&user, &ip]).unwrap();
+        assert!(left > Duration::seconds(30) && left <= Duration::seconds(60), "{}", left);
+    }
+
+    #[test]
+    fn success_and_clear_reset_the_counter() {
+        let guard = guard();
+        let key = user_key("Alice");
+        assert_eq!(key, user_key("alice"));
+        for _ in 0..3 {
+            guard.record_failure(&key);
+        }
+        assert_eq!(guard.lockouts().len(), 1);
+        assert!(guard.clear(&key));
+        assert!(!guard.clear(&key));
+        assert!(guard.lockouts().is_empty());
+
+        guard.record_failure(&key);
+        guard.record_failure(&key);
+        guard.record_success(&key);
+        assert_eq!(guard.record_failure(&key), None);
+    }
+}
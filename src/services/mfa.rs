@@ -0,0 +1,328 @@
+use anyhow::{anyhow, bail, Result};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use kube::Client;
+use rand::RngCore;
+use ring::{aead, hmac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::config::BaseConfig;
+use crate::services::secret_store::JsonSecretStore;
+
+const ISSUER: &str = "KanriGate";
+const SECRET_BYTES: usize = 20;
+const DIGITS: u32 = 6;
+const PERIOD_SECONDS: i64 = 30;
+/// Accept codes from one step either side of now to tolerate clock drift.
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+/// A user's TOTP enrollment. The shared secret is encrypted with AES-256-GCM and
+/// recovery codes are kept as SHA-256 digests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaEnrollment {
+    pub username: String,
+    secret: String,
+    /// False until the user has proven the authenticator works by entering a code.
+    pub enabled: bool,
+    #[serde(default)]
+    recovery_codes: Vec<String>,
+    /// Last accepted time step, so a code cannot be replayed.
+    #[serde(default)]
+    last_step: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What the user needs to add KanriGate to an authenticator app.
+pub struct MfaSecret {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// RFC 6238 TOTP (HMAC-SHA1, 6 digits, 30 seconds) for a base secret and time step.
+fn totp(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let bytes = digest.as_ref();
+
+    // Dynamic truncation, RFC 4226 section 5.3.
+    let offset = (bytes[bytes.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([bytes[offset] & 0x7f, bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// The step within `SKEW_STEPS` of `now` that `code` is valid for, skipping steps up to
+/// `last_step`, which were already used.
+fn matching_step(secret: &[u8], code: &str, now: i64, last_step: i64) -> Option<i64> {
+    ((now - SKEW_STEPS)..=(now + SKEW_STEPS))
+        .filter(|step| *step > last_step)
+        .find(|step| constant_time_eq(totp(secret, *step).as_bytes(), code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hex_sha256(value: &str) -> String {
+    Sha256::digest(value.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Recovery codes are compared case-insensitively and without separators.
+fn digest(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase();
+    hex_sha256(&normalized)
+}
+
+/// Fresh recovery codes, shown to the user once, in the form `ABCD-EFGH`.
+fn recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let encoded = BASE32_NOPAD.encode(&bytes);
+            format!("{}-{}", &encoded[..4], &encoded[4..])
+        })
+        .collect()
+}
+
+impl MfaEnrollment {
+    /// Consumes the recovery code `code`; returns whether it was one of the unused codes.
+    fn use_recovery_code(&mut self, code: &str) -> bool {
+        let hashed = digest(code);
+        let before = self.recovery_codes.len();
+        self.recovery_codes.retain(|c| !constant_time_eq(c.as_bytes(), hashed.as_bytes()));
+        self.recovery_codes.len() < before
+    }
+}
+
+/// Secret data keys are limited to `[-._a-zA-Z0-9]`; other usernames (e.g. from LDAP) are hashed.
+fn record_key(username: &str) -> String {
+    if !username.is_empty() && username.chars().all(|c| c.is_ascii_alphanumeric() || "-._".contains(c)) {
+        username.to_string()
+    } else {
+        format!("sha256-{}", hex_sha256(username))
+    }
+}
+
+/// TOTP enrollments for password logins, stored in the `mfa_secret` Secret.
+pub struct MfaStore {
+    store: JsonSecretStore<MfaEnrollment>,
+    key: aead::LessSafeKey,
+}
+
+impl MfaStore {
+    pub fn new(client: Client, config: &BaseConfig) -> Self {
+        // Without a dedicated key, derive one from the JWT secret; rotating that secret then requires re-enrollment.
+        let material = config.mfa_encryption_key.as_deref()
+            .map(|k| Sha256::digest(k.as_bytes()))
+            .unwrap_or_else(|| Sha256::new().chain_update(b"kanrigate-mfa:").chain_update(config.jwt_secret.as_bytes()).finalize());
+        let key = aead::LessSafeKey::new(
+            aead::UnboundKey::new(&aead::AES_256_GCM, &material).expect("SHA-256 output is a valid AES-256 key"),
+        );
+        Self {
+            store: JsonSecretStore::new(client, &config.mfa_secret),
+            key,
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow!("Failed to encrypt MFA secret"))?;
+        Ok(BASE64.encode([nonce.as_slice(), &sealed].concat()))
+    }
+
+    fn decrypt(&self, stored: &str) -> Result<Vec<u8>> {
+        let raw = BASE64.decode(stored)?;
+        if raw.len() < aead::NONCE_LEN {
+            bail!("Stored MFA secret is truncated");
+        }
+        let (nonce, sealed) = raw.split_at(aead::NONCE_LEN);
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("Invalid MFA nonce"))?;
+        let mut buffer = sealed.to_vec();
+        let plaintext = self.key
+            .open_in_place(nonce, aead::Aad::empty(), &mut buffer)
+            .map_err(|_| anyhow!("Failed to decrypt MFA secret; was the encryption key changed?"))?;
+        Ok(plaintext.to_vec())
+    }
+
+    pub async fn get(&self, username: &str) -> Result<Option<MfaEnrollment>> {
+        let (mut records, _) = self.store.load().await?;
+        Ok(records.remove(&record_key(username)))
+    }
+
+    /// Generates a new secret for `username`, replacing any enrollment that is not yet enabled.
+    pub async fn begin_enrollment(&self, username: &str) -> Result<MfaSecret> {
+        let (records, rv) = self.store.load().await?;
+        let key = record_key(username);
+        if records.get(&key).is_some_and(|e| e.enabled) {
+            bail!("MFA is already enabled for {}", username);
+        }
+
+        let mut secret = [0u8; SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let enrollment = MfaEnrollment {
+            username: username.to_string(),
+            secret: self.encrypt(&secret)?,
+            enabled: false,
+            recovery_codes: Vec::new(),
+            last_step: 0,
+            created_at: Utc::now(),
+        };
+        self.store.write(rv, &key, Some(&enrollment)).await?;
+
+        let encoded = BASE32_NOPAD.encode(&secret);
+        let mut uri = reqwest::Url::parse("otpauth://totp/")?;
+        uri.set_path(&format!("{}:{}", ISSUER, username));
+        uri.query_pairs_mut()
+            .append_pair("secret", &encoded)
+            .append_pair("issuer", ISSUER)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &PERIOD_SECONDS.to_string());
+        Ok(MfaSecret { secret: encoded, otpauth_uri: uri.to_string() })
+    }
+
+    /// Matches `code` against the TOTP window, returning the accepted step.
+    fn match_totp(&self, enrollment: &MfaEnrollment, code: &str) -> Result<Option<i64>> {
+        let secret = self.decrypt(&enrollment.secret)?;
+        let now = Utc::now().timestamp() / PERIOD_SECONDS;
+        Ok(matching_step(&secret, code.trim(), now, enrollment.last_step))
+    }
+
+    /// Enables a pending enrollment once `code` matches and returns fresh recovery codes,
+    /// or `None` if the code is wrong.
+    pub async fn activate(&self, username: &str, code: &str) -> Result<Option<Vec<String>>> {
+        let (mut records, rv) = self.store.load().await?;
+        let key = record_key(username);
+        let mut enrollment = records.remove(&key)
+            .ok_or_else(|| anyhow!("No MFA enrollment in progress for {}", username))?;
+        if enrollment.enabled {
+            bail!("MFA is already enabled for {}", username);
+        }
+
+        let step = match self.match_totp(&enrollment, code)? {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+
+        let codes = recovery_codes();
+
+        enrollment.enabled = true;
+        enrollment.last_step = step;
+        enrollment.recovery_codes = codes.iter().map(|c| digest(c)).collect();
+        self.store.write(rv, &key, Some(&enrollment)).await?;
+        Ok(Some(codes))
+    }
+
+    /// Checks a TOTP code or a one-time recovery code for an enabled enrollment.
+    pub async fn verify(&self, username: &str, code: &str) -> Result<bool> {
+        let (mut records, rv) = self.store.load().await?;
+        let key = record_key(username);
+        let mut enrollment = match records.remove(&key) {
+            Some(e) if e.enabled => e,
+            _ => return Ok(false),
+        };
+
+        if let Some(step) = self.match_totp(&enrollment, code)? {
+            enrollment.last_step = step;
+        } else {
+            if !enrollment.use_recovery_code(code) {
+                return Ok(false);
+            }
+            tracing::info!(user = %username, remaining = enrollment.recovery_codes.len(), "MFA recovery code used");
+        }
+
+        // Persisting the used step or code is what makes them single-use.
+        self.store.write(rv, &key, Some(&enrollment)).await?;
+        Ok(true)
+    }
+
+    /// Deletes the enrollment; returns whether one existed.
+    pub async fn remove(&self, username: &str) -> Result<bool> {
+        let (records, rv) = self.store.load().await?;
+        let key = record_key(username);
+        if !records.contains_key(&key) {
+            return Ok(false);
+        }
+        self.store.write(rv, &key, None).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret of the RFC 6238 appendix B test vectors.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn totp_matches_the_rfc_6238_vectors() {
+        // The RFC lists 8 digits; these are their last 6.
+        for (time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+            assert_eq!(totp(RFC_SECRET, time / PERIOD_SECONDS), code, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_one_step_either_side_of_now() {
+        let now = 1_000_000;
+        for step in [now - 1, now, now + 1] {
+            assert_eq!(matching_step(RFC_SECRET, &totp(RFC_SECRET, step), now, 0), Some(step));
+        }
+        for step in [now - 2, now + 2] {
+            assert_eq!(matching_step(RFC_SECRET, &totp(RFC_SECRET, step), now, 0), None);
+        }
+    }
+
+    #[test]
+    fn used_steps_cannot_be_replayed() {
+        let now = 1_000_000;
+        let code = totp(RFC_SECRET, now);
+        assert_eq!(matching_step(RFC_SECRET, &code, now, now - 1), Some(now));
+        assert_eq!(matching_step(RFC_SECRET, &code, now, now), None);
+    }
+
+    fn enrollment(codes: &[String]) -> MfaEnrollment {
+        MfaEnrollment {
+            username: "alice".to_string(),
+            secret: String::new(),
+            enabled: true,
+            recovery_codes: codes.iter().map(|c| digest(c)).collect(),
+            last_step: 0,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_distinct_and_formatted() {
+        let codes = recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(codes.iter().all(|c| c.len() == 9 && c.as_bytes()[4] == b'-'), "{:?}", codes);
+        let unique: std::collections::BTreeSet<_> = codes.iter().collect();
+        assert_eq!(unique.len(), codes.len());
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use_and_forgiving_about_format() {
+        let codes = recovery_codes();
+        let mut enrollment = enrollment(&codes);
+        let relaxed = codes[0].replace('-', " ").to_lowercase();
+        assert!(enrollment.use_recovery_code(&relaxed));
+        assert!(!enrollment.use_recovery_code(&codes[0]));
+        assert!(!enrollment.use_recovery_code("AAAA-AAAA"));
+        assert_eq!(enrollment.recovery_codes.len(), RECOVERY_CODES - 1);
+    }
+
+    #[test]
+    fn unusual_usernames_get_hashed_record_keys() {
+        assert_eq!(record_key("alice.smith"), "alice.smith");
+        assert!(record_key("CN=Alice,OU=People").starts_with("sha256-"));
+        assert!(record_key("").starts_with("sha256-"));
+    }
+}
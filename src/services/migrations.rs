@@ -0,0 +1,47 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use kube::Client;
+use serde::{Deserialize, Serialize};
+use crate::config::BaseConfig;
+use crate::services::{kubernetes::KubeOps, secret_store::JsonSecretStore};
+
+/// Labels objects created before KanriGate labelled everything it creates.
+const LABEL_MANAGED_OBJECTS: &str = "label-managed-objects";
+
+/// A migration that has completed, kept in the `migrations_secret` Secret under its name.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub completed_at: DateTime<Utc>,
+    pub result: serde_json::Value,
+}
+
+/// Runs the migrations that have not completed yet and records them.
+///
+/// Migrations are idempotent, so when several replicas start at once and run the same one,
+/// the extra runs only cost time.
+pub async fn run(client: Client, config: &BaseConfig) -> Result<()> {
+    let store: JsonSecretStore<MigrationRecord> = JsonSecretStore::new(client.clone(), &config.migrations_secret);
+    let (done, _) = store.load().await?;
+    if done.contains_key(LABEL_MANAGED_OBJECTS) {
+        return Ok(());
+    }
+
+    let state_secrets = [
+        &config.operators_secret,
+        &config.token_rotations_secret,
+        &config.settings_secret,
+        &config.revocation_secret,
+        &config.mfa_secret,
+        &config.api_keys_secret,
+    ].map(|name| name.to_string());
+    let migrated = KubeOps::new(client).label_managed_objects(&state_secrets).await?;
+    tracing::info!(migration = LABEL_MANAGED_OBJECTS, result = ?migrated, "Migration completed");
+
+    let record = MigrationRecord {
+        completed_at: Utc::now(),
+        result: serde_json::to_value(&migrated)?,
+    };
+    // Reload for a fresh resourceVersion; the labelling may have taken a while.
+    let (_, rv) = store.load().await?;
+    store.write(rv, LABEL_MANAGED_OBJECTS, Some(&record)).await
+}
@@ -1 +1,19 @@
+pub mod api_keys;
+pub mod authenticator;
+pub mod cache;
+pub mod certificates;
+pub mod jwt;
 pub mod kubernetes;
+pub mod ldap;
+pub mod login_guard;
+pub mod mfa;
+pub mod migrations;
+pub mod oidc;
+pub mod operators;
+pub mod provisioning;
+pub mod revocation;
+pub mod secret_store;
+pub mod settings;
+pub mod templates;
+pub mod token_review;
+pub mod token_rotation;
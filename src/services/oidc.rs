@@ -0,0 +1,526 @@
+use anyhow::{anyhow, bail, Result};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{decode, decode_header, jwk::{Jwk, JwkSet}, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, RwLock};
+use crate::config::BaseConfig;
+use crate::services::authenticator::{role_from_groups, Role};
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+/// ID token algorithms KanriGate accepts. HMAC and `none` are excluded: the keys come from a
+/// public JWKS, so a symmetric signature would prove nothing.
+const ASYMMETRIC_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+/// Unknown `kid`s refetch the JWKS at most this often, so forged tokens cannot flood the IdP.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Parses `names` into accepted ID token algorithms, rejecting anything not asymmetric.
+fn signing_algorithms(names: &[String]) -> Result<Vec<Algorithm>> {
+    names.iter().map(|name| {
+        match Algorithm::from_str(name.trim()) {
+            Ok(alg) if ASYMMETRIC_ALGORITHMS.contains(&alg) => Ok(alg),
+            _ => Err(anyhow!("Unsupported OIDC signing algorithm {}; use an RS*, PS*, ES* or EdDSA algorithm", name)),
+        }
+    }).collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The identity established by a successful OIDC login.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub username: String,
+    pub groups: Vec<String>,
+}
+
+/// Returns a random URL-safe string suitable for `state`, `nonce` and PKCE verifiers.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// S256 PKCE challenge for the given verifier (RFC 7636 section 4.2).
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Relying-party side of the OpenID Connect authorization-code flow.
+///
+/// Provider metadata is discovered lazily from `{issuer}/.well-known/openid-configuration`
+/// so that KanriGate can start before the IdP is reachable.
+pub struct OidcClient {
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<Option<CachedJwks>>,
+    /// From `oidc_signing_algorithms`; empty means those the provider advertises.
+    algorithms: Vec<Algorithm>,
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: Vec<String>,
+    username_claim: String,
+    groups_claim: String,
+    admin_groups: Vec<String>,
+    operator_groups: Vec<String>,
+    viewer_groups: Vec<String>,
+    allowed_groups: Vec<String>,
+}
+
+impl OidcClient {
+    /// Builds the client when `oidc_issuer` is configured, `None` otherwise.
+    pub fn from_config(config: &BaseConfig) -> Result<Option<Self>> {
+        let issuer = match &config.oidc_issuer {
+            Some(issuer) => issuer.trim_end_matches('/').to_string(),
+            None => return Ok(None),
+        };
+        let client_id = config.oidc_client_id.clone()
+            .ok_or_else(|| anyhow!("oidc_client_id is required when oidc_issuer is set"))?;
+        let redirect_url = config.oidc_redirect_url.clone()
+            .ok_or_else(|| anyhow!("oidc_redirect_url is required when oidc_issuer is set"))?;
+
+        let role_groups_set = !config.oidc_admin_groups.is_empty()
+            || !config.oidc_operator_groups.is_empty()
+            || !config.oidc_viewer_groups.is_empty();
+        if !role_groups_set && config.oidc_allowed_groups.is_empty() {
+            tracing::warn!("OIDC is enabled but no oidc_*_groups are configured; every SSO login will be rejected");
+        } else if !role_groups_set {
+            tracing::warn!(
+                "Only APP_OIDC_ALLOWED_GROUPS is set: every member logs in as admin, as before roles existed. \
+                 Set APP_OIDC_ADMIN_GROUPS, APP_OIDC_OPERATOR_GROUPS or APP_OIDC_VIEWER_GROUPS to grant narrower roles"
+            );
+        }
+
+        Ok(Some(Self {
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(None),
+            algorithms: signing_algorithms(&config.oidc_signing_algorithms)?,
+            issuer,
+            client_id,
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url,
+            scopes: config.oidc_scopes.clone(),
+            username_claim: config.oidc_username_claim.clone(),
+            groups_claim: config.oidc_groups_claim.clone(),
+            admin_groups: config.oidc_admin_groups.clone(),
+            operator_groups: config.oidc_operator_groups.clone(),
+            viewer_groups: config.oidc_viewer_groups.clone(),
+            allowed_groups: config.oidc_allowed_groups.clone(),
+        }))
+    }
+
+    pub fn redirect_url(&self) -> &str {
+        &self.redirect_url
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata.get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", self.issuer);
+            let metadata: ProviderMetadata = self.http.get(&url)
+                .send().await?
+                .error_for_status()?
+                .json().await?;
+
+            if metadata.issuer.trim_end_matches('/') != self.issuer {
+                bail!("Discovered issuer {} does not match configured issuer {}", metadata.issuer, self.issuer);
+            }
+            Ok(metadata)
+        }).await
+    }
+
+    pub async fn authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> Result<String> {
+        let metadata = self.metadata().await?;
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", &self.scopes.join(" "))
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &pkce_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the authorization code, verifies the ID token and maps it to an identity.
+    pub async fn authenticate(&self, code: &str, code_verifier: &str, nonce: &str) -> Result<OidcIdentity> {
+        let metadata = self.metadata().await?;
+
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint).form(&form);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+        let tokens: TokenResponse = request.send().await?
+            .error_for_status()?
+            .json().await?;
+
+        let claims = self.verify_id_token(metadata, &tokens.id_token).await?;
+
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+            bail!("ID token nonce mismatch");
+        }
+
+        self.identity(&claims)
+    }
+
+    /// Algorithms an ID token may be signed with: the configured ones, else the asymmetric
+    /// ones the provider advertises, else RS256, which every provider must support.
+    fn accepted_algorithms(&self, metadata: &ProviderMetadata) -> Vec<Algorithm> {
+        if !self.algorithms.is_empty() {
+            return self.algorithms.clone();
+        }
+        let advertised: Vec<Algorithm> = metadata.id_token_signing_alg_values_supported.iter()
+            .filter_map(|name| Algorithm::from_str(name).ok())
+            .filter(|alg| ASYMMETRIC_ALGORITHMS.contains(alg))
+            .collect();
+        if advertised.is_empty() { vec![Algorithm::RS256] } else { advertised }
+    }
+
+    /// The provider key `kid`, from the cached JWKS, refetched when `kid` is not in it.
+    async fn jwk(&self, jwks_uri: &str, kid: &str) -> Result<Jwk> {
+        let lookup = |cached: &Option<CachedJwks>| -> Option<Result<Jwk>> {
+            let cached = cached.as_ref()?;
+            match cached.keys.find(kid) {
+                Some(jwk) => Some(Ok(jwk.clone())),
+                None if cached.fetched_at.elapsed() < JWKS_REFRESH_INTERVAL => Some(Err(anyhow!("No key {} in IdP JWKS", kid))),
+                None => None,
+            }
+        };
+        if let Some(found) = lookup(&*self.jwks.read().await) {
+            return found;
+        }
+
+        let mut cached = self.jwks.write().await;
+        // Another login may have refreshed the keys while this one waited for the lock.
+        if let Some(found) = lookup(&cached) {
+            return found;
+        }
+        let keys: JwkSet = self.http.get(jwks_uri)
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        let jwk = keys.find(kid).cloned();
+        *cached = Some(CachedJwks { keys, fetched_at: Instant::now() });
+        jwk.ok_or_else(|| anyhow!("No key {} in IdP JWKS", kid))
+    }
+
+    async fn verify_id_token(&self, metadata: &ProviderMetadata, id_token: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let header = decode_header(id_token)?;
+        let algorithms = self.accepted_algorithms(metadata);
+        if !algorithms.contains(&header.alg) {
+            bail!("ID token algorithm {:?} is not accepted", header.alg);
+        }
+        let kid = header.kid.as_deref().ok_or_else(|| anyhow!("ID token has no kid"))?;
+        let jwk = self.jwk(&metadata.jwks_uri, kid).await?;
+        if let Some(key_alg) = jwk.common.key_algorithm {
+            if Algorithm::from_str(&key_alg.to_string()).ok() != Some(header.alg) {
+                bail!("ID token algorithm {:?} does not match key {} ({})", header.alg, kid, key_alg);
+            }
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        let data = decode::<serde_json::Map<String, serde_json::Value>>(
+            id_token,
+            &DecodingKey::from_jwk(&jwk)?,
+            &validation,
+        )?;
+        Ok(data.claims)
+    }
+
+    fn identity(&self, claims: &serde_json::Map<String, serde_json::Value>) -> Result<OidcIdentity> {
+        let username = claims.get(&self.username_claim)
+            .or_else(|| claims.get("sub"))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("ID token has no {} claim", self.username_claim))?
+            .to_string();
+
+        let groups: Vec<String> = match claims.get(&self.groups_claim) {
+            Some(serde_json::Value::Array(items)) => items.iter()
+                .filter_map(|g| g.as_str().map(String::from))
+                .collect(),
+            Some(serde_json::Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+
+        Ok(OidcIdentity { username, groups })
+    }
+
+    /// Role granted by the identity's groups, or `None` if it may not log in.
+    ///
+    /// `oidc_allowed_groups`, when set, is required for any login. Without any of the role
+    /// group lists it keeps its original meaning of full access and grants admin.
+    pub fn role_for(&self, identity: &OidcIdentity) -> Option<Role> {
+        let allowed = role_from_groups(&identity.groups, &[], &[], &self.allowed_groups).is_some();
+        if !self.allowed_groups.is_empty() && !allowed {
+            return None;
+        }
+        let legacy = self.admin_groups.is_empty() && self.operator_groups.is_empty() && self.viewer_groups.is_empty();
+        if legacy {
+            return allowed.then_some(Role::Admin);
+        }
+        role_from_groups(&identity.groups, &self.admin_groups, &self.operator_groups, &self.viewer_groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::{get, post}, Form, Json, Router};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::{rand::SystemRandom, signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING}};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    const KID: &str = "test-key";
+    const CLIENT_ID: &str = "kanrigate";
+    const CODE: &str = "auth-code";
+
+    /// What the mock issuer's token endpoint expects and returns.
+    #[derive(Default)]
+    struct Exchange {
+        challenge: Option<String>,
+        id_token: String,
+    }
+
+    /// An OpenID provider on a local port, signing with a fresh P-256 key.
+    struct MockIssuer {
+        url: String,
+        key: EncodingKey,
+        exchange: Arc<Mutex<Exchange>>,
+    }
+
+    impl MockIssuer {
+        async fn start() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            // An uncompressed point: 0x04, then x and y.
+            let point = pair.public_key().as_ref();
+            let jwks = json!({ "keys": [{
+                "kty": "EC", "crv": "P-256", "use": "sig", "alg": "ES256", "kid": KID,
+                "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            }]});
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let discovery = json!({
+                "issuer": url,
+                "authorization_endpoint": format!("{}/authorize", url),
+                "token_endpoint": format!("{}/token", url),
+                "jwks_uri": format!("{}/jwks", url),
+                "id_token_signing_alg_values_supported": ["ES256"],
+            });
+            let exchange = Arc::new(Mutex::new(Exchange::default()));
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(move || async move { Json(discovery) }))
+                .route("/jwks", get(move || async move { Json(jwks) }))
+                .route("/token", post(token))
+                .with_state(exchange.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { url, key: EncodingKey::from_ec_der(pkcs8.as_ref()), exchange }
+        }
+
+        fn claims(&self, nonce: &str, groups: &[&str]) -> Value {
+            let now = chrono::Utc::now().timestamp();
+            json!({
+                "iss": self.url, "aud": CLIENT_ID, "sub": "alice", "nonce": nonce,
+                "groups": groups, "iat": now, "exp": now + 300,
+            })
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some(KID.to_string());
+            encode(&header, claims, &self.key).unwrap()
+        }
+
+        /// Makes the token endpoint return `id_token` for the next exchange.
+        fn issue(&self, id_token: String) {
+            self.exchange.lock().unwrap().id_token = id_token;
+        }
+
+        fn client(&self, admin: &[&str], operator: &[&str], viewer: &[&str], allowed: &[&str]) -> OidcClient {
+            let groups = |names: &[&str]| names.iter().map(|g| g.to_string()).collect();
+            OidcClient {
+                http: reqwest::Client::new(),
+                metadata: OnceCell::new(),
+                jwks: RwLock::new(None),
+                algorithms: Vec::new(),
+                issuer: self.url.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: None,
+                redirect_url: "http://localhost/apps/oidc/callback".to_string(),
+                scopes: vec!["openid".to_string()],
+                username_claim: "preferred_username".to_string(),
+                groups_claim: "groups".to_string(),
+                admin_groups: groups(admin),
+                operator_groups: groups(operator),
+                viewer_groups: groups(viewer),
+                allowed_groups: groups(allowed),
+            }
+        }
+
+        /// Starts a login as the callback would see it: the authorization request's PKCE
+        /// challenge is what the token endpoint will check the verifier against.
+        async fn authorize(&self, client: &OidcClient, verifier: &str) {
+            let url = reqwest::Url::parse(&client.authorization_url("state", "nonce", verifier).await.unwrap()).unwrap();
+            let challenge = url.query_pairs().find(|(k, _)| k == "code_challenge").map(|(_, v)| v.into_owned());
+            self.exchange.lock().unwrap().challenge = challenge;
+        }
+    }
+
+    async fn token(State(exchange): State<Arc<Mutex<Exchange>>>, Form(form): Form<HashMap<String, String>>) -> axum::response::Response {
+        use axum::response::IntoResponse;
+        let exchange = exchange.lock().unwrap();
+        let verified = form.get("code_verifier").map(|v| pkce_challenge(v)) == exchange.challenge;
+        if form.get("code").map(String::as_str) != Some(CODE) || !verified {
+            return (axum::http::StatusCode::BAD_REQUEST, Json(json!({ "error": "invalid_grant" }))).into_response();
+        }
+        Json(json!({ "id_token": exchange.id_token, "token_type": "Bearer" })).into_response()
+    }
+
+    #[tokio::test]
+    async fn login_maps_groups_to_a_role() {
+        let issuer = MockIssuer::start().await;
+        let client = issuer.client(&["platform-admins"], &["platform-engineers"], &["developers"], &[]);
+        issuer.authorize(&client, "verifier").await;
+        issuer.issue(issuer.sign(&issuer.claims("nonce", &["developers", "platform-engineers"])));
+
+        let identity = client.authenticate(CODE, "verifier", "nonce").await.unwrap();
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.groups, ["developers", "platform-engineers"]);
+        assert_eq!(client.role_for(&identity), Some(Role::Operator));
+    }
+
+    #[tokio::test]
+    async fn wrong_pkce_verifier_is_rejected_by_the_issuer() {
+        let issuer = MockIssuer::start().await;
+        let client = issuer.client(&["platform-admins"], &[], &[], &[]);
+        issuer.authorize(&client, "verifier").await;
+        issuer.issue(issuer.sign(&issuer.claims("nonce", &["platform-admins"])));
+
+        let error = client.authenticate(CODE, "another-verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("400"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn id_token_for_another_audience_or_issuer_is_rejected() {
+        let issuer = MockIssuer::start().await;
+        let client = issuer.client(&["platform-admins"], &[], &[], &[]);
+        issuer.authorize(&client, "verifier").await;
+
+        let mut claims = issuer.claims("nonce", &["platform-admins"]);
+        claims["aud"] = json!("another-client");
+        issuer.issue(issuer.sign(&claims));
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("InvalidAudience"), "{}", error);
+
+        let mut claims = issuer.claims("nonce", &["platform-admins"]);
+        claims["iss"] = json!("https://evil.example.com");
+        issuer.issue(issuer.sign(&claims));
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("InvalidIssuer"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn id_token_with_another_nonce_is_rejected() {
+        let issuer = MockIssuer::start().await;
+        let client = issuer.client(&["platform-admins"], &[], &[], &[]);
+        issuer.authorize(&client, "verifier").await;
+        issuer.issue(issuer.sign(&issuer.claims("replayed", &["platform-admins"])));
+
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("nonce"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn id_token_without_kid_or_with_hmac_is_rejected() {
+        let issuer = MockIssuer::start().await;
+        let client = issuer.client(&["platform-admins"], &[], &[], &[]);
+        issuer.authorize(&client, "verifier").await;
+        let claims = issuer.claims("nonce", &["platform-admins"]);
+
+        issuer.issue(encode(&Header::new(Algorithm::ES256), &claims, &issuer.key).unwrap());
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("no kid"), "{}", error);
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(KID.to_string());
+        issuer.issue(encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap());
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("not accepted"), "{}", error);
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("unknown".to_string());
+        issuer.issue(encode(&header, &claims, &issuer.key).unwrap());
+        let error = client.authenticate(CODE, "verifier", "nonce").await.unwrap_err();
+        assert!(error.to_string().contains("No key unknown"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn legacy_allowed_groups_gate_login_and_grant_admin_alone() {
+        let issuer = MockIssuer::start().await;
+        let identity = |groups: &[&str]| OidcIdentity {
+            username: "alice".to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        };
+
+        let legacy = issuer.client(&[], &[], &[], &["platform-engineers"]);
+        assert_eq!(legacy.role_for(&identity(&["platform-engineers"])), Some(Role::Admin));
+        assert_eq!(legacy.role_for(&identity(&["developers"])), None);
+
+        let gated = issuer.client(&[], &[], &["developers"], &["platform-engineers"]);
+        assert_eq!(gated.role_for(&identity(&["developers"])), None);
+        assert_eq!(gated.role_for(&identity(&["developers", "platform-engineers"])), Some(Role::Viewer));
+        assert_eq!(gated.role_for(&identity(&["platform-engineers"])), None);
+    }
+
+    #[test]
+    fn signing_algorithms_reject_symmetric_ones() {
+        assert_eq!(signing_algorithms(&["RS256".to_string(), "ES256".to_string()]).unwrap(), [Algorithm::RS256, Algorithm::ES256]);
+        assert!(signing_algorithms(&["HS256".to_string()]).is_err());
+        assert!(signing_algorithms(&["none".to_string()]).is_err());
+    }
+}
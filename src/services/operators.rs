@@ -0,0 +1,119 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use kube::Client;
+use serde::{Deserialize, Serialize};
+use crate::services::{authenticator::Role, secret_store::JsonSecretStore};
+
+/// A KanriGate operator account, stored as one JSON document per key in the operators Secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operator {
+    pub username: String,
+    pub password_hash: String,
+    #[serde(default = "default_operator_role")]
+    pub role: Role,
+    /// Namespace names or globs a delegated administrator is limited to; `None` for cluster-wide.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+// Records written before roles existed had full access to the namespaced endpoints.
+fn default_operator_role() -> Role {
+    Role::Operator
+}
+
+/// Operator accounts backed by a Secret in the KanriGate namespace, one data key per operator.
+#[derive(Clone)]
+pub struct OperatorStore {
+    store: JsonSecretStore<Operator>,
+}
+
+impl OperatorStore {
+    pub fn new(client: Client, secret_name: &str) -> Self {
+        Self {
+            store: JsonSecretStore::new(client, secret_name),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Operator>> {
+        let (operators, _) = self.store.load().await?;
+        Ok(operators.into_values().collect())
+    }
+
+    pub async fn get(&self, username: &str) -> Result<Option<Operator>> {
+        let (mut operators, _) = self.store.load().await?;
+        Ok(operators.remove(username))
+    }
+
+    pub async fn add(&self, username: &str, password_hash: &str, role: Role, namespaces: Option<Vec<String>>) -> Result<Operator> {
+        let (operators, rv) = self.store.load().await?;
+        if operators.contains_key(username) {
+            bail!("Operator {} already exists", username);
+        }
+
+        let operator = Operator {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            role,
+            namespaces,
+            disabled: false,
+            created_at: Utc::now(),
+        };
+        self.store.write(rv, username, Some(&operator)).await?;
+        Ok(operator)
+    }
+
+    pub async fn set_disabled(&self, username: &str, disabled: bool) -> Result<Operator> {
+        let (mut operators, rv) = self.store.load().await?;
+        let mut operator = operators
+            .remove(username)
+            .ok_or_else(|| anyhow!("Operator {} not found", username))?;
+
+        operator.disabled = disabled;
+        self.store.write(rv, username, Some(&operator)).await?;
+        Ok(operator)
+    }
+
+    pub async fn set_role(&self, username: &str, role: Role) -> Result<Operator> {
+        let (mut operators, rv) = self.store.load().await?;
+        let mut operator = operators
+            .remove(username)
+            .ok_or_else(|| anyhow!("Operator {} not found", username))?;
+
+        operator.role = role;
+        self.store.write(rv, username, Some(&operator)).await?;
+        Ok(operator)
+    }
+
+    pub async fn set_namespaces(&self, username: &str, namespaces: Option<Vec<String>>) -> Result<Operator> {
+        let (mut operators, rv) = self.store.load().await?;
+        let mut operator = operators
+            .remove(username)
+            .ok_or_else(|| anyhow!("Operator {} not found", username))?;
+
+        operator.namespaces = namespaces;
+        self.store.write(rv, username, Some(&operator)).await?;
+        Ok(operator)
+    }
+
+    pub async fn set_password(&self, username: &str, password_hash: &str) -> Result<Operator> {
+        let (mut operators, rv) = self.store.load().await?;
+        let mut operator = operators
+            .remove(username)
+            .ok_or_else(|| anyhow!("Operator {} not found", username))?;
+
+        operator.password_hash = password_hash.to_string();
+        self.store.write(rv, username, Some(&operator)).await?;
+        Ok(operator)
+    }
+
+    pub async fn remove(&self, username: &str) -> Result<()> {
+        let (operators, rv) = self.store.load().await?;
+        if !operators.contains_key(username) {
+            bail!("Operator {} not found", username);
+        }
+        self.store.write(rv, username, None).await
+    }
+}
@@ -0,0 +1,328 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::services::kubernetes::{ApplyOutcome, KubeOps, KubeconfigToken, SubjectKind};
+
+/// Everything a new ServiceAccount user gets from one `provisionUser` call.
+pub struct ProvisionPlan {
+    pub username: String,
+    /// Namespace of the kubeconfig's context.
+    pub namespace: String,
+    /// Templates to bind, per namespace.
+    pub role_bindings: BTreeMap<String, Vec<String>>,
+    pub cluster_permission: Option<String>,
+    pub token: KubeconfigToken,
+}
+
+/// An object created while provisioning, deleted again if a later step fails.
+enum Created {
+    ServiceAccount(String),
+    Secret(String),
+    RoleBinding { namespace: String, permission: String, name: String },
+    ClusterRoleBinding { permission: String, name: String },
+}
+
+impl fmt::Display for Created {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Created::ServiceAccount(name) => write!(f, "ServiceAccount/{}", name),
+            Created::Secret(name) => write!(f, "Secret/{}", name),
+            Created::RoleBinding { namespace, name, .. } => write!(f, "RoleBinding/{}/{}", namespace, name),
+            Created::ClusterRoleBinding { name, .. } => write!(f, "ClusterRoleBinding/{}", name),
+        }
+    }
+}
+
+pub struct Provisioned {
+    pub kubeconfig: String,
+    /// Objects created, in order, as `Kind/name` or `Kind/namespace/name`.
+    pub created: Vec<String>,
+}
+
+/// A step failed and the objects created before it were deleted, except `left_behind`.
+#[derive(Debug)]
+pub struct ProvisionFailure {
+    pub error: anyhow::Error,
+    pub rolled_back: Vec<String>,
+    pub left_behind: Vec<String>,
+}
+
+impl fmt::Display for ProvisionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}; rolled back {} objects", self.error, self.rolled_back.len())?;
+        if !self.left_behind.is_empty() {
+            write!(f, "; could not delete {}", self.left_behind.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the ServiceAccount, its token Secret, the bindings in `plan` and a kubeconfig,
+/// in that order. When a step fails, everything created so far is deleted in reverse order.
+pub async fn provision(kube: &KubeOps, plan: &ProvisionPlan, cluster_name: &str, control_plane: &str) -> Result<Provisioned, ProvisionFailure> {
+    let mut created = Vec::new();
+    match create_all(kube, plan, cluster_name, control_plane, &mut created).await {
+        Ok(kubeconfig) => Ok(Provisioned {
+            kubeconfig,
+            created: created.iter().map(ToString::to_string).collect(),
+        }),
+        Err(error) => Err(roll_back(kube, &plan.username, created, error).await),
+    }
+}
+
+async fn create_all(kube: &KubeOps, plan: &ProvisionPlan, cluster_name: &str, control_plane: &str, created: &mut Vec<Created>) -> Result<String> {
+    let username = plan.username.as_str();
+    // Only objects this call created are rolled back; ones that already existed stay.
+    let account = kube.create_service_account(username).await?;
+    if account.outcome == ApplyOutcome::Created {
+        created.push(Created::ServiceAccount(account.name));
+    }
+
+    let secret = kube.create_secret(username).await?;
+    if secret.outcome == ApplyOutcome::Created {
+        created.push(Created::Secret(secret.name.clone()));
+    }
+    kube.wait_for_token(username, &secret.name).await?;
+
+    for (namespace, permissions) in &plan.role_bindings {
+        for permission in permissions {
+            let binding = kube.create_role_binding(username, SubjectKind::ServiceAccount, namespace, permission).await?;
+            if binding.outcome == ApplyOutcome::Created {
+                created.push(Created::RoleBinding { namespace: namespace.clone(), permission: permission.clone(), name: binding.name });
+            }
+        }
+    }
+    if let Some(permission) = &plan.cluster_permission {
+        let binding = kube.create_cluster_role_binding(username, SubjectKind::ServiceAccount, permission).await?;
+        if binding.outcome == ApplyOutcome::Created {
+            created.push(Created::ClusterRoleBinding { permission: permission.clone(), name: binding.name });
+        }
+    }
+
+    kube.generate_k8s_config(username, &plan.namespace, cluster_name, control_plane, &plan.token).await
+}
+
+async fn roll_back(kube: &KubeOps, username: &str, created: Vec<Created>, error: anyhow::Error) -> ProvisionFailure {
+    let mut failure = ProvisionFailure { error, rolled_back: Vec::new(), left_behind: Vec::new() };
+    for object in created.into_iter().rev() {
+        let deleted = match &object {
+            Created::ServiceAccount(_) => kube.delete_service_account(username).await.map(|_| ()),
+            Created::Secret(_) => kube.delete_secret(username).await.map(|_| ()),
+            Created::RoleBinding { namespace, permission, .. } => {
+                kube.delete_role_binding(username, SubjectKind::ServiceAccount, namespace, permission).await.map(|_| ())
+            }
+            Created::ClusterRoleBinding { permission, .. } => {
+                kube.delete_cluster_role_binding(username, SubjectKind::ServiceAccount, permission).await.map(|_| ())
+            }
+        };
+        match deleted {
+            Ok(()) => failure.rolled_back.push(object.to_string()),
+            Err(e) => {
+                tracing::error!(target = %username, object = %object, error = %e, "Failed to roll back provisioned object");
+                failure.left_behind.push(object.to_string());
+            }
+        }
+    }
+    failure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::kubernetes::{get_current_namespace, NotManaged};
+    use axum::{body::Bytes, extract::State, http::{Method, StatusCode, Uri}, response::IntoResponse, Json, Router};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// A minimal API server keeping created objects by path, answering `500` to the
+    /// requests listed in `failing`.
+    #[derive(Default)]
+    struct ApiServer {
+        objects: BTreeMap<String, Value>,
+        calls: Vec<String>,
+        failing: Vec<(Method, &'static str)>,
+    }
+
+    fn status(code: StatusCode, message: &str) -> axum::response::Response {
+        let status = if code.is_success() { "Success" } else { "Failure" };
+        let reason = match code {
+            StatusCode::NOT_FOUND => "NotFound",
+            StatusCode::CONFLICT => "AlreadyExists",
+            _ => "",
+        };
+        let body = json!({ "kind": "Status", "apiVersion": "v1", "metadata": {}, "status": status, "message": message, "reason": reason, "code": code.as_u16() });
+        (code, Json(body)).into_response()
+    }
+
+    async fn serve(State(server): State<Arc<Mutex<ApiServer>>>, method: Method, uri: Uri, body: Bytes) -> axum::response::Response {
+        let mut server = server.lock().unwrap();
+        let path = uri.path().to_string();
+        server.calls.push(format!("{} {}", method, path));
+        if server.failing.iter().any(|(m, fragment)| *m == method && path.contains(fragment)) {
+            return status(StatusCode::INTERNAL_SERVER_ERROR, "injected failure");
+        }
+        match method {
+            Method::POST => {
+                let mut object: Value = serde_json::from_slice(&body).unwrap();
+                let name = object["metadata"]["name"].as_str().unwrap().to_string();
+                if server.objects.contains_key(&format!("{}/{}", path, name)) {
+                    return status(StatusCode::CONFLICT, "already exists");
+                }
+                object["metadata"]["uid"] = json!(format!("uid-{}", name));
+                if path.ends_with("/secrets") {
+                    object["data"] = json!({ "token": "dG9rZW4=" });
+                }
+                server.objects.insert(format!("{}/{}", path, name), object.clone());
+                (StatusCode::CREATED, Json(object)).into_response()
+            }
+            Method::GET => match server.objects.get(&path) {
+                Some(object) => Json(object.clone()).into_response(),
+                None if path.ends_with('s') => {
+                    let prefix = format!("{}/", path);
+                    let items: Vec<Value> = server.objects.iter()
+                        .filter(|(key, _)| key.starts_with(&prefix))
+                        .map(|(_, object)| object.clone())
+                        .collect();
+                    Json(json!({ "kind": "List", "apiVersion": "v1", "metadata": {}, "items": items })).into_response()
+                }
+                None => status(StatusCode::NOT_FOUND, "not found"),
+            },
+            Method::DELETE => match server.objects.remove(&path) {
+                Some(_) => status(StatusCode::OK, "deleted"),
+                None => status(StatusCode::NOT_FOUND, "not found"),
+            },
+            _ => status(StatusCode::METHOD_NOT_ALLOWED, "unsupported"),
+        }
+    }
+
+    async fn kube(failing: Vec<(Method, &'static str)>) -> (KubeOps, Arc<Mutex<ApiServer>>) {
+        kube_with(failing, BTreeMap::new()).await
+    }
+
+    async fn kube_with(failing: Vec<(Method, &'static str)>, objects: BTreeMap<String, Value>) -> (KubeOps, Arc<Mutex<ApiServer>>) {
+        let server = Arc::new(Mutex::new(ApiServer { failing, objects, ..Default::default() }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().fallback(serve).with_state(server.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let config = kube::Config::new(url.parse().unwrap());
+        (KubeOps::new(kube::Client::try_from(config).unwrap()), server)
+    }
+
+    fn plan() -> ProvisionPlan {
+        ProvisionPlan {
+            username: "alice".to_string(),
+            namespace: "dev".to_string(),
+            role_bindings: BTreeMap::from([
+                ("dev".to_string(), vec!["developer".to_string()]),
+                ("prod".to_string(), vec!["read-only".to_string()]),
+            ]),
+            cluster_permission: Some("admin".to_string()),
+            token: KubeconfigToken::Legacy,
+        }
+    }
+
+    fn deletions(server: &Mutex<ApiServer>) -> Vec<String> {
+        let server = server.lock().unwrap();
+        server.calls.iter().filter(|c| c.starts_with("DELETE ")).cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn failed_step_rolls_back_in_reverse_creation_order() {
+        let (kube, server) = kube(vec![(Method::POST, "/clusterrolebindings")]).await;
+        let failure = provision(&kube, &plan(), "cluster", "https://k8s.example.com").await.err().unwrap();
+
+        let ns = get_current_namespace();
+        assert_eq!(failure.rolled_back, [
+            "RoleBinding/prod/alice___template-namespaced-resources___read-only___prod".to_string(),
+            "RoleBinding/dev/alice___template-namespaced-resources___developer___dev".to_string(),
+            "Secret/alice-token".to_string(),
+            "ServiceAccount/alice".to_string(),
+        ]);
+        assert!(failure.left_behind.is_empty());
+        assert_eq!(deletions(&server), [
+            "DELETE /apis/rbac.authorization.k8s.io/v1/namespaces/prod/rolebindings/alice___template-namespaced-resources___read-only___prod".to_string(),
+            "DELETE /apis/rbac.authorization.k8s.io/v1/namespaces/dev/rolebindings/alice___template-namespaced-resources___developer___dev".to_string(),
+            format!("DELETE /api/v1/namespaces/{}/secrets/alice-token", ns),
+            format!("DELETE /api/v1/namespaces/{}/serviceaccounts/alice", ns),
+        ]);
+        assert!(server.lock().unwrap().objects.is_empty());
+    }
+
+    #[tokio::test]
+    async fn undeletable_object_is_reported_and_rollback_continues() {
+        let (kube, server) = kube(vec![(Method::POST, "/clusterrolebindings"), (Method::DELETE, "/secrets/")]).await;
+        let failure = provision(&kube, &plan(), "cluster", "https://k8s.example.com").await.err().unwrap();
+
+        assert_eq!(failure.left_behind, ["Secret/alice-token".to_string()]);
+        assert_eq!(failure.rolled_back.last().map(String::as_str), Some("ServiceAccount/alice"));
+        assert_eq!(deletions(&server).len(), 4);
+        assert!(failure.to_string().contains("could not delete Secret/alice-token"), "{}", failure);
+    }
+
+    #[tokio::test]
+    async fn first_step_failure_leaves_nothing_to_roll_back() {
+        let (kube, server) = kube(vec![(Method::POST, "/serviceaccounts")]).await;
+        let failure = provision(&kube, &plan(), "cluster", "https://k8s.example.com").await.err().unwrap();
+
+        assert!(failure.rolled_back.is_empty() && failure.left_behind.is_empty());
+        assert!(deletions(&server).is_empty());
+    }
+
+    /// A ServiceAccount someone else created in the KanriGate namespace, such as KanriGate's own.
+    fn foreign_account(name: &str) -> BTreeMap<String, Value> {
+        let path = format!("/api/v1/namespaces/{}/serviceaccounts/{}", get_current_namespace(), name);
+        let account = json!({
+            "apiVersion": "v1", "kind": "ServiceAccount",
+            "metadata": { "name": name, "uid": "uid-foreign", "labels": { "app.kubernetes.io/managed-by": "Helm" } },
+        });
+        BTreeMap::from([(path, account)])
+    }
+
+    #[tokio::test]
+    async fn foreign_account_is_not_adopted() {
+        let (kube, server) = kube_with(Vec::new(), foreign_account("alice")).await;
+        let failure = provision(&kube, &plan(), "cluster", "https://k8s.example.com").await.err().unwrap();
+
+        assert!(failure.error.downcast_ref::<crate::services::kubernetes::Conflict>().is_some(), "{}", failure);
+        assert!(failure.rolled_back.is_empty());
+        let server = server.lock().unwrap();
+        assert!(!server.calls.iter().any(|c| c.starts_with("POST") && c.ends_with("/secrets")), "{:?}", server.calls);
+        assert!(!server.calls.iter().any(|c| c.starts_with("PATCH")), "{:?}", server.calls);
+    }
+
+    #[tokio::test]
+    async fn no_token_is_issued_for_a_foreign_account() {
+        let (kube, server) = kube_with(Vec::new(), foreign_account("kanrigate")).await;
+        let bound = KubeconfigToken::Bound { expiration_seconds: 3600, audiences: Vec::new() };
+        for token in [KubeconfigToken::Legacy, bound] {
+            let error = kube.generate_k8s_config("kanrigate", "dev", "cluster", "https://k8s.example.com", &token).await.unwrap_err();
+            assert!(error.downcast_ref::<NotManaged>().is_some(), "{}", error);
+        }
+        let error = kube.create_secret("kanrigate").await.unwrap_err();
+        assert!(error.downcast_ref::<NotManaged>().is_some(), "{}", error);
+        assert!(!server.lock().unwrap().calls.iter().any(|c| c.starts_with("POST")));
+    }
+
+    #[tokio::test]
+    async fn account_bound_from_elsewhere_is_not_managed() {
+        let (kube, server) = kube(Vec::new()).await;
+        kube.create_service_account("alice").await.unwrap();
+        kube.create_cluster_role_binding("alice", SubjectKind::ServiceAccount, "admin").await.unwrap();
+        assert!(kube.is_managed_subject(SubjectKind::ServiceAccount, "alice").await.unwrap());
+        assert!(kube.managed_service_accounts().await.unwrap().contains("alice"));
+
+        let binding = json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1", "kind": "ClusterRoleBinding",
+            "metadata": { "name": "alice-extra" },
+            "roleRef": { "apiGroup": "rbac.authorization.k8s.io", "kind": "ClusterRole", "name": "cluster-admin" },
+            "subjects": [{ "kind": "ServiceAccount", "name": "alice", "namespace": get_current_namespace() }],
+        });
+        server.lock().unwrap().objects.insert("/apis/rbac.authorization.k8s.io/v1/clusterrolebindings/alice-extra".to_string(), binding);
+
+        assert!(!kube.is_managed_subject(SubjectKind::ServiceAccount, "alice").await.unwrap());
+        assert!(!kube.managed_service_accounts().await.unwrap().contains("alice"));
+        assert!(!kube.is_managed_subject(SubjectKind::ServiceAccount, "bob").await.unwrap());
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use kube::{
+    api::{Api, Patch, PatchParams, PostParams},
+    runtime::{watcher, WatchStreamExt},
+    Client,
+};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use crate::services::kubernetes::{get_current_namespace, managed_labels};
+
+/// Attempts at a compare-and-swap write before giving up on a conflicting Secret.
+const WRITE_ATTEMPTS: usize = 5;
+
+/// Prefix of the keys that record the one refresh token each session may still use.
+const REFRESH_PREFIX: &str = "refresh.";
+
+/// Where a refresh token stands against the revocation list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshStatus {
+    Usable,
+    /// The session was ended by a logout or an earlier reuse.
+    SessionRevoked,
+    /// The session has since been refreshed with a newer token. Refresh tokens are
+    /// single-use, so this one was stolen.
+    Reused,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Entries {
+    /// Revoked token or session id, and the unix time its tokens expire.
+    revoked: HashMap<String, i64>,
+    /// Session id, and the id and expiry of the refresh token it was last given.
+    refresh: HashMap<String, (String, i64)>,
+}
+
+impl Entries {
+    fn refresh_token(&self, sid: &str, now: i64) -> Option<&str> {
+        self.refresh.get(sid)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(jti, _)| jti.as_str())
+    }
+
+    /// Data keys of the entries that have lapsed by `now`.
+    fn lapsed(&self, now: i64) -> impl Iterator<Item = String> + '_ {
+        let revoked = self.revoked.iter()
+            .filter(move |(_, expires)| **expires <= now)
+            .map(|(id, _)| id.clone());
+        let refresh = self.refresh.iter()
+            .filter(move |(_, (_, expires))| *expires <= now)
+            .map(|(sid, _)| format!("{}{}", REFRESH_PREFIX, sid));
+        revoked.chain(refresh)
+    }
+}
+
+/// Revoked token and session ids, and the refresh token each session may still use,
+/// persisted in a Secret so that they survive restarts and are shared across replicas.
+///
+/// A revoked id is stored under its own key, with the unix time after which the entry is
+/// pointless because every token carrying it has expired. A session's current refresh token
+/// is stored under `refresh.<sid>` as `<expires> <jti>`, so the Secret holds one entry per
+/// session rather than one per refresh. Lookups are served from an in-memory copy that a
+/// watch on the Secret keeps current.
+pub struct RevocationList {
+    client: Client,
+    namespace: String,
+    secret_name: String,
+    entries: RwLock<Entries>,
+}
+
+fn parse_entries(secret: &Secret) -> Entries {
+    let mut entries = Entries::default();
+    for (key, value) in secret.data.iter().flatten() {
+        let Ok(value) = std::str::from_utf8(&value.0) else { continue };
+        match key.strip_prefix(REFRESH_PREFIX) {
+            Some(sid) => {
+                let Some((expires, jti)) = value.split_once(' ') else { continue };
+                let Ok(expires) = expires.parse() else { continue };
+                entries.refresh.insert(sid.to_string(), (jti.to_string(), expires));
+            }
+            None => {
+                let Ok(expires) = value.parse() else { continue };
+                entries.revoked.insert(key.clone(), expires);
+            }
+        }
+    }
+    entries
+}
+
+impl RevocationList {
+    pub fn new(client: Client, secret_name: &str) -> Self {
+        Self {
+            client,
+            namespace: get_current_namespace(),
+            secret_name: secret_name.to_string(),
+            entries: RwLock::new(Entries::default()),
+        }
+    }
+
+    fn api(&self) -> Api<Secret> {
+        Api::namespaced(self.client.clone(), &self.namespace)
+    }
+
+    pub fn is_revoked(&self, id: &str) -> bool {
+        let entries = self.entries.read().expect("revocation lock poisoned");
+        entries.revoked.get(id).is_some_and(|&expires| expires > Utc::now().timestamp())
+    }
+
+    /// Checks a refresh token of session `sid` with id `jti`. A session that was never
+    /// refreshed has no record yet, so the token from its login is usable.
+    pub fn refresh_status(&self, sid: &str, jti: &str) -> RefreshStatus {
+        if self.is_revoked(sid) {
+            return RefreshStatus::SessionRevoked;
+        }
+        let entries = self.entries.read().expect("revocation lock poisoned");
+        match entries.refresh_token(sid, Utc::now().timestamp()) {
+            Some(current) if current != jti => RefreshStatus::Reused,
+            _ => RefreshStatus::Usable,
+        }
+    }
+
+    /// Loads the persisted list, replacing the in-memory copy.
+    pub async fn sync(&self) -> Result<()> {
+        let entries = match self.api().get_opt(&self.secret_name).await? {
+            Some(secret) => parse_entries(&secret),
+            None => Entries::default(),
+        };
+        *self.entries.write().expect("revocation lock poisoned") = entries;
+        Ok(())
+    }
+
+    /// Follows changes made by other replicas for as long as the process runs.
+    pub fn spawn_watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let config = watcher::Config::default().fields(&format!("metadata.name={}", self.secret_name));
+            let mut stream = watcher(self.api(), config)
+                .default_backoff()
+                .applied_objects()
+                .boxed();
+
+            while let Some(event) = stream.next().await {
+                match event {
+                    Ok(secret) => {
+                        *self.entries.write().expect("revocation lock poisoned") = parse_entries(&secret);
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Revocation list watch error"),
+                }
+            }
+        });
+    }
+
+    /// Revokes `id` until `expires_at` (unix seconds), pruning entries that have lapsed.
+    pub async fn revoke(&self, id: &str, expires_at: i64) -> Result<()> {
+        if id.is_empty() {
+            return Ok(());
+        }
+        self.entries.write().expect("revocation lock poisoned").revoked.insert(id.to_string(), expires_at);
+        self.persist(id, expires_at.to_string(), |_| true).await?;
+        Ok(())
+    }
+
+    /// Spends the refresh token `jti` of session `sid`, making `next`, valid until
+    /// `expires_at`, the only one the session may use from now on. Returns `false` when the
+    /// stored record shows `jti` was already spent, which catches a reuse even when the first
+    /// use went to another replica.
+    pub async fn rotate(&self, sid: &str, jti: &str, next: &str, expires_at: i64) -> Result<bool> {
+        let key = format!("{}{}", REFRESH_PREFIX, sid);
+        let value = format!("{} {}", expires_at, next);
+        let now = Utc::now().timestamp();
+        let rotated = self.persist(&key, value, |entries| entries.refresh_token(sid, now).is_none_or(|current| current == jti)).await?;
+        if rotated {
+            self.entries.write().expect("revocation lock poisoned").refresh.insert(sid.to_string(), (next.to_string(), expires_at));
+        }
+        Ok(rotated)
+    }
+
+    /// Stores `value` under `key` if the persisted entries satisfy `precondition`, retrying
+    /// when another replica writes in between. Returns whether the value was stored.
+    async fn persist(&self, key: &str, value: String, precondition: impl Fn(&Entries) -> bool) -> Result<bool> {
+        let mut last_error = None;
+        for _ in 0..WRITE_ATTEMPTS {
+            match self.try_persist(key, &value, &precondition).await {
+                Ok(stored) => return Ok(stored),
+                Err(kube::Error::Api(e)) if e.code == 409 => last_error = Some(kube::Error::Api(e)),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("Failed to persist revocation after {} attempts: {:?}", WRITE_ATTEMPTS, last_error))
+    }
+
+    async fn try_persist(&self, key: &str, value: &str, precondition: &impl Fn(&Entries) -> bool) -> Result<bool, kube::Error> {
+        let now = Utc::now().timestamp();
+        let value = ByteString(value.as_bytes().to_vec());
+
+        match self.api().get_opt(&self.secret_name).await? {
+            Some(secret) => {
+                let entries = parse_entries(&secret);
+                if !precondition(&entries) {
+                    return Ok(false);
+                }
+                let mut data: serde_json::Map<String, serde_json::Value> = entries.lapsed(now)
+                    .map(|key| (key, serde_json::Value::Null))
+                    .collect();
+                data.insert(key.to_string(), json!(value));
+
+                // Including the resourceVersion turns the merge patch into a compare-and-swap.
+                let patch = json!({
+                    "metadata": { "resourceVersion": secret.metadata.resource_version },
+                    "data": data,
+                });
+                self.api()
+                    .patch(&self.secret_name, &PatchParams::default(), &Patch::Merge(&patch))
+                    .await?;
+            }
+            None => {
+                if !precondition(&Entries::default()) {
+                    return Ok(false);
+                }
+                let secret = Secret {
+                    metadata: ObjectMeta {
+                        name: Some(self.secret_name.clone()),
+                        labels: Some(managed_labels()),
+                        ..Default::default()
+                    },
+                    type_: Some("Opaque".to_string()),
+                    data: Some(BTreeMap::from([(key.to_string(), value)])),
+                    ..Default::default()
+                };
+                self.api().create(&PostParams::default(), &secret).await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::{Method, StatusCode, Uri}, response::IntoResponse, Json, Router};
+    use std::sync::Mutex;
+
+    fn client(url: &str) -> Client {
+        Client::try_from(kube::Config::new(url.parse().unwrap())).unwrap()
+    }
+
+    fn list(revoked: &[(&str, i64)], refresh: &[(&str, &str, i64)]) -> RevocationList {
+        let list = RevocationList::new(client("http://127.0.0.1:1"), "kanrigate-revocations");
+        {
+            let mut entries = list.entries.write().unwrap();
+            entries.revoked.extend(revoked.iter().map(|(id, expires)| (id.to_string(), *expires)));
+            entries.refresh.extend(refresh.iter().map(|(sid, jti, expires)| (sid.to_string(), (jti.to_string(), *expires))));
+        }
+        list
+    }
+
+    #[tokio::test]
+    async fn superseded_refresh_token_is_reuse() {
+        let later = Utc::now().timestamp() + 3600;
+        let list = list(&[], &[("sid", "jti-2", later)]);
+        assert_eq!(list.refresh_status("sid", "jti-1"), RefreshStatus::Reused);
+        assert_eq!(list.refresh_status("sid", "jti-2"), RefreshStatus::Usable);
+        assert_eq!(list.refresh_status("other", "jti-1"), RefreshStatus::Usable);
+    }
+
+    #[tokio::test]
+    async fn revoked_session_outranks_reuse() {
+        let later = Utc::now().timestamp() + 3600;
+        let list = list(&[("sid", later)], &[("sid", "jti-2", later)]);
+        assert_eq!(list.refresh_status("sid", "jti-1"), RefreshStatus::SessionRevoked);
+        assert_eq!(list.refresh_status("sid", "jti-2"), RefreshStatus::SessionRevoked);
+    }
+
+    #[tokio::test]
+    async fn lapsed_entries_no_longer_revoke() {
+        let earlier = Utc::now().timestamp() - 1;
+        let list = list(&[("sid", earlier)], &[("sid", "jti-2", earlier)]);
+        assert_eq!(list.refresh_status("sid", "jti-1"), RefreshStatus::Usable);
+    }
+
+    #[test]
+    fn unparseable_entries_are_ignored() {
+        let secret = Secret {
+            data: Some(BTreeMap::from([
+                ("good".to_string(), ByteString(b"1700000000".to_vec())),
+                ("bad".to_string(), ByteString(b"soon".to_vec())),
+                ("refresh.sid".to_string(), ByteString(b"1700000000 jti".to_vec())),
+                ("refresh.bad".to_string(), ByteString(b"1700000000".to_vec())),
+            ])),
+            ..Default::default()
+        };
+        let entries = parse_entries(&secret);
+        assert_eq!(entries.revoked, HashMap::from([("good".to_string(), 1700000000)]));
+        assert_eq!(entries.refresh, HashMap::from([("sid".to_string(), ("jti".to_string(), 1700000000))]));
+    }
+
+    #[test]
+    fn lapsed_entries_are_listed_by_data_key() {
+        let entries = Entries {
+            revoked: HashMap::from([("old".to_string(), 10), ("new".to_string(), 30)]),
+            refresh: HashMap::from([("sid-old".to_string(), ("a".to_string(), 10)), ("sid-new".to_string(), ("b".to_string(), 30))]),
+        };
+        let mut lapsed: Vec<String> = entries.lapsed(20).collect();
+        lapsed.sort();
+        assert_eq!(lapsed, ["old", "refresh.sid-old"]);
+    }
+
+    /// The persisted Secret, and every request that reached the API server.
+    type Server = Arc<Mutex<(Secret, Vec<String>)>>;
+
+    async fn serve(State(server): State<Server>, method: Method, uri: Uri) -> axum::response::Response {
+        let mut server = server.lock().unwrap();
+        server.1.push(format!("{} {}", method, uri.path()));
+        match method {
+            Method::GET | Method::PATCH => Json(server.0.clone()).into_response(),
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+
+    /// A list whose in-memory copy is empty, as on a replica that has not yet seen a write
+    /// made by another one, backed by an API server holding `data`.
+    async fn replica(data: &[(&str, &str)]) -> (RevocationList, Server) {
+        let secret = Secret {
+            metadata: ObjectMeta { name: Some("kanrigate-revocations".to_string()), resource_version: Some("1".to_string()), ..Default::default() },
+            data: Some(data.iter().map(|(key, value)| (key.to_string(), ByteString(value.as_bytes().to_vec()))).collect()),
+            ..Default::default()
+        };
+        let server: Server = Arc::new(Mutex::new((secret, Vec::new())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().fallback(serve).with_state(server.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (RevocationList::new(client(&url), "kanrigate-revocations"), server)
+    }
+
+    #[tokio::test]
+    async fn reuse_is_caught_from_the_stored_record() {
+        let later = (Utc::now().timestamp() + 3600).to_string();
+        let (list, server) = replica(&[("refresh.sid", &format!("{} jti-2", later))]).await;
+        assert_eq!(list.refresh_status("sid", "jti-1"), RefreshStatus::Usable);
+
+        assert!(!list.rotate("sid", "jti-1", "jti-3", Utc::now().timestamp() + 3600).await.unwrap());
+        assert!(!server.lock().unwrap().1.iter().any(|call| call.starts_with("PATCH")));
+    }
+
+    #[tokio::test]
+    async fn rotation_replaces_the_record_and_prunes_lapsed_entries() {
+        let earlier = (Utc::now().timestamp() - 1).to_string();
+        let later = Utc::now().timestamp() + 3600;
+        let (list, server) = replica(&[("refresh.sid", &format!("{} jti-1", later)), ("gone", &earlier)]).await;
+
+        assert!(list.rotate("sid", "jti-1", "jti-2", later).await.unwrap());
+        assert!(server.lock().unwrap().1.iter().any(|call| call.starts_with("PATCH")));
+        assert_eq!(list.refresh_status("sid", "jti-1"), RefreshStatus::Reused);
+        assert_eq!(list.refresh_status("sid", "jti-2"), RefreshStatus::Usable);
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use futures::StreamExt;
+use k8s_openapi::ByteString;
+use kube::{
+    api::{Api, Patch, PatchParams, PostParams},
+    runtime::{watcher, WatchStreamExt},
+    Client,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use crate::services::kubernetes::{get_current_namespace, managed_labels};
+
+/// Records kept as one JSON document per data key in a Secret in the KanriGate namespace.
+///
+/// Updates are merge patches guarded by the Secret's `resourceVersion`, so concurrent
+/// writers from several replicas fail with a conflict instead of overwriting each other.
+#[derive(Clone)]
+pub struct JsonSecretStore<T> {
+    client: Client,
+    namespace: String,
+    secret_name: String,
+    _record: PhantomData<fn() -> T>,
+}
+
+fn parse_records<T: DeserializeOwned>(secret_name: &str, secret: &Secret) -> BTreeMap<String, T> {
+    let mut records = BTreeMap::new();
+    for (key, value) in secret.data.iter().flatten() {
+        match serde_json::from_slice::<T>(&value.0) {
+            Ok(record) => {
+                records.insert(key.clone(), record);
+            }
+            Err(e) => tracing::warn!(secret = %secret_name, key = %key, error = %e, "Skipping malformed record"),
+        }
+    }
+    records
+}
+
+impl<T: Serialize + DeserializeOwned> JsonSecretStore<T> {
+    pub fn new(client: Client, secret_name: &str) -> Self {
+        Self {
+            client,
+            namespace: get_current_namespace(),
+            secret_name: secret_name.to_string(),
+            _record: PhantomData,
+        }
+    }
+
+    fn api(&self) -> Api<Secret> {
+        Api::namespaced(self.client.clone(), &self.namespace)
+    }
+
+    /// All records, with the `resourceVersion` to pass back to `write`.
+    pub async fn load(&self) -> Result<(BTreeMap<String, T>, Option<String>)> {
+        let secret = match self.api().get_opt(&self.secret_name).await? {
+            Some(s) => s,
+            None => return Ok((BTreeMap::new(), None)),
+        };
+
+        Ok((parse_records(&self.secret_name, &secret), secret.metadata.resource_version))
+    }
+
+    /// Calls `apply` with the full record set whenever the Secret changes, for as long as
+    /// the process runs.
+    pub fn spawn_watch<F>(&self, apply: F)
+    where
+        T: 'static,
+        F: Fn(BTreeMap<String, T>) + Send + 'static,
+    {
+        let api = self.api();
+        let secret_name = self.secret_name.clone();
+        tokio::spawn(async move {
+            let config = watcher::Config::default().fields(&format!("metadata.name={}", secret_name));
+            let mut stream = watcher(api, config)
+                .default_backoff()
+                .applied_objects()
+                .boxed();
+
+            while let Some(event) = stream.next().await {
+                match event {
+                    Ok(secret) => apply(parse_records(&secret_name, &secret)),
+                    Err(e) => tracing::warn!(secret = %secret_name, error = %e, "Secret watch error"),
+                }
+            }
+        });
+    }
+
+    /// Stores `record` under `key`, or deletes the key when `record` is `None`.
+    pub async fn write(&self, resource_version: Option<String>, key: &str, record: Option<&T>) -> Result<()> {
+        let value = match record {
+            Some(r) => json!(BASE64.encode(serde_json::to_vec(r)?)),
+            None => serde_json::Value::Null,
+        };
+
+        match resource_version {
+            Some(rv) => {
+                // Including the resourceVersion turns the merge patch into a compare-and-swap.
+                let patch = json!({
+                    "metadata": { "resourceVersion": rv },
+                    "data": { key: value },
+                });
+                self.api()
+                    .patch(&self.secret_name, &PatchParams::default(), &Patch::Merge(&patch))
+                    .await?;
+            }
+            None => {
+                let record = record.ok_or_else(|| anyhow!("{} not found", key))?;
+                let mut data = BTreeMap::new();
+                data.insert(key.to_string(), ByteString(serde_json::to_vec(record)?));
+                let secret = Secret {
+                    metadata: ObjectMeta {
+                        name: Some(self.secret_name.clone()),
+                        labels: Some(managed_labels()),
+                        ..Default::default()
+                    },
+                    type_: Some("Opaque".to_string()),
+                    data: Some(data),
+                    ..Default::default()
+                };
+                self.api().create(&PostParams::default(), &secret).await?;
+            }
+        }
+        Ok(())
+    }
+}
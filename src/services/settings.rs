@@ -0,0 +1,88 @@
+use anyhow::{anyhow, bail, Result};
+use kube::Client;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+use crate::config::{BaseConfig, RELOADABLE_KEYS};
+use crate::services::secret_store::JsonSecretStore;
+
+/// Configuration changed at runtime, persisted in the `settings_secret` Secret.
+///
+/// Each data key is a `BaseConfig` key from `RELOADABLE_KEYS` holding a JSON string. The
+/// entries override the environment, and every replica rebuilds its `BaseConfig` when the
+/// Secret changes, so updates take effect without a restart.
+pub struct SettingsStore {
+    store: JsonSecretStore<String>,
+    current: watch::Sender<Arc<BaseConfig>>,
+}
+
+impl SettingsStore {
+    pub fn new(client: Client, config: Arc<BaseConfig>) -> Self {
+        let store = JsonSecretStore::new(client, &config.settings_secret);
+        let (current, _) = watch::channel(config);
+        Self { store, current }
+    }
+
+    /// The configuration in effect right now.
+    pub fn current(&self) -> Arc<BaseConfig> {
+        self.current.borrow().clone()
+    }
+
+    /// Receives every configuration this store applies.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<BaseConfig>> {
+        self.current.subscribe()
+    }
+
+    /// Loads the persisted settings and applies them.
+    pub async fn sync(&self) -> Result<()> {
+        let (overrides, _) = self.store.load().await?;
+        apply(&self.current, overrides).await
+    }
+
+    /// Follows changes made by other replicas or by hand.
+    pub fn spawn_watch(self: &Arc<Self>) {
+        let this = Arc::clone(self);
+        self.store.spawn_watch(move |overrides| {
+            let this = Arc::clone(&this);
+            tokio::spawn(async move {
+                if let Err(e) = apply(&this.current, overrides).await {
+                    tracing::error!(error = %e, "Ignoring invalid persisted settings");
+                }
+            });
+        });
+    }
+
+    /// Persists `value` for `key` and applies it on this replica immediately.
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        if !RELOADABLE_KEYS.contains(&key) {
+            bail!("{} cannot be changed at runtime", key);
+        }
+        let (mut overrides, rv) = self.store.load().await?;
+        self.store.write(rv, key, Some(&value.to_string())).await?;
+        overrides.insert(key.to_string(), value.to_string());
+        apply(&self.current, overrides).await
+    }
+}
+
+/// Rebuilds `BaseConfig` with `overrides` on top of the environment and publishes it.
+async fn apply(current: &watch::Sender<Arc<BaseConfig>>, overrides: BTreeMap<String, String>) -> Result<()> {
+    let overrides: BTreeMap<String, String> = overrides.into_iter()
+        .filter(|(key, _)| {
+            let known = RELOADABLE_KEYS.contains(&key.as_str());
+            if !known {
+                tracing::warn!(key = %key, "Ignoring setting that cannot be changed at runtime");
+            }
+            known
+        })
+        .collect();
+
+    // Loading validates the control plane address with a blocking HTTP request.
+    let keys: Vec<String> = overrides.keys().cloned().collect();
+    let config = tokio::task::spawn_blocking(move || BaseConfig::load(&overrides).map_err(|e| anyhow!("{}", e)))
+        .await??;
+    current.send_replace(Arc::new(config));
+    if !keys.is_empty() {
+        tracing::info!(keys = ?keys, "Applied persisted settings");
+    }
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{
+    api::{Api, DeleteParams, ListParams, PostParams},
+    Client, ResourceExt,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+use crate::config::templates;
+use crate::services::kubernetes::{create_or_accept, managed_labels, Applied, Conflict, NotManaged, TemplateInfo, TemplateScope};
+
+/// Verbs that let their holder grant, or act with, permissions the template does not list.
+pub const PRIVILEGED_VERBS: [&str; 3] = ["escalate", "bind", "impersonate"];
+
+/// One rule of a template, i.e. a ClusterRole `PolicyRule`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TemplateRule {
+    /// API groups of `resources`; `""` is the core group.
+    #[serde(default)]
+    pub api_groups: Vec<String>,
+    #[serde(default)]
+    pub resources: Vec<String>,
+    /// Restricts the rule to these objects; empty means all of them.
+    #[serde(default)]
+    pub resource_names: Vec<String>,
+    /// Non-resource paths such as `/metrics`; cluster templates only.
+    #[serde(default)]
+    pub non_resource_urls: Vec<String>,
+    pub verbs: Vec<String>,
+}
+
+fn non_empty(values: &[String]) -> Option<Vec<String>> {
+    (!values.is_empty()).then(|| values.to_vec())
+}
+
+impl From<&TemplateRule> for PolicyRule {
+    fn from(rule: &TemplateRule) -> Self {
+        PolicyRule {
+            api_groups: if rule.resources.is_empty() { None } else { Some(rule.api_groups.clone()) },
+            resources: non_empty(&rule.resources),
+            resource_names: non_empty(&rule.resource_names),
+            non_resource_urls: non_empty(&rule.non_resource_urls),
+            verbs: rule.verbs.clone(),
+        }
+    }
+}
+
+impl From<&PolicyRule> for TemplateRule {
+    fn from(rule: &PolicyRule) -> Self {
+        TemplateRule {
+            api_groups: rule.api_groups.clone().unwrap_or_default(),
+            resources: rule.resources.clone().unwrap_or_default(),
+            resource_names: rule.resource_names.clone().unwrap_or_default(),
+            non_resource_urls: rule.non_resource_urls.clone().unwrap_or_default(),
+            verbs: rule.verbs.clone(),
+        }
+    }
+}
+
+/// Why `rules` cannot make up a template of `scope`, if they cannot. A `*` verb on
+/// resources counts as privileged, since it includes every verb in `PRIVILEGED_VERBS`.
+pub fn rules_violation(scope: TemplateScope, rules: &[TemplateRule], allow_privileged_verbs: bool) -> Option<String> {
+    if rules.is_empty() {
+        return Some("A template needs at least one rule".to_string());
+    }
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.verbs.is_empty() || rule.verbs.iter().any(|v| v.is_empty()) {
+            return Some(format!("rules[{}] needs non-empty verbs", i));
+        }
+        match (rule.resources.is_empty(), rule.non_resource_urls.is_empty()) {
+            (true, true) => return Some(format!("rules[{}] needs resources or non_resource_urls", i)),
+            (false, false) => return Some(format!("rules[{}] cannot mix resources and non_resource_urls", i)),
+            (true, false) if scope == TemplateScope::Namespaced => {
+                return Some(format!("rules[{}]: non_resource_urls only apply to cluster templates", i));
+            }
+            (false, true) if rule.api_groups.is_empty() => {
+                return Some(format!("rules[{}] needs api_groups; use \"\" for the core group", i));
+            }
+            _ => {}
+        }
+        if allow_privileged_verbs || rule.resources.is_empty() {
+            continue;
+        }
+        if let Some(verb) = rule.verbs.iter().find(|v| *v == "*" || PRIVILEGED_VERBS.contains(&v.as_str())) {
+            return Some(format!(
+                "rules[{}] uses the privileged verb '{}'; set allow_privileged_verbs to permit it",
+                i, verb
+            ));
+        }
+    }
+    None
+}
+
+/// What a template is made of, as sent to `createTemplate` and `updateTemplate`.
+pub struct TemplateSpec {
+    pub name: String,
+    pub scope: TemplateScope,
+    pub description: Option<String>,
+    pub rules: Vec<TemplateRule>,
+}
+
+/// A template with its rules, as returned by `getTemplate`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TemplateDetail {
+    pub name: String,
+    pub scope: TemplateScope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub rules: Vec<TemplateRule>,
+    /// Whether KanriGate created the template, as opposed to the Helm chart or someone else.
+    pub managed: bool,
+}
+
+impl TemplateDetail {
+    fn from_cluster_role(role: &ClusterRole) -> Option<Self> {
+        let info = TemplateInfo::from_cluster_role(role)?;
+        Some(Self {
+            name: info.name,
+            scope: info.scope,
+            description: info.description,
+            rules: role.rules.iter().flatten().map(TemplateRule::from).collect(),
+            managed: is_managed(role),
+        })
+    }
+}
+
+/// The bindings that reference a template and so change with it.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct BlastRadius {
+    pub role_bindings: usize,
+    pub cluster_role_bindings: usize,
+    /// Namespaces holding the RoleBindings.
+    pub namespaces: Vec<String>,
+    /// Bound subjects, as `Kind/name` or `ServiceAccount/namespace/name`.
+    pub subjects: Vec<String>,
+}
+
+impl BlastRadius {
+    pub fn bindings(&self) -> usize {
+        self.role_bindings + self.cluster_role_bindings
+    }
+}
+
+/// Result of `updateTemplate`: the template as written, or as it would be on a dry run,
+/// and the bindings that pick up the change.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TemplateChanged {
+    pub template: TemplateDetail,
+    pub blast_radius: BlastRadius,
+    pub dry_run: bool,
+}
+
+fn subject_name(subject: &Subject) -> String {
+    match &subject.namespace {
+        Some(namespace) if subject.kind == "ServiceAccount" => format!("{}/{}/{}", subject.kind, namespace, subject.name),
+        _ => format!("{}/{}", subject.kind, subject.name),
+    }
+}
+
+/// Whether KanriGate created the template, as opposed to a chart or another tool that would
+/// put back, or fight over, any change made here.
+fn is_managed(role: &ClusterRole) -> bool {
+    managed_labels().iter().all(|(k, v)| role.labels().get(k) == Some(v))
+}
+
+fn not_managed(name: &str) -> anyhow::Error {
+    NotManaged(format!("Template {} is not managed by KanriGate; change it where it is defined, e.g. the Helm chart", name)).into()
+}
+
+fn references(role_ref: &RoleRef, role_name: &str) -> bool {
+    role_ref.kind == "ClusterRole" && role_ref.name == role_name
+}
+
+/// Creates, updates and deletes the template ClusterRoles.
+pub struct TemplateManager {
+    client: Client,
+}
+
+impl TemplateManager {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn api(&self) -> Api<ClusterRole> {
+        Api::all(self.client.clone())
+    }
+
+    pub async fn get(&self, scope: TemplateScope, name: &str) -> Result<Option<TemplateDetail>> {
+        let role = self.api().get_opt(&scope.role_name(name)).await?;
+        Ok(role.as_ref().and_then(TemplateDetail::from_cluster_role))
+    }
+
+    /// Creates the template; an existing one with the same rules and description is accepted.
+    pub async fn create(&self, spec: &TemplateSpec) -> Result<Applied> {
+        let mut annotations = BTreeMap::new();
+        if let Some(description) = &spec.description {
+            annotations.insert(templates::ANNOTATIONS_DESCRIPTION.to_string(), description.clone());
+        }
+        let role = ClusterRole {
+            metadata: ObjectMeta {
+                name: Some(spec.scope.role_name(&spec.name)),
+                labels: Some(managed_labels()),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            rules: Some(spec.rules.iter().map(PolicyRule::from).collect()),
+            ..Default::default()
+        };
+        create_or_accept(&self.api(), &role, |existing| {
+            if existing.rules != role.rules {
+                Some("rules")
+            } else if existing.annotations().get(templates::ANNOTATIONS_DESCRIPTION) != spec.description.as_ref() {
+                Some("description")
+            } else {
+                None
+            }
+        })
+        .await
+    }
+
+    /// Replaces the rules and description of an existing template. `None` when there is
+    /// no such template, `NotManaged` when KanriGate did not create it. With `dry_run` the
+    /// API server validates the change but keeps it.
+    pub async fn update(&self, spec: &TemplateSpec, dry_run: bool) -> Result<Option<TemplateChanged>> {
+        let role_name = spec.scope.role_name(&spec.name);
+        let mut role = match self.api().get_opt(&role_name).await? {
+            Some(role) if is_managed(&role) => role,
+            Some(_) => return Err(not_managed(&spec.name)),
+            None => return Ok(None),
+        };
+        let blast_radius = self.blast_radius(&role_name).await?;
+
+        let annotations = role.annotations_mut();
+        match &spec.description {
+            Some(description) => annotations.insert(templates::ANNOTATIONS_DESCRIPTION.to_string(), description.clone()),
+            None => annotations.remove(templates::ANNOTATIONS_DESCRIPTION),
+        };
+        role.rules = Some(spec.rules.iter().map(PolicyRule::from).collect());
+
+        let params = PostParams { dry_run, ..Default::default() };
+        let replaced = self.api().replace(&role_name, &params, &role).await?;
+        Ok(TemplateDetail::from_cluster_role(&replaced).map(|template| TemplateChanged { template, blast_radius, dry_run }))
+    }
+
+    /// Deletes the template and returns the bindings left referencing it, which then grant
+    /// nothing. Unless `force` is set, a template that is still bound is a `Conflict`.
+    /// `None` when there is no such template, `NotManaged` when KanriGate did not create it.
+    pub async fn delete(&self, scope: TemplateScope, name: &str, force: bool) -> Result<Option<BlastRadius>> {
+        let role_name = scope.role_name(name);
+        match self.api().get_opt(&role_name).await? {
+            Some(role) if is_managed(&role) => {}
+            Some(_) => return Err(not_managed(name)),
+            None => return Ok(None),
+        }
+        let blast_radius = self.blast_radius(&role_name).await?;
+        if blast_radius.bindings() > 0 && !force {
+            return Err(Conflict(format!(
+                "Template {} is referenced by {} bindings; delete them first or pass force=true",
+                name,
+                blast_radius.bindings()
+            ))
+            .into());
+        }
+        self.api().delete(&role_name, &DeleteParams::default()).await?;
+        Ok(Some(blast_radius))
+    }
+
+    /// Every RoleBinding and ClusterRoleBinding in the cluster that references `role_name`,
+    /// whether or not KanriGate created it.
+    async fn blast_radius(&self, role_name: &str) -> Result<BlastRadius> {
+        let role_bindings: Api<RoleBinding> = Api::all(self.client.clone());
+        let cluster_role_bindings: Api<ClusterRoleBinding> = Api::all(self.client.clone());
+        let params = ListParams::default();
+        let (rbs, crbs) = futures::try_join!(role_bindings.list(&params), cluster_role_bindings.list(&params))?;
+
+        let mut radius = BlastRadius::default();
+        let mut namespaces = BTreeSet::new();
+        let mut subjects = BTreeSet::new();
+        for rb in rbs.iter().filter(|rb| references(&rb.role_ref, role_name)) {
+            radius.role_bindings += 1;
+            namespaces.extend(rb.namespace());
+            subjects.extend(rb.subjects.iter().flatten().map(subject_name));
+        }
+        for crb in crbs.iter().filter(|crb| references(&crb.role_ref, role_name)) {
+            radius.cluster_role_bindings += 1;
+            subjects.extend(crb.subjects.iter().flatten().map(subject_name));
+        }
+        radius.namespaces = namespaces.into_iter().collect();
+        radius.subjects = subjects.into_iter().collect();
+        Ok(radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(resources: &[&str], verbs: &[&str]) -> TemplateRule {
+        TemplateRule {
+            api_groups: vec![String::new()],
+            resources: resources.iter().map(|r| r.to_string()).collect(),
+            verbs: verbs.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn privileged_verbs_need_explicit_permission() {
+        for verb in PRIVILEGED_VERBS.iter().chain(&["*"]) {
+            let rules = [rule(&["pods"], &["get"]), rule(&["roles"], &["get", verb])];
+            let violation = rules_violation(TemplateScope::Namespaced, &rules, false).unwrap();
+            assert!(violation.starts_with("rules[1]") && violation.contains(verb), "{}", violation);
+            assert_eq!(rules_violation(TemplateScope::Namespaced, &rules, true), None);
+        }
+    }
+
+    #[test]
+    fn only_templates_with_kanrigate_labels_are_managed() {
+        let mut role = ClusterRole {
+            metadata: ObjectMeta { labels: Some(managed_labels()), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(is_managed(&role));
+        role.metadata.labels = Some(BTreeMap::from([("app.kubernetes.io/managed-by".to_string(), "Helm".to_string())]));
+        assert!(!is_managed(&role));
+    }
+
+    #[test]
+    fn ordinary_verbs_are_accepted() {
+        let rules = [rule(&["pods", "pods/log"], &["get", "list", "watch"]), rule(&["configmaps"], &["create", "update", "delete"])];
+        assert_eq!(rules_violation(TemplateScope::Namespaced, &rules, false), None);
+    }
+
+    #[test]
+    fn non_resource_urls_only_fit_cluster_templates() {
+        let metrics = TemplateRule {
+            non_resource_urls: vec!["/metrics".to_string()],
+            verbs: vec!["*".to_string()],
+            ..Default::default()
+        };
+        // `*` on a URL is not privileged: it can only mean HTTP verbs on that path.
+        assert_eq!(rules_violation(TemplateScope::Cluster, std::slice::from_ref(&metrics), false), None);
+        assert!(rules_violation(TemplateScope::Namespaced, &[metrics], false).unwrap().contains("cluster templates"));
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        assert!(rules_violation(TemplateScope::Cluster, &[], false).is_some());
+        assert!(rules_violation(TemplateScope::Cluster, &[rule(&["pods"], &[])], false).unwrap().contains("verbs"));
+        assert!(rules_violation(TemplateScope::Cluster, &[rule(&["pods"], &[""])], false).unwrap().contains("verbs"));
+        assert!(rules_violation(TemplateScope::Cluster, &[rule(&[], &["get"])], false).unwrap().contains("resources"));
+
+        let mut mixed = rule(&["pods"], &["get"]);
+        mixed.non_resource_urls = vec!["/healthz".to_string()];
+        assert!(rules_violation(TemplateScope::Cluster, &[mixed], false).unwrap().contains("cannot mix"));
+
+        let mut no_groups = rule(&["pods"], &["get"]);
+        no_groups.api_groups.clear();
+        assert!(rules_violation(TemplateScope::Cluster, &[no_groups], false).unwrap().contains("api_groups"));
+    }
+
+    #[test]
+    fn rules_round_trip_through_policy_rules() {
+        let original = rule(&["deployments"], &["get", "patch"]);
+        let policy = PolicyRule::from(&original);
+        assert_eq!(policy.resource_names, None);
+        assert_eq!(TemplateRule::from(&policy), original);
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use k8s_openapi::api::authentication::v1::{TokenReview, TokenReviewSpec, UserInfo};
+use k8s_openapi::api::authorization::v1::{ResourceAttributes, SubjectAccessReview, SubjectAccessReviewSpec};
+use kube::{
+    api::{Api, PostParams},
+    Client,
+};
+use crate::config::BaseConfig;
+use crate::services::authenticator::Role;
+
+/// The cluster identity behind a bearer token that passed a TokenReview.
+#[derive(Debug, Clone)]
+pub struct KubeIdentity {
+    pub username: String,
+    pub groups: Vec<String>,
+    user: UserInfo,
+}
+
+/// Logs users in with their own Kubernetes credentials.
+///
+/// The token is validated by the API server through a TokenReview, and the KanriGate
+/// role is whichever of the configured verbs cluster RBAC allows on the configured
+/// resource, checked with SubjectAccessReviews from most to least privileged.
+pub struct KubeTokenLogin {
+    client: Client,
+    audiences: Vec<String>,
+    api_group: String,
+    resource: String,
+    admin_verb: String,
+    operator_verb: String,
+    viewer_verb: String,
+}
+
+impl KubeTokenLogin {
+    /// Builds the login when `kube_login_enabled` is set, `None` otherwise.
+    pub fn from_config(client: Client, config: &BaseConfig) -> Option<Self> {
+        if !config.kube_login_enabled {
+            return None;
+        }
+        Some(Self {
+            client,
+            audiences: config.kube_login_audiences.clone(),
+            api_group: config.kube_login_api_group.clone(),
+            resource: config.kube_login_resource.clone(),
+            admin_verb: config.kube_login_admin_verb.clone(),
+            operator_verb: config.kube_login_operator_verb.clone(),
+            viewer_verb: config.kube_login_viewer_verb.clone(),
+        })
+    }
+
+    /// Validates `token` with the API server; `Ok(None)` when it is not authenticated.
+    pub async fn authenticate(&self, token: &str) -> Result<Option<KubeIdentity>> {
+        let review = TokenReview {
+            spec: TokenReviewSpec {
+                token: Some(token.to_string()),
+                // An empty list means the API server's own audiences.
+                audiences: (!self.audiences.is_empty()).then(|| self.audiences.clone()),
+            },
+            ..Default::default()
+        };
+        let review = Api::<TokenReview>::all(self.client.clone())
+            .create(&PostParams::default(), &review)
+            .await?;
+
+        let status = review.status.unwrap_or_default();
+        if status.authenticated != Some(true) {
+            if let Some(error) = status.error {
+                tracing::debug!(error = %error, "TokenReview rejected token");
+            }
+            return Ok(None);
+        }
+        let user = match status.user {
+            Some(user) if user.username.as_deref().is_some_and(|u| !u.is_empty()) => user,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(KubeIdentity {
+            username: user.username.clone().unwrap_or_default(),
+            groups: user.groups.clone().unwrap_or_default(),
+            user,
+        }))
+    }
+
+    async fn allowed(&self, identity: &KubeIdentity, verb: &str) -> Result<bool> {
+        let review = SubjectAccessReview {
+            spec: SubjectAccessReviewSpec {
+                user: identity.user.username.clone(),
+                groups: identity.user.groups.clone(),
+                uid: identity.user.uid.clone(),
+                extra: identity.user.extra.clone(),
+                resource_attributes: Some(ResourceAttributes {
+                    group: Some(self.api_group.clone()),
+                    resource: Some(self.resource.clone()),
+                    verb: Some(verb.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let review = Api::<SubjectAccessReview>::all(self.client.clone())
+            .create(&PostParams::default(), &review)
+            .await?;
+        Ok(review.status.is_some_and(|s| s.allowed))
+    }
+
+    /// Highest role cluster RBAC grants the identity, or `None` if it may not use KanriGate.
+    pub async fn role_for(&self, identity: &KubeIdentity) -> Result<Option<Role>> {
+        for (verb, role) in [
+            (&self.admin_verb, Role::Admin),
+            (&self.operator_verb, Role::Operator),
+            (&self.viewer_verb, Role::Viewer),
+        ] {
+            if self.allowed(identity, verb).await? {
+                return Ok(Some(role));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use kube::{Client, ResourceExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::services::{kubernetes::KubeOps, secret_store::JsonSecretStore};
+
+/// Rotations kept per user; older entries are dropped.
+const HISTORY_LIMIT: usize = 20;
+/// `rotated_by` of rotations made by the background job.
+pub const SCHEDULER: &str = "scheduler";
+
+/// One replacement of a user's token Secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRotation {
+    pub rotated_at: DateTime<Utc>,
+    /// Operator who rotated the token, or `scheduler` for the background job.
+    pub rotated_by: String,
+    pub new_secret: String,
+    /// Secrets whose tokens were invalidated.
+    #[serde(default)]
+    pub old_secrets: Vec<String>,
+    /// Set when the rotation failed; the old tokens then remain valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Replaces ServiceAccount token Secrets and records each rotation in the
+/// `token_rotations_secret` Secret, keyed by user.
+pub struct TokenRotator {
+    kube: KubeOps,
+    log: JsonSecretStore<Vec<TokenRotation>>,
+}
+
+impl TokenRotator {
+    pub fn new(client: Client, secret_name: &str) -> Self {
+        Self {
+            kube: KubeOps::new(client.clone()),
+            log: JsonSecretStore::new(client, secret_name),
+        }
+    }
+
+    /// Rotations of `username`, newest first.
+    pub async fn history(&self, username: &str) -> Result<Vec<TokenRotation>> {
+        let (mut records, _) = self.log.load().await?;
+        let mut history = records.remove(username).unwrap_or_default();
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Appends `rotation`, or replaces the newest entry when `replace_last` is set.
+    async fn record(&self, username: &str, rotation: TokenRotation, replace_last: bool) -> Result<()> {
+        let (mut records, rv) = self.log.load().await?;
+        let mut history = records.remove(username).unwrap_or_default();
+        if replace_last {
+            history.pop();
+        }
+        history.push(rotation);
+        let excess = history.len().saturating_sub(HISTORY_LIMIT);
+        history.drain(..excess);
+        self.log.write(rv, username, Some(&history)).await
+    }
+
+    /// Replaces the token Secret of `username` and returns the rotation.
+    ///
+    /// The rotation is recorded before any Secret changes. The record is a compare-and-swap
+    /// write, so when two replicas rotate the same user at once, one of them fails instead of
+    /// both creating tokens.
+    pub async fn rotate(&self, username: &str, rotated_by: &str) -> Result<TokenRotation> {
+        let current = self.kube.user_token_secrets(username).await?;
+        if current.is_empty() {
+            bail!("{} has no token Secret to rotate", username);
+        }
+
+        let now = Utc::now();
+        let mut rotation = TokenRotation {
+            rotated_at: now,
+            rotated_by: rotated_by.to_string(),
+            new_secret: format!("{}-token-{}", username, now.format("%Y%m%d%H%M%S")),
+            old_secrets: current.iter().map(|s| s.name_any()).collect(),
+            error: None,
+        };
+        self.record(username, rotation.clone(), false).await?;
+
+        match self.kube.replace_token_secret(username, &rotation.new_secret).await {
+            Ok(deleted) => {
+                if deleted != rotation.old_secrets {
+                    rotation.old_secrets = deleted;
+                    self.record(username, rotation.clone(), true).await?;
+                }
+                Ok(rotation)
+            }
+            Err(e) => {
+                rotation.error = Some(e.to_string());
+                if let Err(record_error) = self.record(username, rotation, true).await {
+                    tracing::warn!(user = %username, error = %record_error, "Failed to record failed token rotation");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Rotates every token Secret older than `max_age`, once per `interval`, for as long as
+    /// the process runs.
+    pub fn spawn_schedule(self: Arc<Self>, max_age: Duration, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.rotate_expired(max_age).await {
+                    tracing::warn!(error = %e, "Scheduled token rotation failed");
+                }
+            }
+        });
+    }
+
+    async fn rotate_expired(&self, max_age: Duration) -> Result<()> {
+        let cutoff = Utc::now() - max_age;
+        for (username, secrets) in self.kube.token_secrets().await? {
+            // The newest Secret tells when the user's token was last issued.
+            let newest = secrets.iter().filter_map(|s| s.creation_timestamp()).map(|t| t.0).max();
+            if newest.is_none_or(|created| created > cutoff) {
+                continue;
+            }
+            match self.rotate(&username, SCHEDULER).await {
+                Ok(rotation) => tracing::info!(target = %username, new_secret = %rotation.new_secret, old_secrets = ?rotation.old_secrets, "Token rotated on schedule"),
+                Err(e) => tracing::warn!(target = %username, error = %e, "Scheduled token rotation failed"),
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,29 @@
 use kube::Client;
 use std::sync::Arc;
 use crate::config::BaseConfig;
+use crate::models::SecurityPosture;
+use crate::services::{api_keys::ApiKeyStore, authenticator::Authenticator, cache::KubeCache, jwt::JwtKeys, login_guard::LoginGuard, mfa::MfaStore, oidc::OidcClient, revocation::RevocationList, settings::SettingsStore, token_review::KubeTokenLogin};
 
 #[derive(Clone)]
 pub struct AppState {
     pub client: Client,
-    pub config: Arc<BaseConfig>,
+    /// Watch-backed lists served by the read endpoints.
+    pub cache: KubeCache,
+    pub settings: Arc<SettingsStore>,
+    pub authenticator: Arc<dyn Authenticator>,
+    pub jwt: Arc<JwtKeys>,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub kube_login: Option<Arc<KubeTokenLogin>>,
+    pub revocations: Arc<RevocationList>,
+    pub login_guard: Arc<LoginGuard>,
+    pub mfa: Arc<MfaStore>,
+    pub api_keys: Arc<ApiKeyStore>,
+    pub posture: Arc<SecurityPosture>,
+}
+
+impl AppState {
+    /// The configuration in effect, including settings changed at runtime.
+    pub fn config(&self) -> Arc<BaseConfig> {
+        self.settings.current()
+    }
 }
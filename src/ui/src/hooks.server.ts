@@ -1,8 +1,23 @@
 import type { Handle } from '@sveltejs/kit';
 import { redirect } from '@sveltejs/kit';
+import { api } from '$lib/api';
+import { setSessionCookies, clearSessionCookies } from '$lib/server/session';
 
 export const handle: Handle = async ({ event, resolve }) => {
-	const token = event.cookies.get('access_token');
+	let token = event.cookies.get('access_token');
+	const refreshToken = event.cookies.get('refresh_token');
+
+	// The access token cookie expires with the token; renew it from the refresh token.
+	// Proxied API calls carry their own bearer token and must not spend the single-use refresh token.
+	if (!token && refreshToken && !event.url.pathname.startsWith('/apps/')) {
+		try {
+			const response = await api.refresh(refreshToken);
+			setSessionCookies(event.cookies, response.data);
+			token = response.data.access_token;
+		} catch {
+			clearSessionCookies(event.cookies);
+		}
+	}
 
 	if (token) {
 		event.locals.user = { token };
@@ -12,8 +27,10 @@ export const handle: Handle = async ({ event, resolve }) => {
 
 	// Protect routes
 	const isLoginPage = event.url.pathname === '/login';
+	// The SSO round trip must reach the backend before a session exists
+	const isSsoFlow = event.url.pathname.startsWith('/apps/oidc/');
 	
-	if (!event.locals.user && !isLoginPage) {
+	if (!event.locals.user && !isLoginPage && !isSsoFlow) {
 		throw redirect(303, '/login');
 	}
 
@@ -1,5 +1,6 @@
 import { browser } from '$app/environment';
-import { goto } from '$app/navigation';
+import { goto, invalidateAll } from '$app/navigation';
+import { tick } from 'svelte';
 import { env } from '$env/dynamic/public';
 
 // Default ke '/apps' (relatif) agar diproses oleh SvelteKit proxy di server-side.
@@ -15,9 +16,26 @@ export function setClientToken(token: string | null) {
 	}
 }
 
-async function request<T>(endpoint: string, options: RequestInit & { token?: string } = {}): Promise<T> {
+// Concurrent 401s share one renewal, since each refresh token can only be used once
+let renewal: Promise<void> | null = null;
+
+function renewSession(): Promise<void> {
+	renewal ??= invalidateAll()
+		.then(() => tick())
+		.finally(() => {
+			renewal = null;
+		});
+	return renewal;
+}
+
+function readCookie(name: string): string | null {
+	const match = document.cookie.split('; ').find((pair) => pair.startsWith(`${name}=`));
+	return match ? match.slice(name.length + 1) : null;
+}
+
+async function request<T>(endpoint: string, options: RequestInit & { token?: string; retried?: boolean } = {}): Promise<T> {
 	const url = `${BASE_URL}/${endpoint}`;
-	const { token, ...fetchOptions } = options;
+	const { token, retried, ...fetchOptions } = options;
 
 	const headers: Record<string, string> = {
 		'Content-Type': 'application/json',
@@ -31,6 +49,12 @@ async function request<T>(endpoint: string, options: RequestInit & { token?: str
 		headers['Authorization'] = `Bearer ${authToken}`;
 	}
 
+	// Echo the backend's CSRF cookie so requests authenticated by its session cookie are accepted
+	const csrfToken = browser ? readCookie('kanrigate_csrf') : null;
+	if (csrfToken) {
+		headers['X-CSRF-Token'] = csrfToken;
+	}
+
 	// Create a stable abort controller for this specific request
 	const internalController = new AbortController();
 	const timeoutId = setTimeout(() => {
@@ -51,7 +75,17 @@ async function request<T>(endpoint: string, options: RequestInit & { token?: str
 			signal: internalController.signal 
 		});
 
-		if (response.status === 401 && browser && endpoint !== 'login') {
+		// The access token is short-lived: reloading the layout data lets the server hook
+		// renew it from the refresh token cookie, then the request is tried once more.
+		if (response.status === 401 && browser && !token && !retried && !endpoint.startsWith('login')) {
+			const previous = clientToken;
+			await renewSession();
+			if (clientToken && clientToken !== previous) {
+				return request<T>(endpoint, { ...options, retried: true });
+			}
+		}
+
+		if (response.status === 401 && browser && !endpoint.startsWith('login')) {
 			console.error(`[API] 401 Unauthorized for ${endpoint}. Redirecting to logout.`);
 			goto('/logout');
 			throw new Error('Unauthorized');
@@ -86,6 +120,51 @@ export interface ApiResponse<T> {
 	data: T;
 }
 
+export type SubjectKind = 'ServiceAccount' | 'User';
+
+export interface Applied {
+	name: string;
+	outcome: 'created' | 'unchanged' | 'updated';
+}
+
+export interface TemplateInfo {
+	name: string;
+	scope: 'namespaced' | 'cluster';
+	description?: string;
+	rule_count: number;
+}
+
+export interface TemplateRule {
+	api_groups?: string[];
+	resources?: string[];
+	resource_names?: string[];
+	non_resource_urls?: string[];
+	verbs: string[];
+}
+
+export interface TemplateDetail {
+	name: string;
+	scope: 'namespaced' | 'cluster';
+	description?: string;
+	rules: TemplateRule[];
+	managed: boolean;
+}
+
+export interface TemplateRequest {
+	name: string;
+	scope: 'namespaced' | 'cluster';
+	description?: string;
+	rules: TemplateRule[];
+	allow_privileged_verbs?: boolean;
+}
+
+export interface BlastRadius {
+	role_bindings: number;
+	cluster_role_bindings: number;
+	namespaces: string[];
+	subjects: string[];
+}
+
 export interface RoleBindingsResponse {
 	[namespace: string]: Record<string, boolean>;
 }
@@ -102,52 +181,126 @@ export interface LoginRequest {
 export interface AuthBody {
 	access_token: string;
 	token_type: string;
+	expires_in: number;
+	refresh_token: string;
+	refresh_expires_in: number;
+	recovery_codes?: string[];
+}
+
+export interface MfaChallenge {
+	mfa_token: string;
+	enrollment_required: boolean;
+}
+
+export interface MfaSetupInfo {
+	secret: string;
+	otpauth_uri: string;
 }
 
 export const api = {
 	login: (credentials: LoginRequest, options: RequestInit = {}) =>
-		request<ApiResponse<AuthBody>>('login', {
+		request<ApiResponse<Partial<AuthBody & MfaChallenge>>>('login', {
 			method: 'POST',
 			body: JSON.stringify(credentials),
 			...options
 		}),
 
-	getTemplates: (options: RequestInit & { token?: string } = {}) => request<ApiResponse<string[]>>('getTemplates', options),
+	loginKubernetes: (token: string, options: RequestInit = {}) =>
+		request<ApiResponse<AuthBody>>('login/kubernetes', {
+			method: 'POST',
+			body: JSON.stringify({ token }),
+			...options
+		}),
+
+	loginMfa: (mfaToken: string, code: string, options: RequestInit = {}) =>
+		request<ApiResponse<AuthBody>>('login/mfa', {
+			method: 'POST',
+			body: JSON.stringify({ mfa_token: mfaToken, code }),
+			...options
+		}),
+
+	loginMfaEnroll: (mfaToken: string, options: RequestInit = {}) =>
+		request<ApiResponse<MfaSetupInfo>>('login/mfa/enroll', {
+			method: 'POST',
+			body: JSON.stringify({ mfa_token: mfaToken }),
+			...options
+		}),
+
+	refresh: (refreshToken: string, options: RequestInit = {}) =>
+		request<ApiResponse<AuthBody>>('refresh', {
+			method: 'POST',
+			body: JSON.stringify({ refresh_token: refreshToken }),
+			...options
+		}),
+
+	logout: (options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<string>>('logout', {
+			method: 'POST',
+			...options
+		}),
+
+	changePassword: (currentPassword: string, newPassword: string, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<string>>('changePassword', {
+			method: 'POST',
+			body: JSON.stringify({ current_password: currentPassword, new_password: newPassword }),
+			...options
+		}),
+
+	getTemplates: (options: RequestInit & { token?: string } = {}) => request<ApiResponse<TemplateInfo[]>>('getTemplates', options),
+	getTemplate: (name: string, scope: TemplateDetail['scope'], options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<TemplateDetail>>(`getTemplate?name=${encodeURIComponent(name)}&scope=${scope}`, options),
+
+	createTemplate: (template: TemplateRequest, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<Applied>>('createTemplate', { method: 'POST', body: JSON.stringify(template), ...options }),
+
+	updateTemplate: (template: TemplateRequest, dryRun = false, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<{ template: TemplateDetail; blast_radius: BlastRadius; dry_run: boolean }>>(`updateTemplate?dry_run=${dryRun}`, {
+			method: 'POST',
+			body: JSON.stringify(template),
+			...options
+		}),
+
+	deleteTemplate: (name: string, scope: TemplateDetail['scope'], force = false, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<BlastRadius>>(`deleteTemplate?name=${encodeURIComponent(name)}&scope=${scope}&force=${force}`, {
+			method: 'DELETE',
+			...options
+		}),
+
 	getNamespaces: (options: RequestInit & { token?: string } = {}) => request<ApiResponse<string[]>>('getNamespaces', options),
 	getUsers: (options: RequestInit & { token?: string } = {}) => request<ApiResponse<string[]>>('getServiceAccounts', options),
 
-	getRoleBindings: (username: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<RoleBindingsResponse>>(`getFilteredRoleBindings?username=${encodeURIComponent(username)}`, {
+	getRoleBindings: (username: string, options: RequestInit & { token?: string } = {}, kind: SubjectKind = 'ServiceAccount') =>
+		request<ApiResponse<RoleBindingsResponse>>(`getFilteredRoleBindings?username=${encodeURIComponent(username)}&kind=${kind}`, {
 			method: 'POST',
 			...options
 		}),
 
-	getClusterRoleBindings: (username: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<ClusterRoleBindingsResponse>>(`getFilteredClusterRoleBindings?username=${encodeURIComponent(username)}`, {
+	getClusterRoleBindings: (username: string, options: RequestInit & { token?: string } = {}, kind: SubjectKind = 'ServiceAccount') =>
+		request<ApiResponse<ClusterRoleBindingsResponse>>(`getFilteredClusterRoleBindings?username=${encodeURIComponent(username)}&kind=${kind}`, {
 			method: 'POST',
 			...options
 		}),
 
 	createServiceAccount: (username: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<string>>(`createServiceAccount?username=${encodeURIComponent(username)}`, {
+		request<ApiResponse<Applied>>(`createServiceAccount?username=${encodeURIComponent(username)}`, {
 			method: 'POST',
 			...options
 		}),
 
 	createSecret: (username: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<string>>(`createSecret?username=${encodeURIComponent(username)}`, {
+		request<ApiResponse<Applied>>(`createSecret?username=${encodeURIComponent(username)}`, {
 			method: 'POST',
 			...options
 		}),
 
 	createRoleBinding: (username: string, namespace: string, permission: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<string>>(`createRoleBinding?username=${encodeURIComponent(username)}&namespace=${encodeURIComponent(namespace)}&permission=${encodeURIComponent(permission)}`, {
+		request<ApiResponse<Applied>>(`createRoleBinding?username=${encodeURIComponent(username)}&namespace=${encodeURIComponent(namespace)}&permission=${encodeURIComponent(permission)}`, {
 			method: 'POST',
 			...options
 		}),
 
 	createClusterRoleBinding: (username: string, permission: string, options: RequestInit & { token?: string } = {}) =>
-		request<ApiResponse<string>>(`createClusterRoleBinding?username=${encodeURIComponent(username)}&permission=${encodeURIComponent(permission)}`, {
+		request<ApiResponse<Applied>>(`createClusterRoleBinding?username=${encodeURIComponent(username)}&permission=${encodeURIComponent(permission)}`, {
 			method: 'POST',
 			...options
 		}),
@@ -158,12 +311,50 @@ export const api = {
 			...options
 		}),
 
+	generateCertConfig: (
+		username: string,
+		namespace: string,
+		groups: string[] = [],
+		expirationSeconds?: number,
+		options: RequestInit & { token?: string } = {}
+	) =>
+		request<ApiResponse<string>>('generateK8sCertConfig', {
+			method: 'POST',
+			body: JSON.stringify({ username, namespace, groups, expiration_seconds: expirationSeconds }),
+			...options
+		}),
+
+	provisionUser: (
+		username: string,
+		namespace: string,
+		roleBindings: Record<string, string[]>,
+		clusterPermission?: string,
+		options: RequestInit & { token?: string } = {}
+	) =>
+		request<ApiResponse<{ kubeconfig: string; created: string[] }>>('provisionUser', {
+			method: 'POST',
+			body: JSON.stringify({ username, namespace, role_bindings: roleBindings, cluster_permission: clusterPermission }),
+			...options
+		}),
+
+	rotateToken: (username: string, namespace: string, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<{ kubeconfig: string; rotation: Record<string, unknown>; still_valid: string }>>(
+			`rotateToken?username=${encodeURIComponent(username)}&namespace=${encodeURIComponent(namespace)}`,
+			{ method: 'POST', ...options }
+		),
+
 	getGenerateConfigDownloadUrl: (username: string, namespace: string) =>
 		`${BASE_URL}/generateK8sConfigDownloadFile?username=${encodeURIComponent(username)}&namespace=${encodeURIComponent(namespace)}`,
 
 	deleteServiceAccount: (username: string, options: RequestInit & { token?: string } = {}) =>
 		request<ApiResponse<string>>(`deleteServiceAccount?username=${encodeURIComponent(username)}`, { method: 'DELETE', ...options }),
 
+	deleteUser: (username: string, dryRun = false, options: RequestInit & { token?: string } = {}) =>
+		request<ApiResponse<{ objects: { kind: string; namespace?: string; name: string; outcome: string; error?: string }[] }>>(
+			`deleteUser?username=${encodeURIComponent(username)}&dry_run=${dryRun}`,
+			{ method: 'DELETE', ...options }
+		),
+
 	deleteSecret: (username: string, options: RequestInit & { token?: string } = {}) =>
 		request<ApiResponse<string>>(`deleteSecret?username=${encodeURIComponent(username)}`, { method: 'DELETE', ...options }),
 
@@ -0,0 +1,19 @@
+import type { Cookies } from '@sveltejs/kit';
+import type { AuthBody } from '$lib/api';
+
+const cookieOptions = {
+	path: '/',
+	httpOnly: true,
+	sameSite: 'strict' as const,
+	secure: process.env.NODE_ENV === 'production'
+};
+
+export function setSessionCookies(cookies: Cookies, body: AuthBody) {
+	cookies.set('access_token', body.access_token, { ...cookieOptions, maxAge: body.expires_in });
+	cookies.set('refresh_token', body.refresh_token, { ...cookieOptions, maxAge: body.refresh_expires_in });
+}
+
+export function clearSessionCookies(cookies: Cookies) {
+	cookies.delete('access_token', { path: '/' });
+	cookies.delete('refresh_token', { path: '/' });
+}
@@ -5,13 +5,15 @@ import { env } from '$env/dynamic/private';
 const BACKEND_PORT = env.APP_PORT || process.env.APP_PORT || '3232';
 const BACKEND_HOST = 'localhost';
 
-export const fallback: RequestHandler = async ({ request, url }) => {
+export const fallback: RequestHandler = async ({ request, url, getClientAddress }) => {
 	const backendUrl = new URL(url.pathname + url.search, `http://${BACKEND_HOST}:${BACKEND_PORT}`);
 
 	const headers = new Headers(request.headers);
 	// We might want to remove headers that could conflict
 	headers.delete('host');
 	headers.delete('connection');
+	// Replace anything the browser sent so the backend sees the real client for its login limits
+	headers.set('x-forwarded-for', getClientAddress());
 
 	try {
 		const response = await fetch(backendUrl.toString(), {
@@ -19,6 +21,8 @@ export const fallback: RequestHandler = async ({ request, url }) => {
 			headers,
 			// Pass the body stream directly for efficiency
 			body: request.method !== 'GET' && request.method !== 'HEAD' ? request.body : undefined,
+			// Hand redirects (e.g. the SSO flow) back to the browser instead of following them here
+			redirect: 'manual',
 			// @ts-ignore - duplex is needed for streaming bodies in some fetch implementations (like undici)
 			duplex: 'half'
 		});
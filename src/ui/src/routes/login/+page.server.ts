@@ -1,9 +1,34 @@
 import { fail, redirect } from '@sveltejs/kit';
 import type { Actions } from './$types';
-import { api } from '$lib/api';
+import { api, type AuthBody } from '$lib/api';
+import { setSessionCookies } from '$lib/server/session';
+
+function failure(error: any, extra: Record<string, unknown> = {}) {
+	let message = 'An unexpected error occurred';
+	let status = error.status || 500;
+
+	if (error.message) {
+		try {
+			// Check if message is JSON (common for our API responses)
+			const errorData = JSON.parse(error.message);
+			if (errorData?.meta_data?.message) {
+				message = errorData.meta_data.message;
+				if (errorData.meta_data.status) {
+					status = errorData.meta_data.status;
+				}
+			} else {
+				message = error.message;
+			}
+		} catch (e) {
+			message = error.message;
+		}
+	}
+
+	return fail(status === 401 ? 401 : (status >= 400 && status < 600 ? status : 500), { message, ...extra });
+}
 
 export const actions: Actions = {
-	default: async ({ request, cookies }) => {
+	login: async ({ request, cookies, getClientAddress }) => {
 		const formData = await request.formData();
 		const username = formData.get('username') as string;
 		const password = formData.get('password') as string;
@@ -13,16 +38,18 @@ export const actions: Actions = {
 		}
 
 		try {
-			const response = await api.login({ username, password });
-			
+			// The backend rate-limits failed logins per client address
+			const response = await api.login({ username, password }, {
+				headers: { 'X-Forwarded-For': getClientAddress() }
+			});
+
+			// Password accepted but a second factor is needed
+			if (response?.data?.mfa_token) {
+				return { mfaToken: response.data.mfa_token, enrollmentRequired: response.data.enrollment_required };
+			}
+
 			if (response?.data?.access_token) {
-				cookies.set('access_token', response.data.access_token, {
-					path: '/',
-					httpOnly: true,
-					sameSite: 'strict',
-					secure: process.env.NODE_ENV === 'production',
-					maxAge: 60 * 60 * 24 // 24 hours
-				});
+				setSessionCookies(cookies, response.data as AuthBody);
 
 				throw redirect(303, '/');
 			}
@@ -30,28 +57,64 @@ export const actions: Actions = {
 			return fail(401, { message: 'Invalid credentials' });
 		} catch (error: any) {
 			if (error.status === 303) throw error;
-			
-			let message = 'An unexpected error occurred';
-			let status = error.status || 500;
-
-			if (error.message) {
-				try {
-					// Check if message is JSON (common for our API responses)
-					const errorData = JSON.parse(error.message);
-					if (errorData?.meta_data?.message) {
-						message = errorData.meta_data.message;
-						if (errorData.meta_data.status) {
-							status = errorData.meta_data.status;
-						}
-					} else {
-						message = error.message;
-					}
-				} catch (e) {
-					message = error.message;
-				}
-			}
+			return failure(error);
+		}
+	},
+
+	kubernetes: async ({ request, cookies, getClientAddress }) => {
+		const formData = await request.formData();
+		const token = formData.get('token') as string;
+
+		if (!token) {
+			return fail(400, { message: 'A Kubernetes token is required', kubernetes: true });
+		}
 
-			return fail(status === 401 ? 401 : (status >= 400 && status < 600 ? status : 500), { message });
+		try {
+			const response = await api.loginKubernetes(token, {
+				headers: { 'X-Forwarded-For': getClientAddress() }
+			});
+			setSessionCookies(cookies, response.data);
+			throw redirect(303, '/');
+		} catch (error: any) {
+			if (error.status === 303) throw error;
+			return failure(error, { kubernetes: true });
+		}
+	},
+
+	enroll: async ({ request }) => {
+		const formData = await request.formData();
+		const mfaToken = formData.get('mfa_token') as string;
+
+		try {
+			const response = await api.loginMfaEnroll(mfaToken);
+			return { mfaToken, enrollmentRequired: true, setup: response.data };
+		} catch (error: any) {
+			return failure(error, { mfaToken, enrollmentRequired: true });
+		}
+	},
+
+	mfa: async ({ request, cookies }) => {
+		const formData = await request.formData();
+		const mfaToken = formData.get('mfa_token') as string;
+		const code = formData.get('code') as string;
+		const enrollmentRequired = formData.get('enrollment_required') === 'true';
+
+		if (!code) {
+			return fail(400, { message: 'Verification code is required', mfaToken, enrollmentRequired });
+		}
+
+		try {
+			const response = await api.loginMfa(mfaToken, code);
+			setSessionCookies(cookies, response.data);
+
+			// Recovery codes are only ever shown once, so stop here to display them
+			if (response.data.recovery_codes) {
+				return { recoveryCodes: response.data.recovery_codes };
+			}
+			throw redirect(303, '/');
+		} catch (error: any) {
+			if (error.status === 303) throw error;
+			return failure(error, { mfaToken, enrollmentRequired });
 		}
 	}
 };
@@ -1,7 +1,18 @@
 import { redirect } from '@sveltejs/kit';
 import type { PageServerLoad } from './$types';
+import { api } from '$lib/api';
+import { clearSessionCookies } from '$lib/server/session';
 
 export const load: PageServerLoad = async ({ cookies }) => {
-	cookies.delete('access_token', { path: '/' });
+	const token = cookies.get('access_token');
+	if (token) {
+		// Revoke the session server-side so copies of the tokens stop working too
+		try {
+			await api.logout({ token });
+		} catch (error) {
+			console.error(`[Logout] Failed to revoke session: ${error}`);
+		}
+	}
+	clearSessionCookies(cookies);
 	throw redirect(303, '/login');
 };